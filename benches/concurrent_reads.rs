@@ -0,0 +1,132 @@
+//! Compares reading two large files from the same chunkstore one after the other with
+//! reading them concurrently, as happens when a mounted backup is read by several
+//! programs at once.
+//!
+//! Run with `cargo bench --bench concurrent_reads`.
+
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use futures_util::future;
+use sha1::{Digest, Sha1};
+use tev::{
+    formats::{csd::compress_chunk, csm::Chunk},
+    ChunkStore, ChunkStoreManifest,
+};
+use tokio::runtime::Builder;
+
+const DEPOT: u32 = 1;
+const CHUNK_SIZE: usize = 1024 * 1024;
+const CHUNKS_PER_FILE: usize = 32;
+const ROUNDS: u32 = 5;
+
+fn main() -> anyhow::Result<()> {
+    let dir = std::env::temp_dir().join(format!("tev-bench-{}", std::process::id()));
+    fs::create_dir_all(&dir)?;
+    let result = run(&dir);
+    fs::remove_dir_all(&dir)?;
+    result
+}
+
+fn run(dir: &PathBuf) -> anyhow::Result<()> {
+    let files = write_chunkstore(dir)?;
+    let bytes = (2 * CHUNKS_PER_FILE * CHUNK_SIZE) as f64;
+
+    let runtime = Builder::new_multi_thread().build()?;
+    let chunkstore =
+        Arc::new(runtime.block_on(ChunkStore::open(std::slice::from_ref(dir), DEPOT, 1, None))?);
+
+    let read_file = |shas: Vec<[u8; 20]>| {
+        let chunkstore = chunkstore.clone();
+        async move {
+            for sha in shas {
+                chunkstore.chunk_data(sha).await?;
+            }
+            Ok::<_, anyhow::Error>(())
+        }
+    };
+
+    let mut sequential = Duration::ZERO;
+    let mut concurrent = Duration::ZERO;
+    for _ in 0..ROUNDS {
+        let start = Instant::now();
+        runtime.block_on(async {
+            for shas in &files {
+                read_file(shas.clone()).await?;
+            }
+            Ok::<_, anyhow::Error>(())
+        })?;
+        sequential += start.elapsed();
+
+        let start = Instant::now();
+        runtime.block_on(async {
+            let tasks = files
+                .iter()
+                .map(|shas| tokio::spawn(read_file(shas.clone())));
+            for result in future::join_all(tasks).await {
+                result??;
+            }
+            Ok::<_, anyhow::Error>(())
+        })?;
+        concurrent += start.elapsed();
+    }
+
+    for (label, elapsed) in [("sequential", sequential), ("concurrent", concurrent)] {
+        let elapsed = elapsed / ROUNDS;
+        println!(
+            "{label}: {elapsed:.2?} per round ({:.0} MiB/s)",
+            bytes / elapsed.as_secs_f64() / (1024.0 * 1024.0),
+        );
+    }
+
+    Ok(())
+}
+
+/// Writes a chunkstore containing the chunks of two files, returning the digests of
+/// each file's chunks.
+fn write_chunkstore(dir: &Path) -> anyhow::Result<Vec<Vec<[u8; 20]>>> {
+    let mut csd = vec![];
+    let mut csm = ChunkStoreManifest {
+        is_encrypted: false,
+        depot: DEPOT,
+        chunks: vec![],
+    };
+
+    // Pseudo-random data, so that chunks take some work to decompress.
+    let mut state = 0x2545_F491_4F6C_DD1D_u64;
+    let mut files = vec![];
+    for _ in 0..2 {
+        let mut shas = vec![];
+        for _ in 0..CHUNKS_PER_FILE {
+            let data = (0..CHUNK_SIZE)
+                .map(|_| {
+                    state ^= state << 13;
+                    state ^= state >> 7;
+                    state ^= state << 17;
+                    // Limit the alphabet, so that the data is compressible.
+                    b'a' + (state % 16) as u8
+                })
+                .collect::<Vec<_>>();
+            let sha: [u8; 20] = Sha1::digest(&data).into();
+            let body = compress_chunk(&data)?;
+            csm.chunks.push((
+                sha,
+                Chunk {
+                    offset: csd.len() as u64,
+                    uncompressed_length: data.len() as u32,
+                    compressed_length: body.len() as u32,
+                },
+            ));
+            csd.extend_from_slice(&body);
+            shas.push(sha);
+        }
+        files.push(shas);
+    }
+
+    fs::write(dir.join(format!("{DEPOT}_depotcache_1.csd")), csd)?;
+    csm.write(File::create(dir.join(format!("{DEPOT}_depotcache_1.csm")))?)?;
+
+    Ok(files)
+}
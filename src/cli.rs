@@ -1,50 +1,328 @@
+use std::num::NonZeroUsize;
 use std::path::PathBuf;
 
-use clap::{Args, Parser, Subcommand};
+use byte_unit::Byte;
+use clap::{ArgAction, Args, Parser, Subcommand, ValueEnum};
+use clap_complete::Shell;
+use tracing::level_filters::LevelFilter;
+
+use crate::formats::keys;
 
 #[derive(Parser)]
 pub(crate) struct Options {
+    /// The most detailed level of log messages to print to stderr: off, error, warn,
+    /// info, debug, or trace.
+    #[arg(long, global = true, value_name = "LEVEL", default_value_t = LevelFilter::WARN)]
+    pub(crate) log_level: LevelFilter,
+
+    /// Print more detailed log messages: `-v` for info, `-vv` for debug, and `-vvv` for
+    /// trace. Overrides `--log-level`.
+    #[arg(short, long, global = true, action = ArgAction::Count)]
+    pub(crate) verbose: u8,
+
+    /// Maximum number of threads to use for reading and decompressing chunks in
+    /// `tev backup verify` and `tev backup mount`. Defaults to the number of CPUs.
+    ///
+    /// This also limits how many chunks `tev backup verify` checks at once, while
+    /// `tev backup verify --jobs` limits how many chunkstores are verified at once.
+    #[arg(long, global = true)]
+    pub(crate) threads: Option<NonZeroUsize>,
+
     #[command(subcommand)]
     pub(crate) command: Command,
 }
 
+impl Options {
+    /// Returns the most detailed level of log messages to print.
+    pub(crate) fn max_log_level(&self) -> LevelFilter {
+        match self.verbose {
+            0 => self.log_level,
+            1 => LevelFilter::INFO,
+            2 => LevelFilter::DEBUG,
+            _ => LevelFilter::TRACE,
+        }
+    }
+}
+
 #[derive(Debug, Subcommand)]
 pub(crate) enum Command {
     Inspect(Inspect),
     #[command(subcommand)]
     Backup(Backup),
+    #[command(subcommand)]
+    Manifest(Manifest),
+    Completion(Completion),
 }
 
 /// Inspect a Steam file.
 #[derive(Debug, Args)]
 pub(crate) struct Inspect {
-    /// Path to the file.
+    /// Path to the file, or to a backup folder to summarize the whole backup. Use `-`
+    /// to read a depot manifest from stdin.
     pub(crate) path: PathBuf,
+
+    /// The format in which to print the file's details.
+    #[arg(long, value_enum, default_value_t = InspectFormat::Text)]
+    pub(crate) format: InspectFormat,
+
+    #[command(flatten)]
+    pub(crate) filter: FilterArgs,
+
+    /// For a chunkstore data file, also decompress each chunk and check its SHA-1
+    /// digest, stopping at the first failure. Requires the file's original name.
+    #[arg(long)]
+    pub(crate) verify: bool,
+
+    /// For a depot manifest, list the files that use the chunk with this SHA-1 digest
+    /// (in hex, or a prefix of it), and where in each file it is used.
+    #[arg(long, value_name = "SHA", value_parser = parse_sha_prefix)]
+    pub(crate) find_chunk: Option<String>,
+
+    /// For a depot manifest, hide files smaller than this size (such as `50MiB`).
+    #[arg(long, value_name = "SIZE")]
+    pub(crate) min_size: Option<Byte>,
+
+    /// For a depot manifest, the order in which to list its files. Defaults to the order
+    /// of the manifest.
+    #[arg(long, value_enum)]
+    pub(crate) sort: Option<SortKey>,
+
+    /// List the files of a depot manifest in reverse order (for example, with
+    /// `--sort size`, largest first).
+    #[arg(long)]
+    pub(crate) reverse: bool,
+
+    /// For a depot manifest, only list this many files (after sorting). The totals still
+    /// cover every matching file.
+    #[arg(long, value_name = "N")]
+    pub(crate) limit: Option<usize>,
+
+    /// For a depot manifest, print its files as an indented directory tree instead of a
+    /// flat list. Only affects the text output.
+    #[arg(long)]
+    pub(crate) tree: bool,
+
+    /// For a SKU or backup folder, look up the names of its apps and depots on Steam.
+    /// Names are cached, and only the IDs are shown for any that can't be looked up.
+    #[arg(long)]
+    pub(crate) names: bool,
+
+    /// For a backup folder, the folder (or zip archive) containing the user's cached
+    /// manifest files, to count the files in each depot.
+    #[arg(long)]
+    pub(crate) manifest_dir: Option<PathBuf>,
+
+    #[command(flatten)]
+    pub(crate) depot_keys: DepotKeyArgs,
+}
+
+/// Parses a SHA-1 digest, or a prefix of one, as lowercase hex.
+fn parse_sha_prefix(s: &str) -> Result<String, String> {
+    if s.is_empty() || s.len() > 40 || !s.bytes().all(|b| b.is_ascii_hexdigit()) {
+        Err("expected up to 40 hex digits".into())
+    } else {
+        Ok(s.to_ascii_lowercase())
+    }
+}
+
+/// Keys for encrypted depots.
+#[derive(Debug, Args)]
+pub(crate) struct DepotKeyArgs {
+    /// Key for an encrypted depot, as `<DEPOT>:<HEX>`. May be given multiple times.
+    #[arg(long = "depot-key", value_parser = keys::parse_entry)]
+    pub(crate) depot_keys: Vec<(u32, [u8; 32])>,
+
+    /// Path to a file of depot keys, containing one `<DEPOT>:<HEX>` entry per line.
+    #[arg(long = "keys")]
+    pub(crate) keys_file: Option<PathBuf>,
+
+    /// Path to the Steam client's `config/config.vdf` file, to use the depot keys it
+    /// has cached.
+    #[arg(long)]
+    pub(crate) steam_config: Option<PathBuf>,
+}
+
+/// Downloading depot manifests from Steam.
+#[derive(Debug, Args)]
+pub(crate) struct DownloadArgs {
+    /// Download the backup's depot manifests that are missing from `--manifest-dir`
+    /// from Steam, and save them there. `--manifest-dir` must be a folder.
+    #[arg(long, requires = "manifest_dir")]
+    pub(crate) download_manifests: bool,
+
+    /// The Steam account to log in to for `--download-manifests`. The password is read
+    /// from the `TEV_STEAM_PASSWORD` environment variable. Without this, an anonymous
+    /// session is used, which can only download the manifests of free depots.
+    #[arg(long, requires = "download_manifests")]
+    pub(crate) steam_user: Option<String>,
+}
+
+/// Glob patterns for selecting files by their path within a depot manifest.
+///
+/// Patterns are matched against the full path, using `/` as the separator. `*` and `?`
+/// match within a single path component, and `**` matches across components.
+#[derive(Debug, Args)]
+pub(crate) struct FilterArgs {
+    /// Only include files matching the given glob. May be given multiple times.
+    #[arg(long)]
+    pub(crate) include: Vec<String>,
+
+    /// Exclude files matching the given glob. May be given multiple times.
+    #[arg(long)]
+    pub(crate) exclude: Vec<String>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub(crate) enum OutputFormat {
+    /// Human-readable text.
+    Text,
+    /// A JSON object, for consumption by scripts.
+    Json,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub(crate) enum InspectFormat {
+    /// Human-readable text.
+    Text,
+    /// A JSON object, for consumption by scripts.
+    Json,
+    /// Comma-separated values, with one row per file (depot manifests only).
+    Csv,
+}
+
+/// The orders in which `tev inspect` can list the files of a depot manifest.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub(crate) enum SortKey {
+    /// By path.
+    Name,
+    /// By size, smallest first.
+    Size,
+}
+
+/// Print a shell completion script for `tev`.
+///
+/// For example, with bash: `tev completion bash > ~/.local/share/bash-completion/completions/tev`
+#[derive(Debug, Args)]
+pub(crate) struct Completion {
+    /// The shell to print the completion script for.
+    #[arg(value_enum)]
+    pub(crate) shell: Shell,
 }
 
 /// Manage Steam game backups.
 #[derive(Debug, Subcommand)]
 pub(crate) enum Backup {
     Verify(VerifyBackup),
+    Repair(RepairBackup),
     Mount(MountBackup),
+    Cat(CatBackup),
+    Ls(ListBackup),
+    Find(FindBackup),
+    Sha1sums(BackupSha1Sums),
+    Compare(CompareBackup),
+    Bench(BenchBackup),
+    Stats(BackupStats),
+    CheckSku(CheckSku),
+    Create(CreateBackup),
+    DecryptFilenames(DecryptFilenames),
+    Extract(ExtractBackup),
+    Diff(DiffManifests),
 }
 
 /// Verify one or more Steam game backups.
 ///
 /// If `--manifest-dir` is provided, it will be checked for the presence of the manifest
 /// files necessary to access the files in this backup.
+///
+/// If `--recursive` is provided, each path is searched for backup folders (folders
+/// containing a `sku.sis` file, in any case), which are all verified. The disks of a
+/// multi-disk backup are grouped together, and a summary is printed at the end.
+///
+/// Exits with a non-zero status if any backup fails verification.
 #[derive(Debug, Args)]
 pub(crate) struct VerifyBackup {
     /// Path to a game's backup folder, or a file within it.
     pub(crate) path: Vec<PathBuf>,
 
+    /// Search the given paths for backup folders, and verify all of them.
+    #[arg(short, long)]
+    pub(crate) recursive: bool,
+
     /// Skip verification of individual chunk SHA-1 digests.
     #[arg(long)]
     pub(crate) fast: bool,
 
-    /// Path to the folder containing the user's cached manifest files.
+    /// Check the signatures of the manifests in `--manifest-dir`, using the key given by
+    /// `--signing-key`.
+    #[arg(long, requires_all = ["manifest_dir", "signing_key"])]
+    pub(crate) verify_signature: bool,
+
+    /// Path to Steam's content manifest signing public key (PEM or DER), which is not
+    /// bundled with tev.
+    #[arg(long)]
+    pub(crate) signing_key: Option<PathBuf>,
+
+    /// Also fail verification on warnings, such as a mismatch between the number of
+    /// chunks in a depot's manifest and on disk.
+    #[arg(long)]
+    pub(crate) strict: bool,
+
+    /// Path to the folder (or zip archive) containing the user's cached manifest
+    /// files.
     #[arg(long)]
     pub(crate) manifest_dir: Option<PathBuf>,
+
+    #[command(flatten)]
+    pub(crate) download: DownloadArgs,
+
+    /// Maximum number of chunkstores to verify concurrently. Defaults to the number of
+    /// CPUs. The number of chunks checked at once across them is limited by `--threads`.
+    #[arg(short, long)]
+    pub(crate) jobs: Option<NonZeroUsize>,
+
+    /// Only verify the given depot. May be given multiple times.
+    #[arg(long = "depot")]
+    pub(crate) depots: Vec<u32>,
+
+    /// Only verify the chunks of files selected by these patterns (requires
+    /// `--manifest-dir`).
+    #[command(flatten)]
+    pub(crate) filter: FilterArgs,
+
+    #[command(flatten)]
+    pub(crate) depot_keys: DepotKeyArgs,
+
+    /// Write a JSON report of the verification results to the given path.
+    #[arg(long)]
+    pub(crate) report: Option<PathBuf>,
+
+    /// The format in which to print the verification results. With `json`, the
+    /// results of every backup are printed at the end, in the same form as `--report`.
+    #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+    pub(crate) format: OutputFormat,
+
+    /// Don't print the verification results (use with `--report`).
+    #[arg(short, long)]
+    pub(crate) quiet: bool,
+
+    /// Don't show a progress bar, even if stdout is a terminal.
+    #[arg(long)]
+    pub(crate) no_progress: bool,
+}
+
+/// List the chunks of Steam game backups that need to be downloaded again.
+///
+/// The backups are verified as by `tev backup verify` (which accepts the same
+/// arguments), and then each corrupt or missing chunk is listed with the ID of its
+/// depot and manifest, followed by the files in the manifest that use those chunks.
+///
+/// Each line has four tab-separated fields: the depot ID, the manifest ID, `chunk` or
+/// `file`, and the chunk's SHA-1 digest or the file's path. With `--format json`, a
+/// JSON object is printed instead. Requires `--manifest-dir`.
+#[derive(Debug, Args)]
+pub(crate) struct RepairBackup {
+    #[command(flatten)]
+    pub(crate) verify: VerifyBackup,
 }
 
 /// Mount a Steam game backup.
@@ -56,7 +334,386 @@ pub(crate) struct MountBackup {
     /// Path to the game's backup folder, or a file within it.
     pub(crate) mountpoint: PathBuf,
 
-    /// Path to the folder containing the user's cached manifest files.
+    /// Path to the folder of another disk of a multi-disk backup. May be given multiple
+    /// times. Disks alongside the given backup folder are found automatically.
+    #[arg(long = "disk")]
+    pub(crate) disks: Vec<PathBuf>,
+
+    /// Path to the folder (or zip archive) containing the user's cached manifest
+    /// files.
+    #[arg(long)]
+    pub(crate) manifest_dir: PathBuf,
+
+    #[command(flatten)]
+    pub(crate) download: DownloadArgs,
+
+    /// Maximum amount of memory to use for caching decompressed chunks.
+    #[arg(long, default_value = "256 MiB")]
+    pub(crate) cache_size: Byte,
+
+    #[command(flatten)]
+    pub(crate) depot_keys: DepotKeyArgs,
+
+    /// Run in the background once the backup is mounted, instead of waiting for
+    /// Ctrl-C. The background process unmounts the backup when it is terminated.
+    #[arg(long)]
+    pub(crate) daemon: bool,
+
+    /// Set on the background process started by `--daemon`.
+    #[arg(long, hide = true, conflicts_with = "daemon")]
+    pub(crate) daemon_child: bool,
+}
+
+/// Print the contents of a file within a Steam game backup.
+#[derive(Debug, Args)]
+pub(crate) struct CatBackup {
+    /// Path to the game's backup folder, or a file within it.
+    pub(crate) path: PathBuf,
+
+    /// Path of the file within the backup, as it would appear when the game is installed.
+    pub(crate) file: PathBuf,
+
+    /// Path to the folder of another disk of a multi-disk backup. May be given multiple
+    /// times. Disks alongside the given backup folder are found automatically.
+    #[arg(long = "disk")]
+    pub(crate) disks: Vec<PathBuf>,
+
+    /// Path to the folder (or zip archive) containing the user's cached manifest
+    /// files.
+    #[arg(long)]
+    pub(crate) manifest_dir: PathBuf,
+
+    /// Byte offset within the file at which to start printing.
+    #[arg(long, default_value_t = 0)]
+    pub(crate) offset: u64,
+
+    /// Maximum number of bytes to print. Defaults to the rest of the file.
+    #[arg(long)]
+    pub(crate) length: Option<u64>,
+
+    #[command(flatten)]
+    pub(crate) depot_keys: DepotKeyArgs,
+}
+
+/// List the contents of a directory within a Steam game backup.
+#[derive(Debug, Args)]
+pub(crate) struct ListBackup {
+    /// Path to the game's backup folder, or a file within it.
+    pub(crate) path: PathBuf,
+
+    /// Path of the directory within the backup, as it would appear when the game is
+    /// installed. Defaults to the top-level directory.
+    pub(crate) dir: Option<PathBuf>,
+
+    /// Path to the folder of another disk of a multi-disk backup. May be given multiple
+    /// times. Disks alongside the given backup folder are found automatically.
+    #[arg(long = "disk")]
+    pub(crate) disks: Vec<PathBuf>,
+
+    /// Path to the folder (or zip archive) containing the user's cached manifest
+    /// files.
+    #[arg(long)]
+    pub(crate) manifest_dir: PathBuf,
+
+    /// List the contents of subdirectories recursively.
+    #[arg(short = 'R', long)]
+    pub(crate) recursive: bool,
+
+    #[command(flatten)]
+    pub(crate) depot_keys: DepotKeyArgs,
+}
+
+/// Search for files by name in a Steam game backup.
+///
+/// Every depot's manifest is searched, and each matching file is printed with its depot
+/// and size.
+#[derive(Debug, Args)]
+pub(crate) struct FindBackup {
+    /// Path to the game's backup folder, or a file within it.
+    pub(crate) path: PathBuf,
+
+    /// Glob pattern to search for. Patterns without a `/` are matched against the name
+    /// of each file (in any directory), and others against its full path.
+    pub(crate) pattern: String,
+
+    /// Path to the folder (or zip archive) containing the user's cached manifest
+    /// files.
+    #[arg(long)]
+    pub(crate) manifest_dir: PathBuf,
+
+    /// Match the pattern case-insensitively.
+    #[arg(short, long)]
+    pub(crate) ignore_case: bool,
+
+    #[command(flatten)]
+    pub(crate) depot_keys: DepotKeyArgs,
+}
+
+/// Compute the SHA-1 digest of every file in a Steam game backup.
+///
+/// Each file is read and decompressed from the backup's chunks, and its digests are
+/// written in the format of `sha1sum`, so that extracted files can later be checked with
+/// `sha1sum -c`. Files whose digest doesn't match the one recorded in their depot
+/// manifest are reported.
+#[derive(Debug, Args)]
+pub(crate) struct BackupSha1Sums {
+    /// Path to the game's backup folder, or a file within it.
+    pub(crate) path: PathBuf,
+
+    /// Path to the folder of another disk of a multi-disk backup. May be given multiple
+    /// times. Disks alongside the given backup folder are found automatically.
+    #[arg(long = "disk")]
+    pub(crate) disks: Vec<PathBuf>,
+
+    /// Path to the folder (or zip archive) containing the user's cached manifest
+    /// files.
+    #[arg(long)]
+    pub(crate) manifest_dir: PathBuf,
+
+    /// Write the digests to the given file instead of stdout.
+    #[arg(short, long)]
+    pub(crate) output: Option<PathBuf>,
+
+    /// Maximum number of files to hash concurrently. Defaults to the number of CPUs.
+    #[arg(short, long)]
+    pub(crate) jobs: Option<NonZeroUsize>,
+
+    #[command(flatten)]
+    pub(crate) depot_keys: DepotKeyArgs,
+}
+
+/// Compare an installed game with a Steam game backup.
+///
+/// Each installed file is hashed along the chunk boundaries recorded in its depot
+/// manifest, and compared with the SHA-1 digests of the chunks. Files that differ from
+/// the backup or are missing from the installed game are listed, along with files in
+/// the install folder that are not in the backup. No chunks are read from the backup.
+#[derive(Debug, Args)]
+pub(crate) struct CompareBackup {
+    /// Path to the game's backup folder, or a file within it.
+    pub(crate) path: PathBuf,
+
+    /// Path to the game's install folder.
+    pub(crate) install_dir: PathBuf,
+
+    /// Path to the folder of another disk of a multi-disk backup. May be given multiple
+    /// times. Disks alongside the given backup folder are found automatically.
+    #[arg(long = "disk")]
+    pub(crate) disks: Vec<PathBuf>,
+
+    /// Path to the folder (or zip archive) containing the user's cached manifest
+    /// files.
+    #[arg(long)]
+    pub(crate) manifest_dir: PathBuf,
+
+    /// Maximum number of files to hash concurrently. Defaults to the number of CPUs.
+    #[arg(short, long)]
+    pub(crate) jobs: Option<NonZeroUsize>,
+
+    #[command(flatten)]
+    pub(crate) depot_keys: DepotKeyArgs,
+}
+
+/// Measure how quickly the chunks of a Steam game backup can be checked.
+///
+/// Every chunk is read, decompressed, and hashed as by `tev backup verify`, timing each
+/// stage separately. The throughput of each stage is printed for each compression
+/// format used by the backup's chunks.
+#[derive(Debug, Args)]
+pub(crate) struct BenchBackup {
+    /// Path to the game's backup folder, or a file within it.
+    pub(crate) path: PathBuf,
+
+    /// Path to the folder of another disk of a multi-disk backup. May be given multiple
+    /// times. Disks alongside the given backup folder are found automatically.
+    #[arg(long = "disk")]
+    pub(crate) disks: Vec<PathBuf>,
+
+    /// Maximum number of chunks to check concurrently. Defaults to the number of CPUs.
+    /// The number of threads used to check them is controlled by `--threads`.
+    #[arg(short, long)]
+    pub(crate) jobs: Option<NonZeroUsize>,
+
+    #[command(flatten)]
+    pub(crate) depot_keys: DepotKeyArgs,
+}
+
+/// Print statistics about the chunks stored in a Steam game backup.
+///
+/// The same chunk can be stored for several depots; this reports how much space is
+/// saved by storing each chunk only once.
+#[derive(Debug, Args)]
+pub(crate) struct BackupStats {
+    /// Path to the game's backup folder, or a file within it.
+    pub(crate) path: PathBuf,
+
+    /// Path to the folder of another disk of a multi-disk backup. May be given multiple
+    /// times. Disks alongside the given backup folder are found automatically.
+    #[arg(long = "disk")]
+    pub(crate) disks: Vec<PathBuf>,
+}
+
+/// Check that a Steam game backup's files match its SKU, without reading any chunks.
+///
+/// Every depot must have a manifest ID and chunkstores in the SKU, and every
+/// chunkstore's `.csm` and `.csd` files must be present, with the `.csd` file being the
+/// size that the SKU records. This is much faster than `tev backup verify`, but does
+/// not detect corrupt chunks.
+#[derive(Debug, Args)]
+pub(crate) struct CheckSku {
+    /// Path to the game's backup folder, or a file within it.
+    pub(crate) path: PathBuf,
+
+    /// Path to the folder of another disk of a multi-disk backup. May be given multiple
+    /// times. Disks alongside the given backup folder are found automatically.
+    #[arg(long = "disk")]
+    pub(crate) disks: Vec<PathBuf>,
+
+    /// Path to the folder (or zip archive) containing the user's cached manifest
+    /// files, to also check that each depot's manifest is present.
+    #[arg(long)]
+    pub(crate) manifest_dir: Option<PathBuf>,
+}
+
+/// Create a Steam game backup from a game's files and its depot manifests.
+///
+/// The files are split into chunks along the boundaries recorded in the manifests, and
+/// each chunk is checked against its SHA-1 digest before being written to the backup.
+///
+/// A manifest is required for each depot: a backup only stores chunks, and Steam (and
+/// tev) rely on the depot's manifest to know which chunks make up each file. The SKU
+/// also records each depot's manifest ID, which must match a manifest that exists.
+#[derive(Debug, Args)]
+pub(crate) struct CreateBackup {
+    /// Path to the folder containing the game's files, laid out as when installed.
+    pub(crate) files: PathBuf,
+
+    /// Path to the (new or empty) folder in which to create the backup.
+    pub(crate) output: PathBuf,
+
+    /// Path to a depot manifest describing the files. May be given multiple times, and
+    /// must be given at least once, as a backup cannot be read without its manifests.
+    #[arg(long = "manifest", required = true)]
+    pub(crate) manifests: Vec<PathBuf>,
+
+    /// The name of the game, recorded in the backup's SKU.
+    #[arg(long)]
+    pub(crate) name: String,
+
+    /// An app ID to record in the backup's SKU. May be given multiple times.
+    #[arg(long = "app")]
+    pub(crate) apps: Vec<u32>,
+}
+
+/// Extract the files from a Steam game backup.
+///
+/// Files are recreated under the output folder (or written to a tar archive with
+/// `--tar`) as they would appear when the game is installed, and each chunk is checked
+/// against its SHA-1 digest as it is read.
+#[derive(Debug, Args)]
+pub(crate) struct ExtractBackup {
+    /// Path to the game's backup folder, or a file within it.
+    pub(crate) path: PathBuf,
+
+    /// Path to the folder in which to write the game's files.
+    #[arg(required_unless_present_any = ["tar", "dry_run"])]
+    pub(crate) output: Option<PathBuf>,
+
+    /// Write the game's files to a tar archive at the given path instead of a folder,
+    /// or to stdout if the path is `-`.
+    #[arg(long, value_name = "FILE", conflicts_with = "output")]
+    pub(crate) tar: Option<PathBuf>,
+
+    /// Path to the folder of another disk of a multi-disk backup. May be given multiple
+    /// times. Disks alongside the given backup folder are found automatically.
+    #[arg(long = "disk")]
+    pub(crate) disks: Vec<PathBuf>,
+
+    /// Path to the folder (or zip archive) containing the user's cached manifest
+    /// files.
     #[arg(long)]
     pub(crate) manifest_dir: PathBuf,
+
+    /// Only extract files from the given depot. May be given multiple times.
+    #[arg(long = "depot")]
+    pub(crate) depots: Vec<u32>,
+
+    /// List the files that would be extracted, with their sizes, without reading any
+    /// chunks or writing anything.
+    #[arg(long)]
+    pub(crate) dry_run: bool,
+
+    /// Replace files that already exist in the output folder. By default, extraction
+    /// stops at the first file that already exists.
+    #[arg(long, conflicts_with_all = ["skip_existing", "tar"])]
+    pub(crate) overwrite: bool,
+
+    /// Leave files that already exist in the output folder as they are, if they have
+    /// the expected size. Extraction stops at an existing file with a different size.
+    #[arg(long, conflicts_with = "tar")]
+    pub(crate) skip_existing: bool,
+
+    #[command(flatten)]
+    pub(crate) depot_keys: DepotKeyArgs,
+}
+
+/// Compare two depot manifests, listing the files that were added, removed, or changed.
+///
+/// Files are compared by their sizes, flags, and the SHA-1 digests of their chunks.
+#[derive(Debug, Args)]
+pub(crate) struct DiffManifests {
+    /// Path to the older manifest file.
+    pub(crate) old: PathBuf,
+
+    /// Path to the newer manifest file.
+    pub(crate) new: PathBuf,
+
+    #[command(flatten)]
+    pub(crate) depot_keys: DepotKeyArgs,
+}
+
+/// Decrypt the filenames in a depot manifest.
+///
+/// The manifest with decrypted filenames is written to a new file.
+#[derive(Debug, Args)]
+pub(crate) struct DecryptFilenames {
+    /// Path to the manifest file.
+    pub(crate) path: PathBuf,
+
+    /// Path to write the decrypted manifest to.
+    #[arg(short, long)]
+    pub(crate) output: PathBuf,
+
+    #[command(flatten)]
+    pub(crate) depot_keys: DepotKeyArgs,
+}
+
+/// Work with Steam depot manifests.
+#[derive(Debug, Subcommand)]
+pub(crate) enum Manifest {
+    Convert(ConvertManifest),
+}
+
+/// Convert a depot manifest into another format.
+#[derive(Debug, Args)]
+pub(crate) struct ConvertManifest {
+    /// Path to the manifest file.
+    pub(crate) path: PathBuf,
+
+    /// The format to convert the manifest into.
+    #[arg(long, value_enum)]
+    pub(crate) format: ManifestFormat,
+
+    /// Path to write the converted manifest to. Defaults to stdout.
+    #[arg(short, long)]
+    pub(crate) output: Option<PathBuf>,
+
+    #[command(flatten)]
+    pub(crate) depot_keys: DepotKeyArgs,
+}
+
+#[derive(Clone, Copy, Debug, ValueEnum)]
+pub(crate) enum ManifestFormat {
+    /// The text listing produced by tools like DepotDownloader.
+    Text,
 }
@@ -1,2 +1,7 @@
 mod backup;
+mod completion;
+mod filter;
 mod inspect;
+mod keys;
+mod manifest;
+mod steam;
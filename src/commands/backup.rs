@@ -1,2 +1,440 @@
+use std::collections::{btree_map::Entry, BTreeMap};
+use std::path::{Component, Path, PathBuf};
+
+use anyhow::{anyhow, Context};
+
+use crate::formats::sis::StockKeepingUnit;
+
+mod bench;
+mod cat;
+mod check_sku;
+mod compare;
+mod create;
+mod decrypt_filenames;
+mod diff;
+mod extract;
+mod find;
+mod ls;
 mod mount;
+mod repair;
+mod sha1sums;
+mod stats;
 mod verify;
+
+#[cfg(test)]
+mod testing;
+
+/// Chunk cache size for commands that read files sequentially.
+///
+/// Enough to avoid decompressing chunks twice when they span multiple reads.
+const SEQUENTIAL_CACHE_SIZE: u64 = 16 * 1024 * 1024;
+
+/// Converts a filename from a depot manifest into a relative platform path.
+///
+/// Components may be separated by `/` or `\`, or a mix of both, and empty components
+/// are ignored. `.` components are removed, and `..` components remove the preceding
+/// component but never go above the root of the depot, so that the path stays within
+/// any folder it is joined onto. Components that the platform would not treat as a
+/// plain name (such as `C:` on Windows) are also removed.
+pub(super) fn manifest_path(filename: &str) -> PathBuf {
+    let mut path = PathBuf::new();
+    for component in filename.split(['/', '\\']) {
+        match component {
+            ".." => {
+                path.pop();
+            }
+            name => {
+                let mut components = Path::new(name).components();
+                if let (Some(Component::Normal(name)), None) =
+                    (components.next(), components.next())
+                {
+                    path.push(name);
+                }
+            }
+        }
+    }
+    path
+}
+
+/// Resolves a path to a backup folder, or a file within it, to the backup folder.
+fn resolve_base_dir(path: &Path) -> anyhow::Result<PathBuf> {
+    let metadata = path.metadata()?;
+    if metadata.is_dir() {
+        Ok(path.to_path_buf())
+    } else if metadata.is_file() {
+        Ok(path
+            .parent()
+            .expect("Files always have parents")
+            .to_path_buf())
+    } else {
+        Err(anyhow!("Path does not exist"))
+    }
+}
+
+/// A backup, which may be split across the folders of several disks.
+pub(super) struct Backup {
+    /// The SKU of the backup, with the depots and chunkstores of every disk merged.
+    pub(super) sku: StockKeepingUnit,
+    /// The folders of the disks that were found, in disk order.
+    pub(super) dirs: Vec<PathBuf>,
+    /// The disks that the SKU says exist, but that were not found.
+    pub(super) missing_disks: Vec<u32>,
+}
+
+impl Backup {
+    /// Opens the backup at `path`, which may be a backup folder, a file within it, or a
+    /// folder containing the disks of a multi-disk backup.
+    ///
+    /// The folders of other disks may be provided in `other_disks`. Any disks that are
+    /// still missing are searched for alongside the disks that were found.
+    pub(super) fn open(path: &Path, other_disks: &[PathBuf]) -> anyhow::Result<Self> {
+        let base_dir = resolve_base_dir(path)?;
+
+        let mut dirs = if find_sku(&base_dir).is_some() {
+            vec![base_dir]
+        } else {
+            // The backup may be nested a level down, or split into disk folders.
+            let disks = find_disks(&base_dir)?;
+            if disks.is_empty() {
+                // Explain what is wrong with the folder.
+                check_not_install(&base_dir)?;
+                let searched = subfolders(&base_dir)?
+                    .iter()
+                    .map(|dir| dir.display().to_string())
+                    .collect::<Vec<_>>();
+                return Err(if searched.is_empty() {
+                    anyhow!("Cannot find sku.sis in {}", base_dir.display())
+                } else {
+                    anyhow!(
+                        "Cannot find sku.sis in {} or any of its sub-folders: {}",
+                        base_dir.display(),
+                        searched.join(", "),
+                    )
+                });
+            }
+            disks
+        };
+        for disk in other_disks {
+            dirs.push(resolve_base_dir(disk)?);
+        }
+
+        let mut disks = dirs
+            .into_iter()
+            .map(|dir| read_sku(&dir).map(|sku| (dir, sku)))
+            .collect::<anyhow::Result<Vec<_>>>()?;
+
+        let (name, total) = (&disks[0].1.name, disks[0].1.disks);
+        if disks.len() < usize::try_from(total).unwrap_or(usize::MAX) {
+            let mut parents = disks
+                .iter()
+                .filter_map(|(dir, _)| dir.parent())
+                .map(|parent| parent.to_path_buf())
+                .collect::<Vec<_>>();
+            parents.sort();
+            parents.dedup();
+
+            let mut found = vec![];
+            for parent in parents {
+                for dir in find_disks(&parent).unwrap_or_default() {
+                    if disks.iter().chain(&found).any(|(d, _)| *d == dir) {
+                        continue;
+                    }
+                    if let Ok(sku) = read_sku(&dir) {
+                        if sku.name == *name
+                            && sku.disks == total
+                            && !disks.iter().chain(&found).any(|(_, s)| s.disk == sku.disk)
+                        {
+                            found.push((dir, sku));
+                        }
+                    }
+                }
+            }
+            disks.extend(found);
+        }
+
+        Self::from_disks(disks)
+    }
+
+    /// Opens the backup made up of the given disk folders.
+    fn from_dirs(dirs: Vec<PathBuf>) -> anyhow::Result<Self> {
+        Self::from_disks(
+            dirs.into_iter()
+                .map(|dir| read_sku(&dir).map(|sku| (dir, sku)))
+                .collect::<anyhow::Result<_>>()?,
+        )
+    }
+
+    fn from_disks(mut disks: Vec<(PathBuf, StockKeepingUnit)>) -> anyhow::Result<Self> {
+        disks.sort_by_key(|(_, sku)| sku.disk);
+
+        let mut disks = disks.into_iter();
+        let (first_dir, mut sku) = disks.next().expect("at least one disk");
+        if sku.disk == 0 || sku.disk > sku.disks {
+            return Err(anyhow!(
+                "{} claims to be disk {} of {}",
+                first_dir.display(),
+                sku.disk,
+                sku.disks,
+            ));
+        }
+
+        let mut dirs = vec![first_dir];
+        let mut found = vec![sku.disk];
+        for (dir, disk) in disks {
+            if disk.name != sku.name {
+                return Err(anyhow!(
+                    "{} contains a backup of '{}', not '{}'",
+                    dir.display(),
+                    disk.name,
+                    sku.name,
+                ));
+            }
+            if disk.disks != sku.disks {
+                return Err(anyhow!(
+                    "{} says the backup has {} disks, but {} says it has {}",
+                    dir.display(),
+                    disk.disks,
+                    dirs[0].display(),
+                    sku.disks,
+                ));
+            }
+            if disk.disk > sku.disks {
+                return Err(anyhow!(
+                    "{} claims to be disk {} of {}",
+                    dir.display(),
+                    disk.disk,
+                    disk.disks,
+                ));
+            }
+            if found.contains(&disk.disk) {
+                return Err(anyhow!(
+                    "Found disk {} twice, in {} and {}",
+                    disk.disk,
+                    dirs.last().expect("non-empty").display(),
+                    dir.display(),
+                ));
+            }
+
+            for app in disk.apps {
+                if !sku.apps.contains(&app) {
+                    sku.apps.push(app);
+                }
+            }
+            for depot in disk.depots {
+                if !sku.depots.contains(&depot) {
+                    sku.depots.push(depot);
+                }
+            }
+            for (depot, manifest) in disk.manifests {
+                match sku.manifests.entry(depot) {
+                    Entry::Vacant(entry) => {
+                        entry.insert(manifest);
+                    }
+                    Entry::Occupied(entry) if *entry.get() != manifest => {
+                        return Err(anyhow!(
+                            "Disks {} and {} disagree on the manifest for depot {depot}",
+                            sku.disk,
+                            disk.disk,
+                        ))
+                    }
+                    Entry::Occupied(_) => (),
+                }
+            }
+            for (depot, chunkstores) in disk.chunkstores {
+                let merged = sku.chunkstores.entry(depot).or_default();
+                for (index, length) in chunkstores {
+                    if merged
+                        .insert(index, length)
+                        .is_some_and(|existing| existing != length)
+                    {
+                        return Err(anyhow!(
+                            "Disks disagree on the size of chunkstore {index} for depot {depot}",
+                        ));
+                    }
+                }
+            }
+
+            dirs.push(dir);
+            found.push(disk.disk);
+        }
+
+        let missing_disks = (1..=sku.disks)
+            .filter(|disk| !found.contains(disk))
+            .collect();
+
+        Ok(Self {
+            sku,
+            dirs,
+            missing_disks,
+        })
+    }
+
+    /// Describes the missing disks, like "disk(s) 2, 3 of 3".
+    pub(super) fn describe_missing_disks(&self) -> String {
+        format!(
+            "disk(s) {} of {}",
+            self.missing_disks
+                .iter()
+                .map(|disk| disk.to_string())
+                .collect::<Vec<_>>()
+                .join(", "),
+            self.sku.disks,
+        )
+    }
+
+    /// Returns an error if any disks of the backup are missing.
+    fn require_all_disks(self) -> anyhow::Result<Self> {
+        if self.missing_disks.is_empty() {
+            Ok(self)
+        } else {
+            Err(anyhow!(
+                "Backup of '{}' is missing {}; pass their folders with --disk",
+                self.sku.name,
+                self.describe_missing_disks(),
+            ))
+        }
+    }
+}
+
+/// Returns `true` if two disks' depot manifests can be merged, because they list the
+/// same manifest for every depot that both of them contain.
+pub(super) fn manifests_agree(a: &BTreeMap<u32, u64>, b: &BTreeMap<u32, u64>) -> bool {
+    a.iter()
+        .all(|(depot, manifest)| b.get(depot).is_none_or(|other| other == manifest))
+}
+
+/// Returns the sub-folders of `dir` that are backup folders, such as the `Disk_1`,
+/// `Disk_2`, ... folders of a multi-disk backup.
+fn find_disks(dir: &Path) -> std::io::Result<Vec<PathBuf>> {
+    Ok(subfolders(dir)?
+        .into_iter()
+        .filter(|path| find_sku(path).is_some())
+        .collect())
+}
+
+/// Returns the sub-folders of `dir`, in sorted order.
+fn subfolders(dir: &Path) -> std::io::Result<Vec<PathBuf>> {
+    let mut subfolders = dir
+        .read_dir()?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.is_dir())
+        .collect::<Vec<_>>();
+    subfolders.sort();
+    Ok(subfolders)
+}
+
+/// Finds the SKU file of the backup folder `dir`.
+///
+/// Filenames are matched ignoring case, so `SKU.SIS` is found on case-sensitive
+/// filesystems. If there is no `sku.sis`, the first other `.sis` file is used.
+pub(super) fn find_sku(dir: &Path) -> Option<PathBuf> {
+    let mut candidates = dir
+        .read_dir()
+        .ok()?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| {
+            path.extension()
+                .is_some_and(|ext| ext.eq_ignore_ascii_case("sis"))
+                && path.is_file()
+        })
+        .collect::<Vec<_>>();
+    candidates.sort_by_key(|path| {
+        let is_sku = path
+            .file_name()
+            .is_some_and(|name| name.eq_ignore_ascii_case("sku.sis"));
+        (!is_sku, path.clone())
+    });
+    candidates.into_iter().next()
+}
+
+/// Reads the SKU of the backup in `base_dir`.
+///
+/// If there is no `sku.sis`, this checks whether the user has pointed us at an installed
+/// game instead of a backup, and explains the difference.
+fn read_sku(base_dir: &Path) -> anyhow::Result<StockKeepingUnit> {
+    let Some(sku_path) = find_sku(base_dir) else {
+        check_not_install(base_dir)?;
+        return Err(anyhow!("Cannot find sku.sis in {}", base_dir.display()));
+    };
+
+    StockKeepingUnit::read(&sku_path)
+        .with_context(|| format!("Failed to read {}", sku_path.display()))
+}
+
+/// Returns an error explaining the difference if `dir` looks like an installed game
+/// instead of a backup.
+fn check_not_install(dir: &Path) -> anyhow::Result<()> {
+    if looks_like_install(dir) {
+        Err(anyhow!(
+            "{} looks like an installed game rather than a Steam backup. tev operates on \
+             Steam backup folders (the ones containing sku.sis and .csm/.csd files), not \
+             on game installs.",
+            dir.display(),
+        ))
+    } else {
+        Ok(())
+    }
+}
+
+/// Returns `true` if `dir` contains files, but none of the Steam files that `tev` works
+/// with.
+fn looks_like_install(dir: &Path) -> bool {
+    let Ok(entries) = dir.read_dir() else {
+        return false;
+    };
+
+    let mut has_entries = false;
+    for entry in entries.flatten() {
+        has_entries = true;
+        let path = entry.path();
+        if path.extension().is_some_and(|ext| {
+            ext.eq_ignore_ascii_case("sis")
+                || ext.eq_ignore_ascii_case("csm")
+                || ext.eq_ignore_ascii_case("csd")
+                || ext.eq_ignore_ascii_case("manifest")
+        }) {
+            return false;
+        }
+    }
+
+    has_entries
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::{Path, PathBuf};
+
+    use super::manifest_path;
+
+    fn components(filename: &str) -> Vec<String> {
+        manifest_path(filename)
+            .components()
+            .map(|c| c.as_os_str().to_string_lossy().into_owned())
+            .collect()
+    }
+
+    #[test]
+    fn mixed_separators() {
+        assert_eq!(components("dir\\sub/file"), ["dir", "sub", "file"]);
+        assert_eq!(
+            manifest_path("dir\\sub/file"),
+            Path::new("dir").join("sub").join("file"),
+        );
+    }
+
+    #[test]
+    fn runs_of_separators() {
+        assert_eq!(components("dir//sub\\\\file"), ["dir", "sub", "file"]);
+        assert_eq!(components("/\\dir/\\/file\\"), ["dir", "file"]);
+        assert_eq!(manifest_path("//\\"), PathBuf::new());
+    }
+
+    #[test]
+    fn dot_components() {
+        assert_eq!(components("./dir/./file"), ["dir", "file"]);
+        assert_eq!(components("dir/sub/../file"), ["dir", "file"]);
+        assert_eq!(components("dir\\..\\..\\file"), ["file"]);
+        assert_eq!(components("../../../etc/passwd"), ["etc", "passwd"]);
+        assert_eq!(manifest_path(".."), PathBuf::new());
+    }
+}
@@ -0,0 +1,141 @@
+use std::collections::BTreeMap;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use anyhow::anyhow;
+use byte_unit::{Byte, UnitType};
+use futures_util::{stream, StreamExt};
+use tracing::debug;
+
+use super::Backup;
+use crate::{
+    cli::BenchBackup,
+    formats::csd::{ChunkStore, ChunkTimings},
+};
+
+/// Totals for the chunks that use one compression format.
+#[derive(Default)]
+struct Totals {
+    chunks: usize,
+    compressed: u64,
+    uncompressed: u64,
+    read: Duration,
+    decompress: Duration,
+    hash: Duration,
+}
+
+impl Totals {
+    fn add(&mut self, timings: &ChunkTimings) {
+        self.chunks += 1;
+        self.compressed += timings.compressed_length;
+        self.uncompressed += timings.uncompressed_length;
+        self.read += timings.read;
+        self.decompress += timings.decompress;
+        self.hash += timings.hash;
+    }
+
+    fn print(&self, label: &str) {
+        let compressed = Byte::from_u64(self.compressed).get_appropriate_unit(UnitType::Binary);
+        let uncompressed = Byte::from_u64(self.uncompressed).get_appropriate_unit(UnitType::Binary);
+        println!(
+            "{label:<11}  {:>8}  {:>12}  {:>12}  {:>13}  {:>13}  {:>13}",
+            self.chunks,
+            format!("{compressed:#.2}"),
+            format!("{uncompressed:#.2}"),
+            throughput(self.compressed, self.read),
+            throughput(self.uncompressed, self.decompress),
+            throughput(self.uncompressed, self.hash),
+        );
+    }
+}
+
+impl BenchBackup {
+    pub(crate) async fn run(self) -> anyhow::Result<()> {
+        let backup = Backup::open(&self.path, &self.disks)?.require_all_disks()?;
+        let depot_keys = self.depot_keys.load()?;
+        let jobs = self.jobs.map_or_else(
+            || thread::available_parallelism().map_or(1, |n| n.get()),
+            |jobs| jobs.get(),
+        );
+
+        let mut by_compression = BTreeMap::<_, Totals>::new();
+        let mut failed = 0;
+        let start = Instant::now();
+        for (&depot, chunkstores) in &backup.sku.chunkstores {
+            for &chunkstore_index in chunkstores.keys() {
+                let chunkstore = ChunkStore::open(
+                    &backup.dirs,
+                    depot,
+                    chunkstore_index,
+                    depot_keys.get(&depot).copied(),
+                )
+                .await?;
+
+                // Read the chunks in file order, as verification does.
+                let mut chunks = chunkstore.csm.chunks.iter().collect::<Vec<_>>();
+                chunks.sort_by_key(|(_, chunk)| chunk.offset);
+
+                let mut results = stream::iter(chunks)
+                    .map(|(sha, _)| chunkstore.time_chunk(*sha))
+                    .buffer_unordered(jobs);
+                while let Some(result) = results.next().await {
+                    match result {
+                        Ok(timings) => by_compression
+                            .entry(timings.compression)
+                            .or_default()
+                            .add(&timings),
+                        Err(e) => {
+                            debug!("Failed to check chunk in {}: {e}", chunkstore.csd_filename);
+                            failed += 1;
+                        }
+                    }
+                }
+            }
+        }
+        let elapsed = start.elapsed();
+
+        println!(
+            "{:<11}  {:>8}  {:>12}  {:>12}  {:>13}  {:>13}  {:>13}",
+            "Compression", "Chunks", "Compressed", "Uncompressed", "Read", "Decompress", "SHA-1",
+        );
+        let mut total = Totals::default();
+        for (compression, totals) in &by_compression {
+            totals.print(compression);
+            total.chunks += totals.chunks;
+            total.compressed += totals.compressed;
+            total.uncompressed += totals.uncompressed;
+            total.read += totals.read;
+            total.decompress += totals.decompress;
+            total.hash += totals.hash;
+        }
+        total.print("Total");
+        println!();
+        println!(
+            "Stage throughputs are per thread. Checked {:#.2} of chunks in {:.2}s with up \
+            to {jobs} at once ({}).",
+            Byte::from_u64(total.compressed).get_appropriate_unit(UnitType::Binary),
+            elapsed.as_secs_f64(),
+            throughput(total.compressed, elapsed),
+        );
+
+        if failed > 0 {
+            Err(anyhow!(
+                "{failed} chunks could not be checked (use `tev backup verify` for details)"
+            ))
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// Formats a rate of processing `bytes` in `duration`, in MiB/s.
+fn throughput(bytes: u64, duration: Duration) -> String {
+    if duration.is_zero() {
+        "-".into()
+    } else {
+        format!(
+            "{:.1} MiB/s",
+            bytes as f64 / duration.as_secs_f64() / (1024.0 * 1024.0)
+        )
+    }
+}
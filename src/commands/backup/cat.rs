@@ -0,0 +1,32 @@
+use anyhow::Context;
+use tokio::runtime::Builder;
+
+use super::{mount::BackupFs, Backup, SEQUENTIAL_CACHE_SIZE};
+use crate::{cli::CatBackup, formats::manifest::ManifestSource};
+
+impl CatBackup {
+    pub(crate) fn run(self) -> anyhow::Result<()> {
+        let backup = Backup::open(&self.path, &self.disks)?.require_all_disks()?;
+
+        let depot_keys = self.depot_keys.load()?;
+
+        let filesystem = BackupFs::prepare(
+            backup,
+            &ManifestSource::open(&self.manifest_dir)?,
+            SEQUENTIAL_CACHE_SIZE,
+            &depot_keys,
+            Builder::new_current_thread().build()?,
+        )
+        .context("Failed to prepare filesystem")?;
+
+        filesystem.read_range(
+            &self.file,
+            self.offset,
+            self.length,
+            std::io::stdout().lock(),
+        )?;
+        filesystem.log_cache_stats();
+
+        Ok(())
+    }
+}
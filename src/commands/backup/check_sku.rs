@@ -0,0 +1,106 @@
+use anyhow::anyhow;
+
+use super::Backup;
+use crate::{
+    cli::CheckSku,
+    formats::{csd::ChunkStore, manifest::ManifestSource},
+};
+
+impl CheckSku {
+    pub(crate) fn run(self) -> anyhow::Result<()> {
+        let backup = Backup::open(&self.path, &self.disks)?;
+        let sku = &backup.sku;
+
+        println!("Game: {}", sku.name);
+        if !backup.missing_disks.is_empty() {
+            println!("Missing {}", backup.describe_missing_disks());
+        }
+
+        let manifests = self
+            .manifest_dir
+            .as_deref()
+            .map(ManifestSource::open)
+            .transpose()?;
+
+        let mut failed_depots = 0;
+        for depot in &sku.depots {
+            let mut problems = vec![];
+            let mut warnings = vec![];
+
+            match sku.manifests.get(depot) {
+                Some(manifest_id) => {
+                    if let Some(manifests) = &manifests {
+                        if !manifests.contains(*depot, *manifest_id) {
+                            problems.push(format!(
+                                "{depot}_{manifest_id}.manifest not found in {}",
+                                manifests.path().display(),
+                            ));
+                        }
+                    }
+                }
+                None => problems.push("No manifest ID in the SKU".into()),
+            }
+
+            match sku.chunkstores.get(depot) {
+                Some(chunkstores) if !chunkstores.is_empty() => {
+                    for (index, &length) in chunkstores {
+                        if let Err(e) = ChunkStore::find_csm(&backup.dirs, *depot, *index) {
+                            problems.push(format!("{e:#}"));
+                        }
+
+                        match ChunkStore::find_csd(&backup.dirs, *depot, *index) {
+                            Ok(path) => {
+                                let csd = path.file_name().expect("exists").to_string_lossy();
+                                let actual = path.metadata()?.len();
+                                match u64::try_from(length) {
+                                    Ok(expected) if expected != actual => problems.push(format!(
+                                        "{csd} should be {expected} bytes according to \
+                                            the SKU, but is actually {actual} bytes",
+                                    )),
+                                    Ok(_) => (),
+                                    // Some SKUs record a chunkstore length of -1.
+                                    Err(_) => warnings
+                                        .push(format!("The SKU does not record the size of {csd}")),
+                                }
+                            }
+                            Err(e) => problems.push(format!("{e:#}")),
+                        }
+                    }
+                }
+                _ => problems.push("No chunkstores in the SKU".into()),
+            }
+
+            if problems.is_empty() {
+                println!("Depot {depot}: OK");
+            } else {
+                println!("Depot {depot}: FAILED");
+                failed_depots += 1;
+            }
+            for problem in problems.iter().chain(&warnings) {
+                println!("- {problem}");
+            }
+        }
+
+        for depot in sku.chunkstores.keys() {
+            if !sku.depots.contains(depot) {
+                println!(
+                    "Warning: The SKU has chunkstores for depot {depot}, which it does not list"
+                );
+            }
+        }
+
+        if failed_depots > 0 {
+            Err(anyhow!(
+                "{failed_depots} of {} depots failed the check",
+                sku.depots.len()
+            ))
+        } else if !backup.missing_disks.is_empty() {
+            Err(anyhow!(
+                "Backup is missing {}",
+                backup.describe_missing_disks()
+            ))
+        } else {
+            Ok(())
+        }
+    }
+}
@@ -0,0 +1,215 @@
+use std::collections::HashSet;
+use std::fs::{self, File};
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::thread;
+
+use anyhow::{anyhow, Context};
+use sha1::{Digest, Sha1};
+use tokio::runtime::Builder;
+use tracing::error;
+
+use super::{
+    mount::{is_dir, BackupFs, Node},
+    Backup, SEQUENTIAL_CACHE_SIZE,
+};
+use crate::{cli::CompareBackup, formats::manifest::ManifestSource};
+
+/// How an installed file differs from its depot manifest.
+enum Difference {
+    Missing,
+    Changed(String),
+    Extra,
+}
+
+impl CompareBackup {
+    pub(crate) fn run(self) -> anyhow::Result<()> {
+        let jobs = self.jobs.map_or_else(
+            || thread::available_parallelism().map_or(1, |n| n.get()),
+            |jobs| jobs.get(),
+        );
+
+        if !self.install_dir.is_dir() {
+            return Err(anyhow!("{} is not a folder", self.install_dir.display()));
+        }
+
+        let backup = Backup::open(&self.path, &self.disks)?.require_all_disks()?;
+
+        let depot_keys = self.depot_keys.load()?;
+
+        // No chunks are read from the backup, only its manifests.
+        let filesystem = BackupFs::prepare(
+            backup,
+            &ManifestSource::open(&self.manifest_dir)?,
+            SEQUENTIAL_CACHE_SIZE,
+            &depot_keys,
+            Builder::new_current_thread().build()?,
+        )
+        .context("Failed to prepare filesystem")?;
+
+        // Compare the files on `jobs` threads, each taking the next file when it is free.
+        let nodes = Mutex::new(filesystem.real_nodes().enumerate());
+        let results = Mutex::new(vec![]);
+        thread::scope(|scope| {
+            for _ in 0..jobs {
+                scope.spawn(|| loop {
+                    let Some((i, node)) = nodes.lock().unwrap().next() else {
+                        break;
+                    };
+                    let path = node.path().expect("real nodes have paths");
+                    let difference = compare_node(node, &self.install_dir.join(path));
+                    results.lock().unwrap().push((i, node, difference));
+                });
+            }
+        });
+        let mut results = results.into_inner().unwrap();
+        results.sort_by_key(|(i, _, _)| *i);
+
+        // Manifests don't necessarily list the folders that contain their files.
+        let expected = filesystem
+            .real_nodes()
+            .flat_map(|node| node.path().expect("real nodes have paths").ancestors())
+            .collect::<HashSet<_>>();
+        let mut extra = vec![];
+        find_extra(&self.install_dir, Path::new(""), &expected, &mut extra)
+            .with_context(|| format!("Failed to list {}", self.install_dir.display()))?;
+
+        let mut matched = 0;
+        let mut missing = 0;
+        let mut changed = 0;
+        let mut extra_files = 0;
+        let mut failed = 0;
+        let differences = results
+            .into_iter()
+            .filter_map(|(_, node, difference)| {
+                let path = node.path().expect("real nodes have paths");
+                match difference {
+                    Ok(None) => {
+                        if !is_dir(node.file_mapping()) {
+                            matched += 1;
+                        }
+                        None
+                    }
+                    Ok(Some(difference)) => Some((path.to_owned(), difference)),
+                    Err(e) => {
+                        error!("Failed to compare {}: {e:#}", path.display());
+                        failed += 1;
+                        None
+                    }
+                }
+            })
+            .chain(extra.into_iter().map(|path| (path, Difference::Extra)));
+        for (path, difference) in differences {
+            match difference {
+                Difference::Missing => {
+                    println!("missing  {}", path.display());
+                    missing += 1;
+                }
+                Difference::Changed(reason) => {
+                    println!("changed  {} ({reason})", path.display());
+                    changed += 1;
+                }
+                Difference::Extra => {
+                    println!("extra    {}", path.display());
+                    extra_files += 1;
+                }
+            }
+        }
+
+        println!(
+            "{matched} files match the backup; {changed} changed, {missing} missing, \
+            {extra_files} not in the backup"
+        );
+
+        // Extra files (such as saves or settings) are expected in an installed game.
+        if failed > 0 {
+            Err(anyhow!("{failed} files could not be compared"))
+        } else if changed > 0 || missing > 0 {
+            Err(anyhow!("Installed game does not match the backup"))
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// Compares the installed file at `path` with the depot manifest's entry for it.
+fn compare_node(node: &Node, path: &Path) -> anyhow::Result<Option<Difference>> {
+    let metadata = match fs::symlink_metadata(path) {
+        Ok(metadata) => metadata,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Some(Difference::Missing)),
+        Err(e) => return Err(e.into()),
+    };
+    let file_mapping = node.file_mapping().expect("real nodes have file mappings");
+
+    if is_dir(Some(file_mapping)) {
+        return Ok((!metadata.is_dir()).then(|| Difference::Changed("not a folder".into())));
+    }
+
+    if let Some(target) = node.link_target() {
+        // Symlinks can't be created on every platform, so only check their targets
+        // where they exist.
+        return Ok(if metadata.is_symlink() {
+            let actual = fs::read_link(path)?;
+            (actual != Path::new(target))
+                .then(|| Difference::Changed(format!("links to {}", actual.display())))
+        } else {
+            None
+        });
+    }
+
+    if !metadata.is_file() {
+        return Ok(Some(Difference::Changed("not a file".into())));
+    }
+    if metadata.len() != node.size() {
+        return Ok(Some(Difference::Changed(format!(
+            "{} bytes instead of {}",
+            metadata.len(),
+            node.size(),
+        ))));
+    }
+
+    // Hash the file along the boundaries of its chunks, so that each digest can be
+    // compared with the chunk's SHA-1 digest in the manifest.
+    let mut chunks = file_mapping.chunks.iter().collect::<Vec<_>>();
+    chunks.sort_by_key(|chunk| chunk.offset());
+    let mut file = File::open(path)?;
+    let mut buffer = vec![];
+    let mut changed = 0;
+    for chunk in &chunks {
+        buffer.resize(chunk.cb_original() as usize, 0);
+        file.seek(SeekFrom::Start(chunk.offset()))?;
+        file.read_exact(&mut buffer)?;
+        if Sha1::digest(&buffer).as_slice() != chunk.sha() {
+            changed += 1;
+        }
+    }
+
+    Ok((changed > 0)
+        .then(|| Difference::Changed(format!("{changed} of {} chunks differ", chunks.len()))))
+}
+
+/// Finds the files and folders under `dir` that are not in `expected`, adding their
+/// paths (relative to the install folder) to `extra`.
+///
+/// The contents of extra folders are not listed.
+fn find_extra(
+    dir: &Path,
+    prefix: &Path,
+    expected: &HashSet<&Path>,
+    extra: &mut Vec<PathBuf>,
+) -> io::Result<()> {
+    let mut entries = fs::read_dir(dir)?.collect::<Result<Vec<_>, _>>()?;
+    entries.sort_by_key(|entry| entry.file_name());
+
+    for entry in entries {
+        let path = prefix.join(entry.file_name());
+        if !expected.contains(path.as_path()) {
+            extra.push(path);
+        } else if entry.file_type()?.is_dir() {
+            find_extra(&entry.path(), &path, expected, extra)?;
+        }
+    }
+
+    Ok(())
+}
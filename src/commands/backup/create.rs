@@ -0,0 +1,258 @@
+use std::collections::{BTreeMap, HashSet};
+use std::fs::{self, File};
+use std::io::{BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+use anyhow::{anyhow, Context};
+use byte_unit::{Byte, UnitType};
+use sha1::{Digest, Sha1};
+
+use super::{manifest_path, mount::is_dir};
+use crate::{
+    cli::CreateBackup,
+    formats::{
+        csd::compress_chunk,
+        csm::{Chunk, ChunkStoreManifest},
+        manifest::Manifest,
+        sis::StockKeepingUnit,
+    },
+};
+
+/// The size at which we start a new chunkstore for a depot.
+const MAX_CHUNKSTORE_SIZE: u64 = 1024 * 1024 * 1024;
+
+impl CreateBackup {
+    pub(crate) fn run(self) -> anyhow::Result<()> {
+        if self.output.exists() && self.output.read_dir()?.next().is_some() {
+            return Err(anyhow!("{} is not empty", self.output.display()));
+        }
+        fs::create_dir_all(&self.output)?;
+
+        let mut sku = StockKeepingUnit {
+            name: self.name,
+            disks: 1,
+            disk: 1,
+            backup: 1,
+            contenttype: 3,
+            apps: self.apps,
+            depots: vec![],
+            manifests: BTreeMap::new(),
+            chunkstores: BTreeMap::new(),
+        };
+
+        for manifest_path in &self.manifests {
+            let manifest = Manifest::open(manifest_path)?;
+            let depot = manifest.metadata.depot_id();
+            if manifest.metadata.filenames_encrypted() {
+                return Err(anyhow!(
+                    "Manifest {} for depot {depot} has encrypted filenames",
+                    manifest_path.display(),
+                ));
+            }
+            if sku.manifests.contains_key(&depot) {
+                return Err(anyhow!("Multiple manifests provided for depot {depot}"));
+            }
+
+            println!("Backing up depot {depot}");
+            let chunkstores = write_depot(&self.files, &self.output, &manifest)?;
+
+            sku.depots.push(depot);
+            sku.manifests
+                .insert(depot, manifest.metadata.gid_manifest());
+            sku.chunkstores.insert(depot, chunkstores);
+        }
+
+        sku.write(BufWriter::new(File::create(self.output.join("sku.sis"))?))?;
+
+        let size = Byte::from_u64(
+            sku.chunkstores
+                .values()
+                .flat_map(|chunkstores| chunkstores.values())
+                .map(|&len| len as u64)
+                .sum(),
+        )
+        .get_appropriate_unit(UnitType::Binary);
+        println!("Created backup of {} ({size:#.2})", sku.name);
+
+        Ok(())
+    }
+}
+
+/// An in-progress chunkstore.
+struct ChunkStoreWriter {
+    csm: ChunkStoreManifest,
+    csd: BufWriter<File>,
+    len: u64,
+}
+
+/// Writes the chunkstores for a single depot.
+///
+/// Returns the lengths of the written chunkstores, keyed by chunkstore index.
+fn write_depot(
+    files_dir: &Path,
+    output: &Path,
+    manifest: &Manifest,
+) -> anyhow::Result<BTreeMap<u32, i32>> {
+    let depot = manifest.metadata.depot_id();
+
+    let mut chunkstores = BTreeMap::new();
+    let mut seen = HashSet::new();
+    let mut current: Option<(u32, ChunkStoreWriter)> = None;
+    let mut buf = vec![];
+
+    for file_mapping in &manifest.payload.mappings {
+        if is_dir(Some(file_mapping)) || file_mapping.chunks.is_empty() {
+            continue;
+        }
+
+        let path = files_dir.join(manifest_path(file_mapping.filename()));
+        let mut file =
+            File::open(&path).with_context(|| format!("Cannot open {}", path.display()))?;
+
+        for chunk in &file_mapping.chunks {
+            let sha: [u8; 20] = chunk
+                .sha()
+                .try_into()
+                .map_err(|_| anyhow!("Invalid chunk SHA in manifest"))?;
+            if !seen.insert(sha) {
+                // Chunks are deduplicated across the depot.
+                continue;
+            }
+
+            buf.resize(usize::try_from(chunk.cb_original())?, 0);
+            file.seek(SeekFrom::Start(chunk.offset()))?;
+            file.read_exact(&mut buf)?;
+            if Sha1::digest(&buf) != sha.into() {
+                return Err(anyhow!(
+                    "{} does not match the manifest at offset {}",
+                    path.display(),
+                    chunk.offset(),
+                ));
+            }
+            let compressed = compress_chunk(&buf)?;
+
+            // Start a new chunkstore if necessary.
+            if let Some((index, writer)) =
+                current.take_if(|(_, writer)| writer.len >= MAX_CHUNKSTORE_SIZE)
+            {
+                chunkstores.insert(index, finish_chunkstore(output, index, writer)?);
+            }
+            let (_, writer) = match &mut current {
+                Some(current) => current,
+                None => {
+                    let index = chunkstores.len() as u32 + 1;
+                    let csd_path = output.join(format!("{depot}_depotcache_{index}.csd"));
+                    current.insert((
+                        index,
+                        ChunkStoreWriter {
+                            csm: ChunkStoreManifest {
+                                is_encrypted: false,
+                                depot,
+                                chunks: vec![],
+                            },
+                            csd: BufWriter::new(File::create(csd_path)?),
+                            len: 0,
+                        },
+                    ))
+                }
+            };
+
+            writer.csd.write_all(&compressed)?;
+            writer.csm.chunks.push((
+                sha,
+                Chunk {
+                    offset: writer.len,
+                    uncompressed_length: chunk.cb_original(),
+                    compressed_length: u32::try_from(compressed.len())?,
+                },
+            ));
+            writer.len += compressed.len() as u64;
+        }
+    }
+
+    if let Some((index, writer)) = current {
+        chunkstores.insert(index, finish_chunkstore(output, index, writer)?);
+    }
+
+    Ok(chunkstores)
+}
+
+/// Flushes the chunkstore's data file and writes its manifest.
+///
+/// Returns the length of the data file.
+fn finish_chunkstore(
+    output: &Path,
+    index: u32,
+    mut writer: ChunkStoreWriter,
+) -> anyhow::Result<i32> {
+    writer.csd.flush()?;
+
+    let csm_path = output.join(format!("{}_depotcache_{index}.csm", writer.csm.depot));
+    let mut csm = BufWriter::new(File::create(csm_path)?);
+    writer.csm.write(&mut csm)?;
+    csm.flush()?;
+
+    Ok(i32::try_from(writer.len)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use clap::Parser;
+    use tokio::runtime::Builder;
+
+    use crate::cli::{self, CreateBackup, Options};
+    use crate::commands::backup::testing::{TempDir, TestBackup};
+
+    #[test]
+    fn round_trip_through_verify() {
+        let files: &[(&str, &[u8])] = &[
+            ("game.exe", &[7; 1000]),
+            ("data/a.bin", b"some data that spans several chunks"),
+            ("data/empty.txt", b""),
+        ];
+        // Only the depot manifest of this backup is used.
+        let source = TestBackup::new(files, 16);
+
+        let game = TempDir::new();
+        for (path, contents) in files {
+            let path = game.path().join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, contents).unwrap();
+        }
+
+        let output = TempDir::new();
+        let backup = output.path().join("backup");
+        CreateBackup {
+            files: game.path().to_path_buf(),
+            output: backup.clone(),
+            manifests: vec![source.manifest_path()],
+            name: "Round Trip".into(),
+            apps: vec![100],
+        }
+        .run()
+        .unwrap();
+
+        let options = Options::try_parse_from([
+            "tev".as_ref(),
+            "backup".as_ref(),
+            "verify".as_ref(),
+            backup.as_os_str(),
+            "--manifest-dir".as_ref(),
+            source.manifest_dir().as_os_str(),
+            "--strict".as_ref(),
+            "--quiet".as_ref(),
+        ])
+        .unwrap();
+        let cli::Command::Backup(cli::Backup::Verify(verify)) = options.command else {
+            unreachable!("parsed a verify command");
+        };
+        Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap()
+            .block_on(verify.run())
+            .unwrap();
+    }
+}
@@ -0,0 +1,41 @@
+use std::fs::File;
+use std::io::{BufWriter, Write};
+
+use anyhow::{anyhow, Context};
+
+use crate::{cli::DecryptFilenames, formats::manifest::Manifest};
+
+impl DecryptFilenames {
+    pub(crate) fn run(self) -> anyhow::Result<()> {
+        let mut manifest = Manifest::open(&self.path)?;
+        if !manifest.metadata.filenames_encrypted() {
+            return Err(anyhow!(
+                "{} does not have encrypted filenames",
+                self.path.display(),
+            ));
+        }
+
+        let depot = manifest.metadata.depot_id();
+        let depot_key = self.depot_keys.load()?.remove(&depot).ok_or_else(|| {
+            anyhow!(
+                "No key was provided for depot {depot}; pass --depot-key, --keys, or \
+                --steam-config",
+            )
+        })?;
+
+        manifest
+            .decrypt_filenames(&depot_key)
+            .context("Failed to decrypt filenames; is the depot key correct?")?;
+
+        let mut writer = BufWriter::new(File::create(&self.output)?);
+        manifest.write(&mut writer)?;
+        writer.flush()?;
+
+        println!(
+            "Wrote manifest with decrypted filenames to {}",
+            self.output.display(),
+        );
+
+        Ok(())
+    }
+}
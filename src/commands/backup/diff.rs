@@ -0,0 +1,135 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::path::Path;
+
+use anyhow::{anyhow, Context};
+use byte_unit::{Byte, UnitType};
+use steam_vent::proto::content_manifest::content_manifest_payload::FileMapping;
+
+use crate::{cli::DiffManifests, formats::manifest::Manifest};
+
+impl DiffManifests {
+    pub(crate) fn run(self) -> anyhow::Result<()> {
+        let depot_keys = self.depot_keys.load()?;
+        let old = open_manifest(&self.old, &depot_keys)?;
+        let new = open_manifest(&self.new, &depot_keys)?;
+
+        if old.metadata.filenames_encrypted() != new.metadata.filenames_encrypted() {
+            return Err(anyhow!(
+                "Only one of the manifests has encrypted filenames; provide its depot key"
+            ));
+        }
+
+        let old_files = files_by_name(&old);
+        let new_files = files_by_name(&new);
+
+        let mut added = 0;
+        let mut removed = 0;
+        let mut changed = 0;
+
+        for (name, old_file) in &old_files {
+            if !new_files.contains_key(name) {
+                println!("- {name}");
+                removed += 1;
+            } else {
+                let new_file = new_files[name];
+                if let Some(description) = describe_change(old_file, new_file) {
+                    println!("~ {name} ({description})");
+                    changed += 1;
+                }
+            }
+        }
+        for (name, new_file) in &new_files {
+            if !old_files.contains_key(name) {
+                println!("+ {name} ({})", format_size(new_file.size()));
+                added += 1;
+            }
+        }
+
+        println!();
+        println!("{added} added, {removed} removed, {changed} changed");
+
+        Ok(())
+    }
+}
+
+/// Opens a manifest, decrypting its filenames if a key is given for its depot.
+fn open_manifest(path: &Path, depot_keys: &HashMap<u32, [u8; 32]>) -> anyhow::Result<Manifest> {
+    let mut manifest = Manifest::open(path)
+        .with_context(|| format!("Failed to read manifest {}", path.display()))?;
+    if let Some(depot_key) = depot_keys.get(&manifest.metadata.depot_id()) {
+        manifest.decrypt_filenames(depot_key).with_context(|| {
+            format!(
+                "Failed to decrypt filenames in {}; is the depot key correct?",
+                path.display()
+            )
+        })?;
+    }
+    Ok(manifest)
+}
+
+/// Returns the manifest's file mappings, keyed by filename.
+///
+/// Path separators are normalized to `/`. If the filenames are encrypted, the
+/// hex-encoded SHA-1 digest of each filename is used instead.
+fn files_by_name(manifest: &Manifest) -> BTreeMap<String, &FileMapping> {
+    manifest
+        .payload
+        .mappings
+        .iter()
+        .map(|file_mapping| {
+            let name = if manifest.metadata.filenames_encrypted() {
+                hex::encode(file_mapping.sha_filename())
+            } else {
+                file_mapping.filename().replace('\\', "/")
+            };
+            (name, file_mapping)
+        })
+        .collect()
+}
+
+/// Returns a description of how a file changed, or `None` if it is unchanged.
+fn describe_change(old: &FileMapping, new: &FileMapping) -> Option<String> {
+    let old_chunks = old
+        .chunks
+        .iter()
+        .map(|chunk| chunk.sha())
+        .collect::<HashSet<_>>();
+    let differing_chunks = new
+        .chunks
+        .iter()
+        .filter(|chunk| !old_chunks.contains(chunk.sha()))
+        .count();
+
+    if old.size() == new.size()
+        && old.flags() == new.flags()
+        && old.linktarget() == new.linktarget()
+        && old.chunks.len() == new.chunks.len()
+        && differing_chunks == 0
+    {
+        return None;
+    }
+
+    let mut description = match new.size().cmp(&old.size()) {
+        std::cmp::Ordering::Greater => format!("+{}", format_size(new.size() - old.size())),
+        std::cmp::Ordering::Less => format!("-{}", format_size(old.size() - new.size())),
+        std::cmp::Ordering::Equal => "same size".into(),
+    };
+    description.push_str(&format!(
+        ", {differing_chunks} of {} chunks differ",
+        new.chunks.len()
+    ));
+    if old.flags() != new.flags() {
+        description.push_str(&format!(", flags {:#x} -> {:#x}", old.flags(), new.flags()));
+    }
+    if old.linktarget() != new.linktarget() {
+        description.push_str(&format!(", link target -> {}", new.linktarget()));
+    }
+    Some(description)
+}
+
+fn format_size(size: u64) -> String {
+    format!(
+        "{:#.2}",
+        Byte::from_u64(size).get_appropriate_unit(UnitType::Binary)
+    )
+}
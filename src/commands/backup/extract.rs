@@ -0,0 +1,453 @@
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+
+use anyhow::{anyhow, Context};
+use byte_unit::{Byte, UnitType};
+use tokio::runtime::Builder;
+
+use super::{
+    mount::{is_dir, BackupFs, Node},
+    Backup, SEQUENTIAL_CACHE_SIZE,
+};
+use crate::{cli::ExtractBackup, formats::manifest::ManifestSource};
+
+impl ExtractBackup {
+    pub(crate) fn run(self) -> anyhow::Result<()> {
+        let backup = Backup::open(&self.path, &self.disks)?.require_all_disks()?;
+
+        let depot_keys = self.depot_keys.load()?;
+
+        let filesystem = BackupFs::prepare(
+            backup,
+            &ManifestSource::open(&self.manifest_dir)?,
+            SEQUENTIAL_CACHE_SIZE,
+            &depot_keys,
+            Builder::new_current_thread().build()?,
+        )
+        .context("Failed to prepare filesystem")?;
+
+        let nodes = filesystem
+            .real_nodes()
+            .filter(|node| {
+                self.depots.is_empty() || self.depots.contains(&node.metadata().depot_id())
+            })
+            .collect::<Vec<_>>();
+
+        if self.dry_run {
+            let (files, bytes, incomplete) = list(nodes.into_iter());
+            let size = Byte::from_u64(bytes).get_appropriate_unit(UnitType::Binary);
+            println!("Would extract {files} files ({size:#.2})");
+            if incomplete > 0 {
+                println!("{incomplete} files cannot be extracted, as their chunks are missing");
+            }
+            return Ok(());
+        }
+
+        // Check that every file can be read before writing any of them.
+        check_complete(&nodes)?;
+        let nodes = nodes.into_iter();
+
+        match (&self.tar, &self.output) {
+            (Some(tar), _) if tar == Path::new("-") => {
+                let (files, bytes) = write_tar(&filesystem, nodes, io::stdout().lock())?;
+                // Keep stdout for the archive.
+                eprintln!("{}", summary(files, bytes));
+            }
+            (Some(tar), _) => {
+                let file = File::create(tar)
+                    .with_context(|| format!("Failed to create {}", tar.display()))?;
+                let (files, bytes) = write_tar(&filesystem, nodes, file)?;
+                println!("{}", summary(files, bytes));
+            }
+            (None, Some(output)) => {
+                let existing = if self.overwrite {
+                    Existing::Overwrite
+                } else if self.skip_existing {
+                    Existing::Skip
+                } else {
+                    Existing::Error
+                };
+                let (files, bytes, skipped) = extract_to(&filesystem, nodes, output, existing)?;
+                if skipped > 0 {
+                    println!(
+                        "{}, skipped {skipped} existing files",
+                        summary(files, bytes)
+                    );
+                } else {
+                    println!("{}", summary(files, bytes));
+                }
+            }
+            (None, None) => unreachable!("clap requires one of output or --tar"),
+        }
+        filesystem.log_cache_stats();
+
+        Ok(())
+    }
+}
+
+fn summary(files: u64, bytes: u64) -> String {
+    let size = Byte::from_u64(bytes).get_appropriate_unit(UnitType::Binary);
+    format!("Extracted {files} files ({size:#.2})")
+}
+
+/// Returns an error listing the given files that use chunks missing from the backup.
+fn check_complete(nodes: &[&Node]) -> anyhow::Result<()> {
+    let incomplete = nodes
+        .iter()
+        .filter(|node| node.is_incomplete())
+        .map(|node| node.path().expect("real nodes have paths"))
+        .collect::<Vec<_>>();
+    if incomplete.is_empty() {
+        return Ok(());
+    }
+
+    eprintln!("These files use chunks that are missing from the backup:");
+    for path in &incomplete {
+        eprintln!("- {}", path.display());
+    }
+    Err(anyhow!(
+        "Cannot extract {} files, as their chunks are missing from the backup",
+        incomplete.len(),
+    ))
+}
+
+/// Prints the given nodes as they would be extracted, returning the number of files
+/// and bytes that would be written, and the number of files whose chunks are missing.
+fn list<'a>(nodes: impl Iterator<Item = &'a Node>) -> (u64, u64, u64) {
+    let mut files = 0;
+    let mut bytes = 0;
+    let mut incomplete = 0;
+    for node in nodes {
+        let path = node.path().expect("real nodes have paths");
+
+        if is_dir(node.file_mapping()) {
+            println!("{:7}  {:>10}  {}", "dir", "-", path.display());
+        } else if let Some(target) = node.link_target() {
+            println!(
+                "{:7}  {:>10}  {} -> {target}",
+                "symlink",
+                "-",
+                path.display()
+            );
+            files += 1;
+        } else {
+            let size = Byte::from_u64(node.size()).get_appropriate_unit(UnitType::Binary);
+            let missing = if node.is_incomplete() {
+                incomplete += 1;
+                "  (missing chunks)"
+            } else {
+                ""
+            };
+            println!(
+                "{:7}  {:>10}  {}{missing}",
+                "file",
+                format!("{size:#.2}"),
+                path.display()
+            );
+            files += 1;
+            bytes += node.size();
+        }
+    }
+
+    (files, bytes, incomplete)
+}
+
+/// What to do with files that already exist in the output folder.
+#[derive(Clone, Copy)]
+enum Existing {
+    Error,
+    Overwrite,
+    Skip,
+}
+
+/// Writes the given nodes to files under `output`, returning the number of files and
+/// bytes written, and the number of existing files that were skipped.
+fn extract_to<'a>(
+    filesystem: &BackupFs,
+    nodes: impl Iterator<Item = &'a Node>,
+    output: &Path,
+    existing: Existing,
+) -> anyhow::Result<(u64, u64, u64)> {
+    fs::create_dir_all(output)?;
+
+    let mut files = 0;
+    let mut bytes = 0;
+    let mut skipped = 0;
+    for node in nodes {
+        let relative_path = node.path().expect("real nodes have paths");
+        check_no_symlinks(output, relative_path)?;
+        let path = output.join(relative_path);
+
+        if is_dir(node.file_mapping()) {
+            fs::create_dir_all(&path)
+                .with_context(|| format!("Failed to create {}", path.display()))?;
+            continue;
+        }
+
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)
+                .with_context(|| format!("Failed to create {}", parent.display()))?;
+        }
+
+        if !prepare_path(&path, node, existing)? {
+            skipped += 1;
+            continue;
+        }
+
+        if let Some(target) = node.link_target() {
+            create_symlink(target, &path)?;
+            files += 1;
+            continue;
+        }
+
+        let file =
+            File::create(&path).with_context(|| format!("Failed to create {}", path.display()))?;
+        let mut writer = BufWriter::new(file);
+        bytes += filesystem.read_node(node, 0, None, &mut writer)?;
+        let file = writer.into_inner().map_err(|e| e.into_error())?;
+        if node.is_executable() {
+            set_executable(&file)
+                .with_context(|| format!("Failed to set permissions on {}", path.display()))?;
+        }
+        files += 1;
+    }
+
+    Ok((files, bytes, skipped))
+}
+
+/// Checks that none of the folders between `output` and the file at `relative_path`
+/// are symlinks.
+///
+/// Otherwise, a symlink in the backup could direct a later file outside of `output`.
+fn check_no_symlinks(output: &Path, relative_path: &Path) -> anyhow::Result<()> {
+    let mut path = output.to_path_buf();
+    for component in relative_path
+        .parent()
+        .into_iter()
+        .flat_map(Path::components)
+    {
+        path.push(component);
+        if fs::symlink_metadata(&path).is_ok_and(|metadata| metadata.is_symlink()) {
+            return Err(anyhow!(
+                "Refusing to write {} through the symlink {}",
+                output.join(relative_path).display(),
+                path.display(),
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Checks whether the file for `node` already exists at `path`, and handles it
+/// according to `existing`.
+///
+/// Returns `false` if the existing file should be kept as it is.
+fn prepare_path(path: &Path, node: &Node, existing: Existing) -> anyhow::Result<bool> {
+    // Don't follow symlinks, so that we never write through one.
+    let metadata = match fs::symlink_metadata(path) {
+        Ok(metadata) => metadata,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(true),
+        Err(e) => {
+            return Err(e).with_context(|| format!("Failed to check {}", path.display()));
+        }
+    };
+    if metadata.is_dir() {
+        return Err(anyhow!("{} already exists as a folder", path.display()));
+    }
+
+    match existing {
+        Existing::Error => Err(anyhow!(
+            "{} already exists; use --overwrite to replace it, or --skip-existing to keep it",
+            path.display(),
+        )),
+        Existing::Overwrite => {
+            fs::remove_file(path)
+                .with_context(|| format!("Failed to remove {}", path.display()))?;
+            Ok(true)
+        }
+        Existing::Skip => {
+            let matches = if node.link_target().is_some() {
+                metadata.is_symlink()
+            } else {
+                metadata.is_file() && metadata.len() == node.size()
+            };
+            if matches {
+                Ok(false)
+            } else {
+                Err(anyhow!(
+                    "{} already exists, but is not the expected {}; use --overwrite to replace it",
+                    path.display(),
+                    if node.link_target().is_some() {
+                        "symlink".into()
+                    } else {
+                        format!("size ({} bytes)", node.size())
+                    },
+                ))
+            }
+        }
+    }
+}
+
+/// Writes the given nodes to a tar archive, returning the number of files and bytes
+/// written.
+///
+/// Each entry's modification time is the creation time of its depot manifest.
+fn write_tar<'a, W: Write>(
+    filesystem: &BackupFs,
+    nodes: impl Iterator<Item = &'a Node>,
+    writer: W,
+) -> anyhow::Result<(u64, u64)> {
+    let mut archive = tar::Builder::new(BufWriter::new(writer));
+
+    let mut files = 0;
+    let mut bytes = 0;
+    for node in nodes {
+        let path = node.path().expect("real nodes have paths");
+
+        let mut header = tar::Header::new_gnu();
+        header.set_mtime(node.metadata().creation_time().into());
+
+        if is_dir(node.file_mapping()) {
+            header.set_entry_type(tar::EntryType::Directory);
+            header.set_mode(0o755);
+            header.set_size(0);
+            archive.append_data(&mut header, path, io::empty())
+        } else if let Some(target) = node.link_target() {
+            header.set_entry_type(tar::EntryType::Symlink);
+            header.set_mode(0o777);
+            header.set_size(0);
+            files += 1;
+            archive.append_link(&mut header, path, target)
+        } else {
+            header.set_entry_type(tar::EntryType::Regular);
+            header.set_mode(if node.is_executable() { 0o755 } else { 0o644 });
+            header.set_size(node.size());
+            files += 1;
+            bytes += node.size();
+            // Read a chunk's worth at a time.
+            let reader = BufReader::with_capacity(1024 * 1024, NodeReader::new(filesystem, node));
+            archive.append_data(&mut header, path, reader)
+        }
+        .with_context(|| format!("Failed to write {} to the archive", path.display()))?;
+    }
+
+    archive
+        .into_inner()?
+        .into_inner()
+        .map_err(|e| e.into_error())?;
+
+    Ok((files, bytes))
+}
+
+/// Reads the contents of a file in a backup.
+struct NodeReader<'a> {
+    filesystem: &'a BackupFs,
+    node: &'a Node,
+    position: u64,
+}
+
+impl<'a> NodeReader<'a> {
+    fn new(filesystem: &'a BackupFs, node: &'a Node) -> Self {
+        Self {
+            filesystem,
+            node,
+            position: 0,
+        }
+    }
+}
+
+impl Read for NodeReader<'_> {
+    fn read(&mut self, mut buf: &mut [u8]) -> io::Result<usize> {
+        let read = self
+            .filesystem
+            .read_node(self.node, self.position, Some(buf.len() as u64), &mut buf)
+            .map_err(io::Error::other)?;
+        self.position += read;
+        Ok(read as usize)
+    }
+}
+
+#[cfg(unix)]
+fn create_symlink(target: &str, path: &Path) -> anyhow::Result<()> {
+    std::os::unix::fs::symlink(target, path)
+        .with_context(|| format!("Failed to create symlink {}", path.display()))
+}
+
+#[cfg(not(unix))]
+fn create_symlink(target: &str, path: &Path) -> anyhow::Result<()> {
+    // Creating symlinks on Windows requires elevated privileges.
+    tracing::warn!(
+        "Skipping symlink {} -> {target} (not supported on this platform)",
+        path.display(),
+    );
+    Ok(())
+}
+
+#[cfg(unix)]
+fn set_executable(file: &File) -> std::io::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+
+    let mut permissions = file.metadata()?.permissions();
+    // Grant execute permission wherever read permission is granted.
+    permissions.set_mode(permissions.mode() | ((permissions.mode() & 0o444) >> 2));
+    file.set_permissions(permissions)
+}
+
+#[cfg(not(unix))]
+fn set_executable(_: &File) -> std::io::Result<()> {
+    // Windows has no executable bit.
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::{check_complete, extract_to, Existing};
+    use crate::commands::backup::testing::TestBackup;
+
+    #[test]
+    fn empty_file() {
+        let backup = TestBackup::new(&[("empty.txt", b""), ("dir/full.txt", b"contents")], 4);
+        let filesystem = backup.open();
+        let empty = filesystem
+            .real_nodes()
+            .find(|node| node.name() == "empty.txt")
+            .unwrap();
+        assert!(empty.file_mapping().unwrap().chunks.is_empty());
+
+        let output = backup.output_dir();
+        let (files, bytes, skipped) = extract_to(
+            &filesystem,
+            filesystem.real_nodes(),
+            &output,
+            Existing::Error,
+        )
+        .unwrap();
+        assert_eq!((files, bytes, skipped), (2, 8, 0));
+
+        let metadata = fs::metadata(output.join("empty.txt")).unwrap();
+        assert!(metadata.is_file());
+        assert_eq!(metadata.len(), 0);
+        assert_eq!(fs::read(output.join("dir/full.txt")).unwrap(), b"contents");
+    }
+
+    #[test]
+    fn missing_chunks() {
+        let backup = TestBackup::new(&[("a.txt", b"aaaa"), ("b.txt", b"bbbb")], 4);
+        backup.remove_chunk(b"bbbb");
+        let filesystem = backup.open();
+        let nodes = filesystem.real_nodes().collect::<Vec<_>>();
+        assert!(!nodes[0].is_incomplete());
+        assert!(nodes[1].is_incomplete());
+
+        // Extraction is refused before anything is written.
+        assert!(check_complete(&nodes).is_err());
+        assert!(check_complete(&nodes[..1]).is_ok());
+
+        // Reading the incomplete file is an error, rather than a panic.
+        let mut data = vec![];
+        assert!(filesystem.read_node(nodes[1], 0, None, &mut data).is_err());
+    }
+}
@@ -0,0 +1,79 @@
+use anyhow::{anyhow, Context};
+use byte_unit::{Byte, UnitType};
+use globset::GlobBuilder;
+use tracing::warn;
+
+use super::{manifest_path, mount::is_dir, Backup};
+use crate::{cli::FindBackup, formats::manifest::ManifestSource};
+
+impl FindBackup {
+    pub(crate) fn run(self) -> anyhow::Result<()> {
+        let backup = Backup::open(&self.path, &[])?;
+        let depot_keys = self.depot_keys.load()?;
+
+        // Patterns without a separator match the file's name in any directory, like
+        // `find -name`.
+        let match_name = !self.pattern.contains('/');
+        let glob = GlobBuilder::new(&self.pattern)
+            .literal_separator(true)
+            .case_insensitive(self.ignore_case)
+            .build()?
+            .compile_matcher();
+
+        let manifests = ManifestSource::open(&self.manifest_dir)?;
+
+        let mut found = 0;
+        for (depot, manifest_id) in &backup.sku.manifests {
+            let mut manifest = manifests.read(*depot, *manifest_id)?;
+            if let Some(depot_key) = depot_keys.get(depot) {
+                manifest.decrypt_filenames(depot_key).with_context(|| {
+                    format!("Failed to decrypt filenames of manifest {manifest_id}")
+                })?;
+            } else if manifest.metadata.filenames_encrypted() {
+                warn!(
+                    "The filenames in manifest {manifest_id} are encrypted, but no key was \
+                    provided for depot {depot}; skipping it",
+                );
+                continue;
+            }
+
+            for file_mapping in &manifest.payload.mappings {
+                let path = manifest_path(file_mapping.filename());
+                let candidate = if match_name {
+                    path.file_name().unwrap_or_default().to_string_lossy()
+                } else {
+                    file_mapping.filename().replace('\\', "/").into()
+                };
+                if !glob.is_match(candidate.as_ref()) {
+                    continue;
+                }
+                found += 1;
+
+                let size = if is_dir(Some(file_mapping)) {
+                    "dir".into()
+                } else if !file_mapping.linktarget().is_empty() {
+                    "symlink".into()
+                } else {
+                    let size =
+                        Byte::from_u64(file_mapping.size()).get_appropriate_unit(UnitType::Binary);
+                    format!("{size:#.2}")
+                };
+                if file_mapping.linktarget().is_empty() {
+                    println!("{depot:>10}  {size:>10}  {}", path.display());
+                } else {
+                    println!(
+                        "{depot:>10}  {size:>10}  {} -> {}",
+                        path.display(),
+                        file_mapping.linktarget(),
+                    );
+                }
+            }
+        }
+
+        if found == 0 {
+            Err(anyhow!("No files match {}", self.pattern))
+        } else {
+            Ok(())
+        }
+    }
+}
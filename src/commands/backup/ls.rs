@@ -0,0 +1,64 @@
+use std::path::Path;
+
+use anyhow::Context;
+use byte_unit::{Byte, UnitType};
+use tokio::runtime::Builder;
+
+use super::{
+    mount::{is_dir, BackupFs},
+    Backup, SEQUENTIAL_CACHE_SIZE,
+};
+use crate::{cli::ListBackup, formats::manifest::ManifestSource};
+
+impl ListBackup {
+    pub(crate) fn run(self) -> anyhow::Result<()> {
+        let backup = Backup::open(&self.path, &self.disks)?.require_all_disks()?;
+
+        let depot_keys = self.depot_keys.load()?;
+
+        let filesystem = BackupFs::prepare(
+            backup,
+            &ManifestSource::open(&self.manifest_dir)?,
+            SEQUENTIAL_CACHE_SIZE,
+            &depot_keys,
+            Builder::new_current_thread().build()?,
+        )
+        .context("Failed to prepare filesystem")?;
+
+        let dir = self.dir.unwrap_or_default();
+        let ino = filesystem.lookup_dir(&dir)?;
+        list(&filesystem, ino, Path::new(""), self.recursive);
+
+        Ok(())
+    }
+}
+
+/// Prints the entries of the directory `ino`, prefixing their names with `prefix`.
+fn list(filesystem: &BackupFs, ino: u64, prefix: &Path, recursive: bool) {
+    for (entry_ino, node) in filesystem.dir_entries(ino) {
+        let path = prefix.join(node.name());
+
+        let kind = if is_dir(node.file_mapping()) {
+            "dir"
+        } else if node.link_target().is_some() {
+            "symlink"
+        } else {
+            "file"
+        };
+        let size = if kind == "file" {
+            let size = Byte::from_u64(node.size()).get_appropriate_unit(UnitType::Binary);
+            format!("{size:#.2}")
+        } else {
+            "-".into()
+        };
+
+        match node.link_target() {
+            Some(target) => println!("{kind:7}  {size:>10}  {} -> {target}", path.display()),
+            None => println!("{kind:7}  {size:>10}  {}", path.display()),
+        }
+
+        if recursive && kind == "dir" {
+            list(filesystem, entry_ino, &path, recursive);
+        }
+    }
+}
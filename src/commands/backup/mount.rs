@@ -1,70 +1,167 @@
 use std::{
-    collections::HashMap,
-    path::{Path, PathBuf},
-    sync::{Arc, RwLock},
+    collections::{BTreeMap, HashMap, HashSet},
+    env,
+    ffi::OsStr,
+    io::{BufRead, BufReader, Write},
+    mem,
+    num::NonZeroUsize,
+    path::{Component, Path, PathBuf},
+    process::{Command, Stdio},
+    sync::{Arc, Mutex},
 };
 
 use anyhow::{anyhow, Context};
+use byte_unit::{Byte, UnitType};
 use futures_util::future;
 use steam_vent::proto::content_manifest::{
-    content_manifest_payload::FileMapping, ContentManifestMetadata,
+    content_manifest_payload::{file_mapping::ChunkData, FileMapping},
+    ContentManifestMetadata,
 };
 use tokio::runtime::{Builder, Runtime};
+use tracing::{debug, error, trace, warn};
 
+use self::cache::ChunkCache;
+use super::{manifest_path, Backup};
 use crate::{
     cli::MountBackup,
-    formats::{csd::ChunkStore, manifest::Manifest, sis::StockKeepingUnit},
+    formats::{
+        csd::ChunkStore,
+        manifest::{Manifest, ManifestSource},
+        sis::StockKeepingUnit,
+    },
 };
 
+mod cache;
 #[cfg(unix)]
 mod fuse;
 #[cfg(windows)]
 mod windows;
 
 impl MountBackup {
-    pub(crate) fn run(self) -> anyhow::Result<()> {
-        let base_dir = {
-            let metadata = self.path.metadata()?;
-            if metadata.is_dir() {
-                Ok(self.path)
-            } else if metadata.is_file() {
-                Ok(self
-                    .path
-                    .parent()
-                    .expect("Files always have parents")
-                    .to_path_buf())
-            } else {
-                Err(anyhow!("Path does not exist"))
-            }?
-        };
+    pub(crate) fn run(self, threads: Option<NonZeroUsize>) -> anyhow::Result<()> {
+        if self.daemon {
+            return spawn_daemon();
+        }
+
+        // Dokan on Windows issues reads from several threads at once, so allow their
+        // chunks to be read in parallel.
+        let mut builder = Builder::new_multi_thread();
+        builder.thread_name("tev-mount");
+        if let Some(threads) = threads {
+            builder
+                .worker_threads(threads.get())
+                .max_blocking_threads(threads.get());
+        }
+        let runtime = builder.build()?;
+
+        let backup = Backup::open(&self.path, &self.disks)?.require_all_disks()?;
+
+        let depot_keys = self.depot_keys.load()?;
+        let manifests = ManifestSource::open(&self.manifest_dir)?;
+
+        if let Some(downloader) = self.download.downloader() {
+            // Use a separate runtime, so the connection to Steam is closed once the
+            // manifests have been downloaded.
+            let downloads = Builder::new_current_thread()
+                .enable_all()
+                .build()?
+                .block_on(downloader.download_missing(
+                    &backup.sku,
+                    &backup.sku.depots,
+                    &manifests,
+                ))?;
+            for download in downloads {
+                let (depot, manifest_id) = (download.depot, download.manifest_id);
+                match download.result {
+                    // Only the mounted signal may go to stdout, as `--daemon` waits for it.
+                    Ok(()) => eprintln!("Downloaded manifest {manifest_id} for depot {depot}"),
+                    Err(e) => {
+                        warn!("Failed to download manifest {manifest_id} for depot {depot}: {e:#}")
+                    }
+                }
+            }
+        }
 
-        let filesystem = BackupFs::prepare(base_dir, self.manifest_dir)
-            .context("Failed to prepare filesystem")?;
+        let filesystem = BackupFs::prepare(
+            backup,
+            &manifests,
+            self.cache_size.as_u64(),
+            &depot_keys,
+            runtime,
+        )
+        .context("Failed to prepare filesystem")?;
 
-        filesystem.mount(self.mountpoint)?;
+        filesystem.mount(self.mountpoint, self.daemon_child)?;
 
         Ok(())
     }
 }
 
-fn is_dir(file_mapping: Option<&FileMapping>) -> bool {
+/// Runs this command again as a background process, and returns once that process has
+/// mounted the backup.
+fn spawn_daemon() -> anyhow::Result<()> {
+    let mut command = Command::new(env::current_exe()?);
+    command
+        .args(env::args_os().skip(1).map(|arg| {
+            if arg == "--daemon" {
+                "--daemon-child".into()
+            } else {
+                arg
+            }
+        }))
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped());
+
+    // Detach the background process from the terminal's Ctrl-C.
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::CommandExt;
+        command.process_group(0);
+    }
+    #[cfg(windows)]
+    {
+        use std::os::windows::process::CommandExt;
+        use winapi::um::winbase::CREATE_NEW_PROCESS_GROUP;
+        command.creation_flags(CREATE_NEW_PROCESS_GROUP);
+    }
+
+    let mut child = command
+        .spawn()
+        .context("Failed to start background process")?;
+
+    // The background process prints a single line once the backup is mounted, and
+    // nothing after that. If it exits before then, it has already printed its error.
+    let mut line = String::new();
+    BufReader::new(child.stdout.take().expect("stdout is piped")).read_line(&mut line)?;
+    if line.is_empty() {
+        let status = child.wait()?;
+        return Err(anyhow!(
+            "Background process exited before mounting ({status})"
+        ));
+    }
+
+    print!("{line}");
+    println!("Running in the background as process {}", child.id());
+
+    Ok(())
+}
+
+pub(super) fn is_dir(file_mapping: Option<&FileMapping>) -> bool {
     if let Some(file_mapping) = file_mapping {
-        if file_mapping.flags() & 0b0100_0000 != 0 {
-            true
-        } else {
-            false
-        }
+        file_mapping.flags() & 0b0100_0000 != 0
     } else {
         // Synthetic nodes are always directories.
         true
     }
 }
 
-enum Node {
+pub(super) enum Node {
     Real {
         metadata: Arc<ContentManifestMetadata>,
         path: PathBuf,
         file_mapping: FileMapping,
+        /// Whether any of this file's chunks are missing from the backup.
+        incomplete: bool,
     },
     Synthetic {
         metadata: Arc<ContentManifestMetadata>,
@@ -73,26 +170,51 @@ enum Node {
 }
 
 impl Node {
-    fn metadata(&self) -> &Arc<ContentManifestMetadata> {
+    pub(super) fn metadata(&self) -> &Arc<ContentManifestMetadata> {
         match self {
             Node::Real { metadata, .. } => metadata,
             Node::Synthetic { metadata, .. } => metadata,
         }
     }
 
-    fn file_mapping(&self) -> Option<&FileMapping> {
+    pub(super) fn file_mapping(&self) -> Option<&FileMapping> {
         match self {
             Node::Real { file_mapping, .. } => Some(file_mapping),
             Node::Synthetic { .. } => None,
         }
     }
 
+    /// Returns `true` if this node is a file that the manifest marks as executable.
+    pub(super) fn is_executable(&self) -> bool {
+        self.file_mapping()
+            .is_some_and(|file_mapping| file_mapping.flags() & 0b1_0000_0000 != 0)
+    }
+
+    /// Returns the target of this node if it is a symlink.
+    ///
+    /// Directories are never treated as symlinks, even if they have a link target.
+    pub(super) fn link_target(&self) -> Option<&str> {
+        self.file_mapping()
+            .filter(|file_mapping| !is_dir(Some(file_mapping)))
+            .map(|file_mapping| file_mapping.linktarget())
+            .filter(|target| !target.is_empty())
+    }
+
+    /// Returns `true` if this node is a file whose contents can't be fully read, because
+    /// some of its chunks are not in any chunkstore.
+    pub(super) fn is_incomplete(&self) -> bool {
+        match self {
+            Node::Real { incomplete, .. } => *incomplete,
+            Node::Synthetic { .. } => false,
+        }
+    }
+
     /// Returns the size of this file in bytes, or 0 for a directory.
-    fn size(&self) -> u64 {
+    pub(super) fn size(&self) -> u64 {
         self.file_mapping().map(|f| f.size()).unwrap_or(0)
     }
 
-    fn path(&self) -> Option<&Path> {
+    pub(super) fn path(&self) -> Option<&Path> {
         // We only need paths for real nodes.
         match self {
             Node::Real { path, .. } => Some(path),
@@ -100,26 +222,26 @@ impl Node {
         }
     }
 
-    fn name(&self) -> &str {
+    pub(super) fn name(&self) -> &OsStr {
         match self {
-            Node::Real { path, .. } => path
-                .file_name()
-                .map(|n| n.to_str().expect("valid str"))
-                .unwrap_or(path.to_str().expect("valid str")),
-            Node::Synthetic { name, .. } => name,
+            Node::Real { path, .. } => path.file_name().unwrap_or(path.as_os_str()),
+            Node::Synthetic { name, .. } => OsStr::new(name),
         }
     }
 }
 
 const ROOT_INODE: u64 = 1;
 
-struct BackupFs {
+pub(super) struct BackupFs {
     sku: StockKeepingUnit,
     runtime: Runtime,
-    chunks: HashMap<[u8; 20], Arc<RwLock<ChunkStore>>>,
-    /// The filesystem's inodes, excluding the root.
+    chunks: HashMap<[u8; 20], Arc<ChunkStore>>,
+    /// Recently-read chunks, shared across all reads.
+    cache: Mutex<ChunkCache>,
+    /// The filesystem's inodes, excluding the root, sorted by path.
     ///
-    /// The inode of a node in this vec is `pos + 2`.
+    /// The inode of a node in this vec is `pos + 2`. Inodes therefore only depend on
+    /// the set of paths in the backup, and are the same every time it is mounted.
     inodes: Vec<Node>,
     /// A map from directory inodes to their contents.
     dir_map: HashMap<u64, Vec<u64>>,
@@ -130,42 +252,59 @@ struct BackupFs {
 }
 
 impl BackupFs {
-    fn prepare(base_dir: PathBuf, manifest_dir: PathBuf) -> anyhow::Result<Self> {
-        let sku = StockKeepingUnit::read(&base_dir.join("sku.sis"))
-            .with_context(|| format!("Cannot find sku.sis in {}", base_dir.display()))?;
+    /// Reads the backup's manifests and opens its chunkstores.
+    ///
+    /// Chunks are read from the chunkstores on `runtime`. `BackupFs` only needs shared
+    /// access to read them, so reads can be made from several threads at once.
+    pub(super) fn prepare(
+        backup: Backup,
+        manifest_source: &ManifestSource,
+        cache_size: u64,
+        depot_keys: &HashMap<u32, [u8; 32]>,
+        runtime: Runtime,
+    ) -> anyhow::Result<Self> {
+        let Backup { sku, dirs, .. } = backup;
 
         // Read all of the manifests into memory.
         let manifests = sku
             .manifests
             .iter()
-            .map(|(depot, manifest)| {
-                let manifest_path = manifest_dir.join(format!("{}_{}.manifest", depot, manifest));
-                let manifest = Manifest::open(&manifest_path).with_context(|| {
-                    format!(
-                        "Cannot find manifest {manifest} for depot {depot} in {}",
-                        manifest_dir.display()
-                    )
-                })?;
+            .map(|(depot, manifest_id)| {
+                let mut manifest = manifest_source.read(*depot, *manifest_id)?;
                 if manifest.metadata.depot_id() == *depot {
+                    if let Some(depot_key) = depot_keys.get(depot) {
+                        manifest.decrypt_filenames(depot_key).with_context(|| {
+                            format!("Failed to decrypt filenames of manifest {manifest_id}")
+                        })?;
+                    } else if manifest.metadata.filenames_encrypted() {
+                        warn!(
+                            "The filenames in manifest {manifest_id} are encrypted, but no \
+                            key was provided for depot {depot}; its files will have encrypted \
+                            names",
+                        );
+                    }
                     Ok(manifest)
                 } else {
                     Err(anyhow!(
-                        "{} does not belong to depot {depot}",
-                        manifest_path.display()
+                        "Manifest {manifest_id} in {} does not belong to depot {depot}",
+                        manifest_source.path().display()
                     ))
                 }
             })
             .collect::<anyhow::Result<Vec<_>>>()?;
 
-        let runtime = Builder::new_current_thread().build()?;
-
         // Open all of the chunkstores.
         let chunkstores = runtime
             .block_on(future::join_all(sku.chunkstores.iter().flat_map(
                 |(depot, chunkstores)| {
-                    let base_dir = &base_dir;
+                    let dirs = &dirs;
                     chunkstores.keys().map(move |chunkstore_index| {
-                        ChunkStore::open(base_dir, *depot, *chunkstore_index)
+                        ChunkStore::open(
+                            dirs,
+                            *depot,
+                            *chunkstore_index,
+                            depot_keys.get(depot).copied(),
+                        )
                     })
                 },
             )))
@@ -181,12 +320,29 @@ impl BackupFs {
                 .map(|(sha, _)| *sha)
                 .collect::<Vec<_>>();
 
-            let chunkstore = Arc::new(RwLock::new(chunkstore));
+            let chunkstore = Arc::new(chunkstore);
             for sha in chunk_shas {
                 chunks.insert(sha, chunkstore.clone());
             }
         }
 
+        // Find the files that can't be fully read because their chunks are missing.
+        let available = chunks.keys().copied().collect::<HashSet<_>>();
+        let mut incomplete = HashSet::new();
+        for manifest in &manifests {
+            let depot = manifest.metadata.depot_id();
+            let missing = manifest.verify_chunks_present(&available);
+            if !missing.is_empty() {
+                warn!(
+                    "Depot {depot} is missing {} chunks, so {} of its files cannot be fully \
+                    read",
+                    missing.chunks.len(),
+                    missing.files.len(),
+                );
+            }
+            incomplete.extend(missing.files.into_iter().map(|file| (depot, file)));
+        }
+
         // Assign inodes for each file in the backup.
         let mut inodes = manifests
             .into_iter()
@@ -196,103 +352,102 @@ impl BackupFs {
                 } = manifest;
 
                 let metadata = Arc::new(metadata);
-
-                payload.mappings.into_iter().map(move |mut file_mapping| {
-                    // Convert file names into platform paths.
-                    let filename = file_mapping.take_filename();
-                    let path = if filename.contains('/') {
-                        filename.split('/').collect()
-                    } else {
-                        filename.split('\\').collect()
-                    };
-
-                    Node::Real {
-                        metadata: metadata.clone(),
-                        path,
-                        file_mapping,
-                    }
-                })
+                let incomplete = &incomplete;
+
+                payload
+                    .mappings
+                    .into_iter()
+                    .filter_map(move |mut file_mapping| {
+                        // Convert file names into platform paths.
+                        let filename = file_mapping.take_filename();
+                        let incomplete =
+                            incomplete.contains(&(metadata.depot_id(), filename.clone()));
+                        let path = manifest_path(&filename);
+                        if path.as_os_str().is_empty() {
+                            warn!(
+                                "Skipping file {filename:?} in depot {}, as it has no name \
+                                within the depot",
+                                metadata.depot_id(),
+                            );
+                            return None;
+                        }
+
+                        Some(Node::Real {
+                            metadata: metadata.clone(),
+                            path,
+                            file_mapping,
+                            incomplete,
+                        })
+                    })
             })
             .collect::<Vec<_>>();
 
-        // Remove any duplicate directories (which can occur across multiple depots).
+        // Sort the files by path first, so that conflicting files are renamed in a
+        // consistent order.
         inodes.sort_by_key(|node| node.path().expect("all real nodes").to_path_buf());
-        inodes.dedup_by(|a, b| a.path() == b.path());
+        let deduped = dedupe_nodes(inodes);
 
-        // Generate a map from paths to inodes.
-        let mut path_map = inodes
+        // Add synthetic nodes for parent directories that aren't listed in any manifest,
+        // taking their metadata from the first file within them.
+        let real_paths = deduped
             .iter()
-            .zip(0u64..)
-            .map(|(node, index)| {
-                let path = node
-                    .path()
-                    .expect("inodes currently only contains real nodes");
-                (path.to_path_buf(), index + 2)
-            })
+            .map(|node| node.path().expect("all real nodes"))
+            .collect::<HashSet<_>>();
+        let mut synthetic = BTreeMap::new();
+        for node in &deduped {
+            let path = node.path().expect("all real nodes");
+            for parent in path.ancestors().skip(1) {
+                if parent.as_os_str().is_empty() || real_paths.contains(parent) {
+                    break;
+                }
+                synthetic
+                    .entry(parent.to_path_buf())
+                    .or_insert_with(|| node.metadata().clone());
+            }
+        }
+        let mut nodes = deduped
+            .into_iter()
+            .map(|node| (node.path().expect("all real nodes").to_path_buf(), node))
+            .chain(synthetic.into_iter().map(|(path, metadata)| {
+                let name = path
+                    .file_name()
+                    .expect("not empty")
+                    .to_string_lossy()
+                    .into_owned();
+                (path, Node::Synthetic { metadata, name })
+            }))
+            .collect::<Vec<_>>();
+
+        // Assign inodes in order of path, so that they don't depend on the order of the
+        // manifests or of the files within them. Each path is unique, so each inode is
+        // too.
+        nodes.sort_by(|(a, _), (b, _)| a.cmp(b));
+        let mut path_map = nodes
+            .iter()
+            .zip(ROOT_INODE + 1..)
+            .map(|((path, _), ino)| (path.clone(), ino))
             .collect::<HashMap<_, _>>();
-        // Add the root inode to the map.
-        path_map.insert(PathBuf::new(), 1);
+        path_map.insert(PathBuf::new(), ROOT_INODE);
 
-        // Precompute a directory map from parents to children, adding synthetic inodes as
-        // necessary.
+        // Precompute a directory map from parents to children.
         let mut dir_map = HashMap::<_, Vec<_>>::new();
-        for index in 0..inodes.len() {
-            let node = inodes.get(index).expect("present by construction");
-            let metadata = node.metadata().clone();
-
-            let mut ino = (index as u64) + 2;
-            let mut parent_path = node
-                .path()
-                .expect("real by construction")
-                .parent()
-                .expect("not a root by construction")
-                .to_path_buf();
-
-            loop {
-                match path_map.get(&parent_path) {
-                    Some(parent_ino) => {
-                        dir_map.entry(*parent_ino).or_default().push(ino);
-                        break;
-                    }
-                    None => {
-                        let parent_ino = (inodes.len() as u64) + 2;
-                        let name = parent_path
-                            .file_name()
-                            .expect("not empty")
-                            .to_string_lossy()
-                            .into_owned();
-
-                        // We're creating a new node as a parent, so we need to loop and
-                        // find its grandparent.
-                        let mut path = parent_path
-                            .parent()
-                            .expect("not root by construction")
-                            .to_path_buf();
-                        std::mem::swap(&mut parent_path, &mut path);
-
-                        path_map.insert(path, parent_ino);
-                        inodes.push(Node::Synthetic {
-                            metadata: metadata.clone(),
-                            name,
-                        });
-                        dir_map.entry(parent_ino).or_default().push(ino);
-
-                        ino = parent_ino;
-                    }
-                }
-            }
+        for ((path, _), ino) in nodes.iter().zip(ROOT_INODE + 1..) {
+            let parent = path.parent().expect("not a root by construction");
+            dir_map.entry(path_map[parent]).or_default().push(ino);
         }
+        let inodes = nodes.into_iter().map(|(_, node)| node).collect::<Vec<_>>();
 
         #[cfg(unix)]
-        let fuse_info = fuse::FsInfo::prepare(&inodes);
+        let fuse_info = fuse::FsInfo::prepare(&inodes, &dir_map);
 
         #[cfg(windows)]
-        let windows_info = windows::FsInfo::prepare(path_map);
+        let windows_info = windows::FsInfo::prepare(&inodes, path_map)?;
 
         Ok(Self {
             sku,
             runtime,
             chunks,
+            cache: Mutex::new(ChunkCache::new(cache_size)),
             inodes,
             dir_map,
             #[cfg(unix)]
@@ -303,6 +458,240 @@ impl BackupFs {
     }
 }
 
+impl BackupFs {
+    /// Logs how effective the chunk cache was, and the most memory that was used for
+    /// decompressed chunks.
+    pub(super) fn log_cache_stats(&self) {
+        let cache = self.cache.lock().unwrap();
+        let (hits, misses) = cache.hits_and_misses();
+        debug!("Chunk cache: {hits} hits, {misses} misses");
+        debug!(
+            "Peak memory used for chunks: {:#.2}",
+            Byte::from_u64(cache.peak_size()).get_appropriate_unit(UnitType::Binary),
+        );
+    }
+
+    /// Looks up the inode for the given path within the backup.
+    pub(super) fn lookup_path(&self, path: &Path) -> Option<u64> {
+        let mut ino = ROOT_INODE;
+        for component in path.components() {
+            match component {
+                Component::Normal(name) => {
+                    ino = *self.dir_map.get(&ino)?.iter().find(|&&entry_ino| {
+                        get_node(&self.inodes, entry_ino).is_some_and(|node| node.name() == name)
+                    })?;
+                }
+                Component::RootDir | Component::CurDir => (),
+                Component::Prefix(_) | Component::ParentDir => return None,
+            }
+        }
+        Some(ino)
+    }
+
+    /// Looks up the inode for the directory at the given path within the backup.
+    pub(super) fn lookup_dir(&self, path: &Path) -> anyhow::Result<u64> {
+        match self.lookup_path(path) {
+            Some(ROOT_INODE) => Ok(ROOT_INODE),
+            Some(ino) => get_node(&self.inodes, ino)
+                .filter(|node| is_dir(node.file_mapping()))
+                .map(|_| ino)
+                .ok_or_else(|| anyhow!("{} is not a directory", path.display())),
+            None => Err(anyhow!("{} does not exist in the backup", path.display())),
+        }
+    }
+
+    /// Returns the inodes and nodes of the entries in the given directory, sorted by
+    /// name.
+    pub(super) fn dir_entries(&self, ino: u64) -> Vec<(u64, &Node)> {
+        let mut entries = self
+            .dir_map
+            .get(&ino)
+            .into_iter()
+            .flatten()
+            .map(|&entry_ino| {
+                (
+                    entry_ino,
+                    get_node(&self.inodes, entry_ino).expect("valid by construction"),
+                )
+            })
+            .collect::<Vec<_>>();
+        entries.sort_by(|(_, a), (_, b)| a.name().cmp(b.name()));
+        entries
+    }
+
+    /// Writes up to `length` bytes of the file at `path` to `writer`, starting at
+    /// `offset`.
+    ///
+    /// Only the chunks overlapping the requested range are read and decompressed.
+    ///
+    /// Returns the number of bytes written.
+    pub(super) fn read_range<W: Write>(
+        &self,
+        path: &Path,
+        offset: u64,
+        length: Option<u64>,
+        writer: W,
+    ) -> anyhow::Result<u64> {
+        let node = match self.lookup_path(path) {
+            Some(ROOT_INODE) => None,
+            Some(ino) => get_node(&self.inodes, ino),
+            None => return Err(anyhow!("{} does not exist in the backup", path.display())),
+        }
+        .filter(|node| !is_dir(node.file_mapping()))
+        .ok_or_else(|| anyhow!("{} is a directory", path.display()))?;
+
+        self.read_node(node, offset, length, writer)
+    }
+
+    /// Returns the nodes for every file and directory listed in the backup's manifests.
+    ///
+    /// Parent directories that are implied by a file's path, but not listed in any
+    /// manifest, are not included.
+    pub(super) fn real_nodes(&self) -> impl Iterator<Item = &Node> {
+        self.inodes
+            .iter()
+            .filter(|node| matches!(node, Node::Real { .. }))
+    }
+
+    /// Writes up to `length` bytes of the file at `node` to `writer`, starting at
+    /// `offset`.
+    pub(super) fn read_node<W: Write>(
+        &self,
+        node: &Node,
+        offset: u64,
+        length: Option<u64>,
+        mut writer: W,
+    ) -> anyhow::Result<u64> {
+        let path = node.path().unwrap_or(Path::new(""));
+
+        let end = length
+            .map_or(node.size(), |length| offset.saturating_add(length))
+            .min(node.size());
+        if offset > end {
+            return Err(anyhow!(
+                "Offset {offset} is past the end of {} ({} bytes)",
+                path.display(),
+                node.size(),
+            ));
+        }
+
+        // Read one chunk's worth at a time.
+        let mut buf = vec![
+            0;
+            usize::try_from(end - offset)
+                .unwrap_or(usize::MAX)
+                .min(1024 * 1024)
+        ];
+        let mut position = offset;
+        while position < end {
+            let to_read = usize::try_from(end - position)
+                .unwrap_or(usize::MAX)
+                .min(buf.len());
+            let read = read_data(
+                &self.runtime,
+                &self.chunks,
+                &self.cache,
+                node,
+                position,
+                &mut buf[..to_read],
+            )
+            .map_err(|_| anyhow!("Failed to read {}", path.display()))?;
+            writer.write_all(&buf[..read as usize])?;
+            position += read;
+        }
+
+        Ok(position - offset)
+    }
+}
+
+/// Removes duplicate directories and identical files (which can occur across multiple
+/// depots) from the given real nodes, and renames differing files that share a path
+/// with another file or directory, so that every remaining node has a unique path.
+///
+/// Where entries share a path, the first one is kept at that path (unless a later one
+/// is a directory, which is kept so that its contents can be reached).
+fn dedupe_nodes(nodes: Vec<Node>) -> Vec<Node> {
+    let mut conflicts = vec![];
+    let mut deduped: Vec<Node> = Vec::with_capacity(nodes.len());
+    let mut by_path = HashMap::new();
+    for node in nodes {
+        let path = node.path().expect("all real nodes").to_path_buf();
+        let Some(&i) = by_path.get(&path) else {
+            by_path.insert(path, deduped.len());
+            deduped.push(node);
+            continue;
+        };
+
+        let prev = &mut deduped[i];
+        let prev_is_dir = is_dir(prev.file_mapping());
+        let node_is_dir = is_dir(node.file_mapping());
+        if prev_is_dir && node_is_dir || prev.file_mapping() == node.file_mapping() {
+            continue;
+        }
+
+        // A directory keeps its path, so that its contents can be reached, and the file
+        // that it conflicts with is renamed.
+        if node_is_dir {
+            conflicts.push(mem::replace(prev, node));
+        } else {
+            conflicts.push(node);
+        }
+    }
+
+    // Files can also share a path with a directory that is only implied by the paths
+    // within it.
+    let parents = deduped
+        .iter()
+        .flat_map(|node| node.path().expect("all real nodes").ancestors().skip(1))
+        .map(Path::to_path_buf)
+        .collect::<HashSet<_>>();
+    let (implied_dir_conflicts, mut deduped): (Vec<_>, Vec<_>) =
+        deduped.into_iter().partition(|node| {
+            !is_dir(node.file_mapping()) && parents.contains(node.path().expect("all real nodes"))
+        });
+    conflicts.extend(implied_dir_conflicts);
+
+    // Keep differing files that share a path with another file or directory by
+    // renaming them to a path that isn't taken.
+    let mut taken = deduped
+        .iter()
+        .map(|node| node.path().expect("all real nodes").to_path_buf())
+        .chain(parents)
+        .collect::<HashSet<_>>();
+    for mut node in conflicts {
+        let depot = node.metadata().depot_id();
+        let Node::Real { path, .. } = &mut node else {
+            unreachable!("nodes only contains real nodes");
+        };
+
+        let name = path.file_name().expect("not empty").to_os_string();
+        let renamed = (1..)
+            .map(|n| {
+                let mut name = name.clone();
+                if n == 1 {
+                    name.push(format!(" (depot {depot})"));
+                } else {
+                    name.push(format!(" (depot {depot}, {n})"));
+                }
+                path.with_file_name(name)
+            })
+            .find(|renamed| !taken.contains(renamed))
+            .expect("some name is free");
+        warn!(
+            "Depots contain different entries at {}; the file in depot {depot} is available \
+            as {}",
+            path.display(),
+            renamed.display(),
+        );
+        taken.insert(renamed.clone());
+        *path = renamed;
+        deduped.push(node);
+    }
+
+    deduped.sort_by_key(|node| node.path().expect("all real nodes").to_path_buf());
+    deduped
+}
+
 fn get_node(inodes: &[Node], ino: u64) -> Option<&Node> {
     if let Some(index) = ino.checked_sub(ROOT_INODE + 1) {
         inodes.get(index as usize)
@@ -313,17 +702,14 @@ fn get_node(inodes: &[Node], ino: u64) -> Option<&Node> {
 
 fn read_data(
     runtime: &Runtime,
-    chunks: &HashMap<[u8; 20], Arc<RwLock<ChunkStore>>>,
+    chunks: &HashMap<[u8; 20], Arc<ChunkStore>>,
+    cache: &Mutex<ChunkCache>,
     node: &Node,
     offset: u64,
     buf: &mut [u8],
 ) -> Result<u64, ReadError> {
     let file_size = node.size();
 
-    if offset > file_size {
-        return Err(ReadError::InvalidParameter);
-    }
-
     let file_mapping = match node.file_mapping() {
         Some(f) => f,
         None => {
@@ -332,46 +718,204 @@ fn read_data(
         }
     };
 
-    // If we have nothing to read, no need to access the chunkstores.
-    let to_read = u64::min(buf.len() as u64, file_size - offset);
+    trace!(
+        offset,
+        len = buf.len(),
+        "Reading {}",
+        node.path().unwrap_or(Path::new("")).display(),
+    );
+
+    // If we have nothing to read (such as for an empty file, or a read at or past the
+    // end of the file), no need to access the chunkstores.
+    let to_read = u64::min(buf.len() as u64, file_size.saturating_sub(offset));
     if to_read == 0 {
         return Ok(0);
     }
 
+    // Any bytes that aren't covered by a chunk are zeros.
+    let buf = &mut buf[..usize::try_from(to_read).unwrap()];
+    buf.fill(0);
+
     // Find the relevant chunks.
-    for chunk in &file_mapping.chunks {
-        // Determine how the buffer and chunk overlap.
-        let read_start = offset;
-        let read_end = offset + to_read;
+    let read_start = offset;
+    for chunk in chunks_for_file(file_mapping, offset, to_read) {
         let chunk_start = chunk.offset();
-        let chunk_end = chunk.offset() + u64::from(chunk.cb_original());
-
-        if read_start < chunk_end && chunk_start < read_end {
-            // This chunk contains requested data.
-            let sha = chunk.sha().try_into().unwrap();
-            let chunkstore = chunks.get(&sha).expect("correct by construction");
-            let mut chunkstore = chunkstore.write().unwrap();
-            match runtime.block_on(chunkstore.chunk_data(sha)) {
-                Ok(chunk_data) => {
-                    let buf = &mut buf
-                        [usize::try_from(chunk_start.saturating_sub(read_start)).unwrap()..];
-                    let chunk_data = &chunk_data
-                        [usize::try_from(read_start.saturating_sub(chunk_start)).unwrap()..];
-                    let chunk_read = usize::min(buf.len(), chunk_data.len());
-
-                    buf[..chunk_read].copy_from_slice(&chunk_data[..chunk_read]);
-                }
-                Err(_) => {
+
+        // This chunk contains requested data.
+        let sha = chunk.sha().try_into().unwrap();
+        let cached = cache.lock().unwrap().get(&sha);
+        let chunk_data = match cached {
+            Some(chunk_data) => {
+                trace!("Chunk {} is cached", hex::encode(sha));
+                chunk_data
+            }
+            None => {
+                debug!("Chunk {} is not cached, decompressing it", hex::encode(sha));
+                let Some(chunkstore) = chunks.get(&sha) else {
+                    error!("Chunk {} is not in any chunkstore", hex::encode(sha));
                     return Err(ReadError::Io);
-                }
-            };
-        }
+                };
+                let buffer = cache.lock().unwrap().take_buffer();
+                let chunk_data = Arc::new(
+                    runtime
+                        .block_on(chunkstore.chunk_data_in(sha, buffer))
+                        .map_err(|e| {
+                            error!("Failed to read chunk {}: {e:#}", hex::encode(sha));
+                            ReadError::Io
+                        })?,
+                );
+                cache.lock().unwrap().insert(sha, chunk_data.clone());
+                chunk_data
+            }
+        };
+
+        let buf = &mut buf[usize::try_from(chunk_start.saturating_sub(read_start)).unwrap()..];
+        let chunk_data =
+            &chunk_data[usize::try_from(read_start.saturating_sub(chunk_start)).unwrap()..];
+        let chunk_read = usize::min(buf.len(), chunk_data.len());
+
+        buf[..chunk_read].copy_from_slice(&chunk_data[..chunk_read]);
     }
 
     Ok(to_read)
 }
 
+/// Returns the chunks of the given file that overlap the byte range
+/// `[offset, offset + len)`.
+fn chunks_for_file(
+    file_mapping: &FileMapping,
+    offset: u64,
+    len: u64,
+) -> impl Iterator<Item = &ChunkData> {
+    let end = offset + len;
+    file_mapping.chunks.iter().filter(move |chunk| {
+        let chunk_start = chunk.offset();
+        let chunk_end = chunk_start + u64::from(chunk.cb_original());
+        offset < chunk_end && chunk_start < end
+    })
+}
+
 enum ReadError {
     InvalidParameter,
     Io,
 }
+
+#[cfg(test)]
+mod tests {
+    use std::path::{Path, PathBuf};
+    use std::sync::Arc;
+
+    use steam_vent::proto::content_manifest::{
+        content_manifest_payload::FileMapping, ContentManifestMetadata,
+    };
+
+    use super::{dedupe_nodes, Node, ROOT_INODE};
+    use crate::commands::backup::testing::TestBackup;
+
+    fn file(depot: u32, path: &str, contents: &[u8]) -> Node {
+        let mut metadata = ContentManifestMetadata::new();
+        metadata.set_depot_id(depot);
+        let mut file_mapping = FileMapping::new();
+        file_mapping.set_size(contents.len() as u64);
+        file_mapping.set_flags(0);
+        file_mapping.set_sha_content(contents.to_vec());
+        Node::Real {
+            metadata: Arc::new(metadata),
+            path: path.into(),
+            file_mapping,
+            incomplete: false,
+        }
+    }
+
+    #[test]
+    fn conflicting_files_get_unique_paths() {
+        let nodes = dedupe_nodes(vec![
+            file(1, "file", b"one"),
+            file(2, "file", b"two"),
+            // Already has the name that depot 2's file would be renamed to.
+            file(1, "file (depot 2)", b"three"),
+            // Identical to depot 1's file, so it is dropped even though another file
+            // at the same path came between them.
+            file(3, "file", b"one"),
+        ]);
+
+        let paths = nodes
+            .iter()
+            .map(|node| {
+                (
+                    node.metadata().depot_id(),
+                    node.path().unwrap().to_path_buf(),
+                )
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            paths,
+            [
+                (1, PathBuf::from("file")),
+                (1, PathBuf::from("file (depot 2)")),
+                (2, PathBuf::from("file (depot 2, 2)")),
+            ],
+        );
+    }
+
+    #[test]
+    fn mixed_separators() {
+        let backup = TestBackup::new(&[("dir\\sub/file", b"contents")], 4);
+        let filesystem = backup.open();
+        let names = |ino| {
+            filesystem
+                .dir_entries(ino)
+                .into_iter()
+                .map(|(_, node)| node.name().to_string_lossy().into_owned())
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(names(ROOT_INODE), ["dir"]);
+        let dir = filesystem.lookup_dir(Path::new("dir")).unwrap();
+        assert_eq!(names(dir), ["sub"]);
+        let sub = filesystem
+            .lookup_dir(&Path::new("dir").join("sub"))
+            .unwrap();
+        assert_eq!(names(sub), ["file"]);
+
+        let mut data = vec![];
+        filesystem
+            .read_range(
+                &Path::new("dir").join("sub").join("file"),
+                0,
+                None,
+                &mut data,
+            )
+            .unwrap();
+        assert_eq!(data, b"contents");
+    }
+
+    #[test]
+    fn ranged_read_only_fetches_overlapping_chunks() {
+        let contents = (0..40).collect::<Vec<u8>>();
+        // Ten 4-byte chunks.
+        let backup = TestBackup::new(&[("file.bin", &contents)], 4);
+        let filesystem = backup.open();
+        let node = filesystem.real_nodes().next().unwrap();
+        assert_eq!(node.file_mapping().unwrap().chunks.len(), 10);
+
+        // Bytes 9 to 14 are in the third and fourth chunks.
+        let mut data = vec![];
+        let read = filesystem.read_node(node, 9, Some(6), &mut data).unwrap();
+        assert_eq!(read, 6);
+        assert_eq!(data, &contents[9..15]);
+        assert_eq!(filesystem.cache.lock().unwrap().hits_and_misses(), (0, 2));
+
+        // A read within a single chunk fetches only that chunk, which is now cached.
+        let mut data = vec![];
+        filesystem.read_node(node, 13, Some(2), &mut data).unwrap();
+        assert_eq!(data, &contents[13..15]);
+        assert_eq!(filesystem.cache.lock().unwrap().hits_and_misses(), (1, 2));
+
+        // Reads past the end of the file fetch nothing.
+        let mut data = vec![];
+        filesystem.read_node(node, 40, Some(10), &mut data).unwrap();
+        assert!(data.is_empty());
+        assert_eq!(filesystem.cache.lock().unwrap().hits_and_misses(), (1, 2));
+    }
+}
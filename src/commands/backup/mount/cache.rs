@@ -0,0 +1,94 @@
+use std::sync::Arc;
+
+use lru::LruCache;
+
+/// The maximum number of evicted chunk buffers to keep for reuse.
+const MAX_SPARE_BUFFERS: usize = 8;
+
+/// A cache of decompressed chunks, bounded by the total size of the cached chunks.
+///
+/// The buffers of evicted chunks are recycled for decompressing new chunks.
+pub(super) struct ChunkCache {
+    chunks: LruCache<[u8; 20], Arc<Vec<u8>>>,
+    size: u64,
+    capacity: u64,
+    spare_buffers: Vec<Vec<u8>>,
+    /// The most memory that cached chunks and spare buffers have used at once.
+    peak_size: u64,
+    /// The number of lookups that found their chunk in the cache.
+    hits: u64,
+    /// The number of lookups that did not find their chunk in the cache.
+    misses: u64,
+}
+
+impl ChunkCache {
+    /// Creates a cache that will hold at most `capacity` bytes of chunk data.
+    pub(super) fn new(capacity: u64) -> Self {
+        Self {
+            chunks: LruCache::unbounded(),
+            size: 0,
+            capacity,
+            spare_buffers: vec![],
+            peak_size: 0,
+            hits: 0,
+            misses: 0,
+        }
+    }
+
+    /// Returns a buffer to decompress a chunk into, reusing an evicted chunk's buffer
+    /// if one is available.
+    pub(super) fn take_buffer(&mut self) -> Vec<u8> {
+        self.spare_buffers.pop().unwrap_or_default()
+    }
+
+    pub(super) fn peak_size(&self) -> u64 {
+        self.peak_size
+    }
+
+    /// Returns the number of lookups that have hit and missed the cache.
+    pub(super) fn hits_and_misses(&self) -> (u64, u64) {
+        (self.hits, self.misses)
+    }
+
+    pub(super) fn get(&mut self, sha: &[u8; 20]) -> Option<Arc<Vec<u8>>> {
+        let chunk = self.chunks.get(sha).cloned();
+        if chunk.is_some() {
+            self.hits += 1;
+        } else {
+            self.misses += 1;
+        }
+        chunk
+    }
+
+    pub(super) fn insert(&mut self, sha: [u8; 20], data: Arc<Vec<u8>>) {
+        let len = data.len() as u64;
+        if len > self.capacity {
+            return;
+        }
+
+        if let Some(old) = self.chunks.put(sha, data) {
+            self.size -= old.len() as u64;
+        }
+        self.size += len;
+
+        // Evict the least-recently-used chunks until we are within capacity.
+        while self.size > self.capacity {
+            let (_, evicted) = self.chunks.pop_lru().expect("size is non-zero");
+            self.size -= evicted.len() as u64;
+
+            // Keep the buffer unless a read is still using it.
+            if self.spare_buffers.len() < MAX_SPARE_BUFFERS {
+                if let Ok(buffer) = Arc::try_unwrap(evicted) {
+                    self.spare_buffers.push(buffer);
+                }
+            }
+        }
+
+        let spare_size = self
+            .spare_buffers
+            .iter()
+            .map(|buffer| buffer.capacity() as u64)
+            .sum::<u64>();
+        self.peak_size = self.peak_size.max(self.size + spare_size);
+    }
+}
@@ -1,25 +1,19 @@
 use std::collections::HashMap;
+use std::ffi::OsStr;
+use std::io;
 use std::path::PathBuf;
 use std::sync::mpsc;
+use std::thread::{self, JoinHandle};
 use std::time::Duration;
 use std::time::UNIX_EPOCH;
 
-use anyhow::Context;
+use anyhow::{anyhow, Context};
 use fuser::{FileAttr, FileType, Filesystem, MountOption};
-use steam_vent::proto::content_manifest::content_manifest_payload::FileMapping;
 
 use super::{get_node, is_dir, read_data, BackupFs, Node, ReadError, ROOT_INODE};
 
 const TTL: &Duration = &Duration::from_secs(10);
 
-fn steam_to_filetype(file_mapping: Option<&FileMapping>) -> FileType {
-    if is_dir(file_mapping) {
-        FileType::Directory
-    } else {
-        FileType::RegularFile
-    }
-}
-
 impl Node {
     /// Returns the size of this file in bytes and "blocks".
     fn blocks(&self) -> u64 {
@@ -27,7 +21,37 @@ impl Node {
     }
 
     fn kind(&self) -> FileType {
-        steam_to_filetype(self.file_mapping())
+        if is_dir(self.file_mapping()) {
+            FileType::Directory
+        } else if self.link_target().is_some() {
+            FileType::Symlink
+        } else {
+            FileType::RegularFile
+        }
+    }
+
+    /// Returns the extended attributes of this node, as `(name, value)` pairs.
+    ///
+    /// - `user.tev.depot`: the ID of the depot containing this node.
+    /// - `user.tev.chunks`: the hex-encoded SHA-1 digests of this file's chunks, in
+    ///   file order and separated by newlines (only for files with chunks).
+    fn xattrs(&self) -> Vec<(&'static str, Vec<u8>)> {
+        let mut xattrs = vec![(
+            "user.tev.depot",
+            self.metadata().depot_id().to_string().into_bytes(),
+        )];
+
+        if let Some(file_mapping) = self.file_mapping().filter(|f| !f.chunks.is_empty()) {
+            let mut chunks = file_mapping.chunks.iter().collect::<Vec<_>>();
+            chunks.sort_by_key(|chunk| chunk.offset());
+            let shas = chunks
+                .into_iter()
+                .map(|chunk| hex::encode(chunk.sha()))
+                .collect::<Vec<_>>();
+            xattrs.push(("user.tev.chunks", shas.join("\n").into_bytes()));
+        }
+
+        xattrs
     }
 
     fn attr(&self, ino: u64) -> FileAttr {
@@ -35,14 +59,20 @@ impl Node {
 
         FileAttr {
             ino,
-            size: self.size(),
+            // The size of a symlink is the length of its target.
+            size: self
+                .link_target()
+                .map_or(self.size(), |target| target.len() as u64),
             blocks: self.blocks(),
             atime: crtime,
             mtime: crtime,
             ctime: crtime,
             crtime,
             kind: self.kind(),
-            perm: 0o0755,
+            perm: match self.kind() {
+                FileType::RegularFile if !self.is_executable() => 0o0644,
+                _ => 0o0755,
+            },
             nlink: 1,
             uid: 1000,
             gid: 1000,
@@ -73,8 +103,28 @@ const ROOT_ATTR: &FileAttr = &FileAttr {
     flags: 0,
 };
 
+/// The error for a missing extended attribute.
+#[cfg(target_os = "linux")]
+const ENOATTR: i32 = libc::ENODATA;
+#[cfg(not(target_os = "linux"))]
+const ENOATTR: i32 = libc::ENOATTR;
+
+/// Replies to a `getxattr` or `listxattr` request with `value`, or with its size if
+/// the caller's buffer `size` is 0.
+fn reply_xattr(reply: fuser::ReplyXattr, size: u32, value: &[u8]) {
+    if size == 0 {
+        reply.size(value.len() as u32);
+    } else if value.len() <= size as usize {
+        reply.data(value);
+    } else {
+        reply.error(libc::ERANGE);
+    }
+}
+
 pub(super) struct FsInfo {
     blocks: u64,
+    /// A map from directory inodes (including empty directories) to their parents.
+    parents: HashMap<u64, u64>,
     /// Open files map to inodes because the backup contents can never change.
     open_files: HashMap<u64, u64>,
     open_dirs: HashMap<u64, u64>,
@@ -84,10 +134,24 @@ pub(super) struct FsInfo {
 }
 
 impl FsInfo {
-    pub(super) fn prepare(inodes: &[Node]) -> Self {
+    pub(super) fn prepare(inodes: &[Node], dir_map: &HashMap<u64, Vec<u64>>) -> Self {
         let blocks = inodes.iter().map(|node| node.blocks()).sum();
+        let mut parents = dir_map
+            .iter()
+            .flat_map(|(&parent, children)| children.iter().map(move |&child| (child, parent)))
+            .filter(|&(child, _)| {
+                is_dir(
+                    get_node(inodes, child)
+                        .expect("valid by construction")
+                        .file_mapping(),
+                )
+            })
+            .collect::<HashMap<_, _>>();
+        // The root is its own parent.
+        parents.insert(ROOT_INODE, ROOT_INODE);
         Self {
             blocks,
+            parents,
             open_files: HashMap::new(),
             open_dirs: HashMap::new(),
             next_file_fh: 0,
@@ -98,15 +162,21 @@ impl FsInfo {
 }
 
 impl BackupFs {
-    pub(super) fn mount(self, mountpoint: PathBuf) -> anyhow::Result<()> {
+    /// Mounts the filesystem, and waits for Ctrl-C to unmount it.
+    ///
+    /// If `background` is set, nothing is printed after the filesystem is mounted, as
+    /// nothing may be reading our output.
+    pub(super) fn mount(self, mountpoint: PathBuf, background: bool) -> anyhow::Result<()> {
         let (tx, rx) = mpsc::channel();
         ctrlc::set_handler(move || tx.send(()).expect("Could not send signal on channel."))
             .context("Error setting Ctrl-C handler")?;
 
         let name = self.sku.name.clone();
 
-        // Mount the filesystem.
-        let fs = fuser::spawn_mount2(
+        // Mount the filesystem, and run its session on a background thread. We run the
+        // session ourselves rather than with `fuser::spawn_mount2`, so that an error
+        // that ends the session is returned instead of panicking when it is joined.
+        let mut session = fuser::Session::new(
             self,
             &mountpoint,
             &[
@@ -115,20 +185,70 @@ impl BackupFs {
                 MountOption::AllowOther,
             ],
         )
-        .context("Runtime")?;
+        .context("Failed to mount filesystem")?;
+        let mut unmounter = session.unmount_callable();
+        let guard = thread::spawn(move || session.run());
 
         println!("Mounted '{name}' at {}", mountpoint.display());
-        println!("Waiting for Ctrl-C...");
-        rx.recv().expect("Could not receive from channel");
+        if !background {
+            println!("Waiting for Ctrl-C...");
+        }
+        loop {
+            match rx.recv_timeout(Duration::from_secs(1)) {
+                Ok(()) => break,
+                Err(mpsc::RecvTimeoutError::Timeout) => {
+                    // The filesystem may have been unmounted by something else (such
+                    // as `fusermount -u`), which ends the session.
+                    if guard.is_finished() {
+                        join_session(guard)?;
+                        if !background {
+                            println!("'{name}' was unmounted");
+                        }
+                        return Ok(());
+                    }
+                }
+                Err(mpsc::RecvTimeoutError::Disconnected) => {
+                    panic!("Could not receive from channel")
+                }
+            }
+        }
+
+        // Unmount the filesystem, and wait for the session to end.
+        if !background {
+            println!("Unmounting '{name}' from {}", mountpoint.display());
+        }
+        unmounter
+            .unmount()
+            .context("Failed to unmount filesystem")?;
+        join_session(guard)
+    }
+}
 
-        // Unmount the filesystem.
-        drop(fs);
+/// Waits for a FUSE session's thread to end, returning any error that ended it.
+fn join_session(guard: JoinHandle<io::Result<()>>) -> anyhow::Result<()> {
+    guard
+        .join()
+        .map_err(|_| anyhow!("FUSE session panicked"))?
+        .context("FUSE session failed")
+}
 
-        Ok(())
+impl BackupFs {
+    /// Returns the extended attributes of the given inode, or `None` if it does not
+    /// exist.
+    fn xattrs(&self, ino: u64) -> Option<Vec<(&'static str, Vec<u8>)>> {
+        if ino == ROOT_INODE {
+            Some(vec![])
+        } else {
+            get_node(&self.inodes, ino).map(|node| node.xattrs())
+        }
     }
 }
 
 impl Filesystem for BackupFs {
+    fn destroy(&mut self) {
+        self.log_cache_stats();
+    }
+
     fn lookup(
         &mut self,
         _req: &fuser::Request<'_>,
@@ -186,6 +306,16 @@ impl Filesystem for BackupFs {
         }
     }
 
+    fn readlink(&mut self, _req: &fuser::Request<'_>, ino: u64, reply: fuser::ReplyData) {
+        match get_node(&self.inodes, ino) {
+            Some(node) => match node.link_target() {
+                Some(target) => reply.data(target.as_bytes()),
+                None => reply.error(libc::EINVAL),
+            },
+            None => reply.error(libc::ENOENT),
+        }
+    }
+
     fn open(&mut self, _req: &fuser::Request<'_>, ino: u64, _flags: i32, reply: fuser::ReplyOpen) {
         let fh = self.fuse_info.next_file_fh;
         self.fuse_info.open_files.insert(fh, ino);
@@ -218,6 +348,7 @@ impl Filesystem for BackupFs {
                 match read_data(
                     &self.runtime,
                     &self.chunks,
+                    &self.cache,
                     node,
                     offset,
                     &mut self.fuse_info.read_buf,
@@ -279,13 +410,32 @@ impl Filesystem for BackupFs {
 
         // The filesystem is immutable, so we don't need to separately cache data for
         // potentially-deleted inodes. Instead just verify the file handle.
-        match (self.dir_map.get(&ino), self.fuse_info.open_dirs.get(&fh)) {
-            (Some(dir_map), Some(expected_ino)) if *expected_ino == ino => {
-                for (entry_offset, &entry_ino) in dir_map.iter().enumerate().skip(offset) {
+        match (
+            self.fuse_info.parents.get(&ino),
+            self.fuse_info.open_dirs.get(&fh),
+        ) {
+            (Some(&parent), Some(expected_ino)) if *expected_ino == ino => {
+                let entries = [
+                    (ino, FileType::Directory, OsStr::new(".")),
+                    (parent, FileType::Directory, OsStr::new("..")),
+                ]
+                .into_iter()
+                .chain(
+                    self.dir_map
+                        .get(&ino)
+                        .into_iter()
+                        .flatten()
+                        .map(|&entry_ino| {
+                            let node =
+                                get_node(&self.inodes, entry_ino).expect("valid by construction");
+                            (entry_ino, node.kind(), node.name())
+                        }),
+                );
+
+                for (entry_offset, (entry_ino, kind, name)) in entries.enumerate().skip(offset) {
                     // Apparently this is 1-indexed.
                     let offset = entry_offset as i64 + 1;
-                    let node = get_node(&self.inodes, entry_ino).expect("valid by construction");
-                    if reply.add(entry_ino, offset, node.kind(), node.name()) {
+                    if reply.add(entry_ino, offset, kind, name) {
                         break;
                     }
                 }
@@ -317,17 +467,75 @@ impl Filesystem for BackupFs {
     }
 
     fn statfs(&mut self, _req: &fuser::Request<'_>, _ino: u64, reply: fuser::ReplyStatfs) {
+        // The filesystem is read-only, so every block is used and none are free. Block
+        // counts are in the same units as the files' `blocks` attributes.
         reply.statfs(
             self.fuse_info.blocks,
             0,
             0,
             u64::try_from(self.inodes.len()).unwrap() + 1,
             0,
-            // Same as the average chunk size.
-            1024 * 1024,
+            BLKSIZE,
             255,
-            // Same as the average chunk size.
-            1024 * 1024,
+            BLKSIZE,
         );
     }
+
+    fn getxattr(
+        &mut self,
+        _req: &fuser::Request<'_>,
+        ino: u64,
+        name: &OsStr,
+        size: u32,
+        reply: fuser::ReplyXattr,
+    ) {
+        match self.xattrs(ino) {
+            Some(xattrs) => match xattrs.into_iter().find(|(xattr, _)| *xattr == name) {
+                Some((_, value)) => reply_xattr(reply, size, &value),
+                None => reply.error(ENOATTR),
+            },
+            None => reply.error(libc::ENOENT),
+        }
+    }
+
+    fn listxattr(
+        &mut self,
+        _req: &fuser::Request<'_>,
+        ino: u64,
+        size: u32,
+        reply: fuser::ReplyXattr,
+    ) {
+        match self.xattrs(ino) {
+            Some(xattrs) => {
+                // The names are listed one after another, each terminated by a NUL.
+                let names = xattrs
+                    .into_iter()
+                    .flat_map(|(name, _)| name.bytes().chain([0]))
+                    .collect::<Vec<_>>();
+                reply_xattr(reply, size, &names);
+            }
+            None => reply.error(libc::ENOENT),
+        }
+    }
+
+    fn access(&mut self, _req: &fuser::Request<'_>, ino: u64, mask: i32, reply: fuser::ReplyEmpty) {
+        // Matches the permissions reported by `getattr`.
+        let executable = if ino == ROOT_INODE {
+            true
+        } else if let Some(node) = get_node(&self.inodes, ino) {
+            node.kind() != FileType::RegularFile || node.is_executable()
+        } else {
+            reply.error(libc::ENOENT);
+            return;
+        };
+
+        if mask & libc::W_OK != 0 {
+            // Nothing can be written to a backup.
+            reply.error(libc::EROFS);
+        } else if mask & libc::X_OK != 0 && !executable {
+            reply.error(libc::EACCES);
+        } else {
+            reply.ok();
+        }
+    }
 }
@@ -1,18 +1,23 @@
-use std::collections::HashMap;
+use std::collections::{hash_map::Entry, HashMap};
 use std::path::PathBuf;
 use std::sync::mpsc;
 use std::time::{Duration, UNIX_EPOCH};
+use std::{io, ptr};
 
 use anyhow::{anyhow, Context};
 use dokan::{
-    CreateFileInfo, DiskSpaceInfo, FileInfo, FileSystemHandler, FileSystemMounter, FillDataError,
-    FillDataResult, FindData, MountFlags, MountOptions, OperationInfo, OperationResult, VolumeInfo,
-    IO_SECURITY_CONTEXT,
+    is_name_in_expression, CreateFileInfo, DiskSpaceInfo, FileInfo, FileSystemHandler,
+    FileSystemMounter, FillDataError, FillDataResult, FindData, MountFlags, MountOptions,
+    OperationInfo, OperationResult, VolumeInfo, IO_SECURITY_CONTEXT,
 };
 use dokan_sys::win32;
 use steam_vent::proto::content_manifest::content_manifest_payload::FileMapping;
+use tracing::warn;
 use widestring::{U16CStr, U16CString};
-use winapi::{shared::ntstatus, um::winnt};
+use winapi::{
+    shared::{minwindef::FALSE, ntstatus, sddl},
+    um::{handleapi, processthreadsapi, securitybaseapi, winbase, winnt},
+};
 
 use super::{get_node, is_dir, read_data, BackupFs, Node, ReadError, ROOT_INODE};
 
@@ -38,7 +43,7 @@ impl Node {
             last_access_time: crtime,
             last_write_time: crtime,
             file_size: self.size(),
-            number_of_links: 0,
+            number_of_links: 1,
             file_index: ino,
         }
     }
@@ -50,35 +55,148 @@ const ROOT_FILE_INFO: FileInfo = FileInfo {
     last_access_time: UNIX_EPOCH,
     last_write_time: UNIX_EPOCH,
     file_size: 0,
-    number_of_links: 0,
+    number_of_links: 1,
     file_index: 1,
 };
 
 pub(super) struct FsInfo {
+    /// A map from case-folded paths (see [`fold_case`]) to inodes.
     path_map: HashMap<U16CString, u64>,
+    /// Self-relative security descriptor applied to every entry in the filesystem.
+    security_descriptor: Vec<u8>,
+    /// The total size of every file in the filesystem.
+    total_size: u64,
 }
 
 impl FsInfo {
-    pub(super) fn prepare(path_map: HashMap<PathBuf, u64>) -> Self {
-        // Rewrite the path map to the type `dokan` uses.
-        let path_map = path_map
-            .into_iter()
-            .map(|(path, ino)| {
-                // `path_map` is provided with no root; add one here.
-                let path = format!("\\{}", path.display());
-                (
-                    U16CString::from_str(&path).expect("valid by construction"),
-                    ino,
-                )
-            })
-            .collect();
-
-        Self { path_map }
+    pub(super) fn prepare(
+        inodes: &[Node],
+        path_map: HashMap<PathBuf, u64>,
+    ) -> anyhow::Result<Self> {
+        let total_size = inodes.iter().map(|node| node.size()).sum();
+
+        // Rewrite the path map to the type `dokan` uses. Windows paths are
+        // case-insensitive, so we fold their case.
+        let mut paths = path_map.into_iter().collect::<Vec<_>>();
+        paths.sort();
+        let mut folded_path_map = HashMap::new();
+        for (path, ino) in paths {
+            // `path_map` is provided with no root; add one here.
+            let path = U16CString::from_str(format!("\\{}", path.display()))
+                .expect("valid by construction");
+            match folded_path_map.entry(fold_case(&path)) {
+                Entry::Vacant(entry) => {
+                    entry.insert(ino);
+                }
+                Entry::Occupied(_) => warn!(
+                    "{} differs from another path only by case, and will not be accessible",
+                    path.to_string_lossy(),
+                ),
+            }
+        }
+        let path_map = folded_path_map;
+
+        let security_descriptor =
+            read_only_security_descriptor().context("Failed to build security descriptor")?;
+
+        Ok(Self {
+            path_map,
+            security_descriptor,
+            total_size,
+        })
+    }
+}
+
+/// Folds the case of a path, so that paths can be compared case-insensitively like
+/// Windows does.
+fn fold_case(path: &U16CStr) -> U16CString {
+    U16CString::from_str(path.to_string_lossy().to_lowercase()).expect("no nulls were added")
+}
+
+/// Builds a security descriptor that grants the mounting user read and execute access,
+/// and nothing else.
+///
+/// The DACL is protected so that no write access can be inherited, matching the
+/// `WRITE_PROTECT` mount flag.
+fn read_only_security_descriptor() -> io::Result<Vec<u8>> {
+    let user_sid = current_user_sid()?;
+    let sddl = U16CString::from_str(format!("O:{user_sid}G:{user_sid}D:P(A;;FRFX;;;{user_sid})"))
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+
+    let mut descriptor: winnt::PSECURITY_DESCRIPTOR = ptr::null_mut();
+    let mut descriptor_len = 0;
+    if unsafe {
+        sddl::ConvertStringSecurityDescriptorToSecurityDescriptorW(
+            sddl.as_ptr(),
+            sddl::SDDL_REVISION_1.into(),
+            &mut descriptor,
+            &mut descriptor_len,
+        )
+    } == FALSE
+    {
+        return Err(io::Error::last_os_error());
+    }
+
+    let bytes =
+        unsafe { std::slice::from_raw_parts(descriptor as *const u8, descriptor_len as usize) }
+            .to_vec();
+    unsafe { winbase::LocalFree(descriptor) };
+
+    Ok(bytes)
+}
+
+/// Returns the SID of the user running this process, in string form.
+fn current_user_sid() -> io::Result<String> {
+    let mut token = ptr::null_mut();
+    if unsafe {
+        processthreadsapi::OpenProcessToken(
+            processthreadsapi::GetCurrentProcess(),
+            winnt::TOKEN_QUERY,
+            &mut token,
+        )
+    } == FALSE
+    {
+        return Err(io::Error::last_os_error());
+    }
+
+    // Query the required buffer size, then fetch the token user.
+    let mut len = 0;
+    unsafe {
+        securitybaseapi::GetTokenInformation(token, winnt::TokenUser, ptr::null_mut(), 0, &mut len)
+    };
+    let mut buf = vec![0u8; len as usize];
+    let res = unsafe {
+        securitybaseapi::GetTokenInformation(
+            token,
+            winnt::TokenUser,
+            buf.as_mut_ptr().cast(),
+            len,
+            &mut len,
+        )
+    };
+    let err = io::Error::last_os_error();
+    unsafe { handleapi::CloseHandle(token) };
+    if res == FALSE {
+        return Err(err);
     }
+
+    let token_user = unsafe { ptr::read_unaligned(buf.as_ptr() as *const winnt::TOKEN_USER) };
+    let mut sid_str = ptr::null_mut();
+    if unsafe { sddl::ConvertSidToStringSidW(token_user.User.Sid, &mut sid_str) } == FALSE {
+        return Err(io::Error::last_os_error());
+    }
+    let sid = unsafe { U16CStr::from_ptr_str(sid_str) }.to_string_lossy();
+    unsafe { winbase::LocalFree(sid_str.cast()) };
+
+    Ok(sid)
 }
 
 impl BackupFs {
-    pub(super) fn mount(self, mountpoint: PathBuf) -> anyhow::Result<()> {
+    /// Mounts the filesystem, and waits for Ctrl-C to unmount it.
+    ///
+    /// If `background` is set, nothing is printed after the filesystem is mounted, as
+    /// nothing may be reading our output.
+    pub(super) fn mount(self, mountpoint: PathBuf, background: bool) -> anyhow::Result<()> {
         let mount_point = U16CString::from_os_str(mountpoint.as_os_str())?;
 
         let (tx, rx) = mpsc::channel();
@@ -102,19 +220,62 @@ impl BackupFs {
         let fs = mounter.mount()?;
 
         println!("Mounted '{name}' at {}", mountpoint.display());
-        println!("Waiting for Ctrl-C...");
+        if !background {
+            println!("Waiting for Ctrl-C...");
+        }
         rx.recv().expect("Could not receive from channel");
 
         // Unmount the filesystem.
-        if !dokan::unmount(&mount_point) {
+        if !dokan::unmount(&mount_point) && !background {
             println!("Failed to unmount the system; program might hang.");
         }
         drop(fs);
 
         dokan::shutdown();
 
+        self.log_cache_stats();
+
         Ok(())
     }
+
+    /// Fills in the entries of the folder `ino` whose names match the DOS-style
+    /// `pattern`, or all of its entries if `pattern` is `None`.
+    fn find_entries(
+        &self,
+        ino: u64,
+        pattern: Option<&U16CStr>,
+        mut fill_find_data: impl FnMut(&FindData) -> FillDataResult,
+    ) -> OperationResult<()> {
+        match self.dir_map.get(&ino) {
+            Some(dir_map) => {
+                for entry_ino in dir_map {
+                    let node = get_node(&self.inodes, *entry_ino).expect("valid by construction");
+                    // Names are converted lossily for display.
+                    let file_name = U16CString::from_str(node.name().to_string_lossy())
+                        .map_err(|_| ntstatus::STATUS_OBJECT_NAME_INVALID)?;
+                    // Names on Windows are case-insensitive.
+                    if pattern
+                        .is_some_and(|pattern| !is_name_in_expression(pattern, &file_name, true))
+                    {
+                        continue;
+                    }
+
+                    let file_info = node.file_info(*entry_ino);
+                    fill_find_data(&FindData {
+                        attributes: file_info.attributes,
+                        creation_time: file_info.creation_time,
+                        last_access_time: file_info.last_access_time,
+                        last_write_time: file_info.last_write_time,
+                        file_size: file_info.file_size,
+                        file_name,
+                    })
+                    .map_err(|e| <FillDataError as Into<i32>>::into(e))?;
+                }
+                Ok(())
+            }
+            None => Err(ntstatus::STATUS_INVALID_PARAMETER),
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -147,7 +308,7 @@ impl<'c, 'h: 'c> FileSystemHandler<'c, 'h> for BackupFs {
             // Reject all write attempts.
             Err(ntstatus::STATUS_MEDIA_WRITE_PROTECTED)
         } else if create_disposition == win32::FILE_OPEN {
-            match self.windows_info.path_map.get(file_name) {
+            match self.windows_info.path_map.get(&fold_case(file_name)) {
                 // Path does not exist.
                 None => Err(ntstatus::STATUS_OBJECT_NAME_NOT_FOUND),
                 // Path exists, get its details.
@@ -187,7 +348,14 @@ impl<'c, 'h: 'c> FileSystemHandler<'c, 'h> for BackupFs {
     ) -> OperationResult<u32> {
         let node = get_node(&self.inodes, context.ino).ok_or(ntstatus::STATUS_INVALID_PARAMETER)?;
 
-        match read_data(&self.runtime, &self.chunks, node, offset as u64, buffer) {
+        match read_data(
+            &self.runtime,
+            &self.chunks,
+            &self.cache,
+            node,
+            offset as u64,
+            buffer,
+        ) {
             Ok(read) => Ok(read as u32),
             Err(ReadError::InvalidParameter) => Err(ntstatus::STATUS_INVALID_PARAMETER),
             Err(ReadError::Io) => Err(ntstatus::STATUS_DATA_ERROR),
@@ -212,28 +380,57 @@ impl<'c, 'h: 'c> FileSystemHandler<'c, 'h> for BackupFs {
     fn find_files(
         &'h self,
         _file_name: &U16CStr,
-        mut fill_find_data: impl FnMut(&FindData) -> FillDataResult,
+        fill_find_data: impl FnMut(&FindData) -> FillDataResult,
         _info: &OperationInfo<'c, 'h, Self>,
         context: &'c Self::Context,
     ) -> OperationResult<()> {
-        match self.dir_map.get(&context.ino) {
-            Some(dir_map) => {
-                for entry_ino in dir_map {
-                    let node = get_node(&self.inodes, *entry_ino).expect("valid by construction");
-                    let file_info = node.file_info(*entry_ino);
-                    fill_find_data(&FindData {
-                        attributes: file_info.attributes,
-                        creation_time: file_info.creation_time,
-                        last_access_time: file_info.last_access_time,
-                        last_write_time: file_info.last_write_time,
-                        file_size: file_info.file_size,
-                        file_name: U16CString::from_str(node.name()).unwrap(),
-                    })
-                    .map_err(|e| <FillDataError as Into<i32>>::into(e))?;
-                }
-                Ok(())
-            }
-            None => Err(ntstatus::STATUS_INVALID_PARAMETER),
+        self.find_entries(context.ino, None, fill_find_data)
+    }
+
+    fn find_files_with_pattern(
+        &'h self,
+        _file_name: &U16CStr,
+        pattern: &U16CStr,
+        fill_find_data: impl FnMut(&FindData) -> FillDataResult,
+        _info: &OperationInfo<'c, 'h, Self>,
+        context: &'c Self::Context,
+    ) -> OperationResult<()> {
+        // Every name matches `*`, so don't bother matching against it.
+        let pattern = (pattern.as_slice() != [u16::from(b'*')]).then_some(pattern);
+        self.find_entries(context.ino, pattern, fill_find_data)
+    }
+
+    fn get_file_security(
+        &'h self,
+        _file_name: &U16CStr,
+        security_information: u32,
+        security_descriptor: winnt::PSECURITY_DESCRIPTOR,
+        buffer_length: u32,
+        _info: &OperationInfo<'c, 'h, Self>,
+        _context: &'c Self::Context,
+    ) -> OperationResult<u32> {
+        // Every entry is read-only, so they all share the same descriptor.
+        let object_descriptor = self.windows_info.security_descriptor.as_ptr() as *mut _;
+        let len = self.windows_info.security_descriptor.len() as u32;
+        if len > buffer_length {
+            // Dokan will request again with a large enough buffer.
+            return Ok(len);
+        }
+
+        let mut ret_len = 0;
+        if unsafe {
+            securitybaseapi::GetPrivateObjectSecurity(
+                object_descriptor,
+                security_information,
+                security_descriptor,
+                buffer_length,
+                &mut ret_len,
+            )
+        } == FALSE
+        {
+            Err(ntstatus::STATUS_INVALID_PARAMETER)
+        } else {
+            Ok(len)
         }
     }
 
@@ -241,8 +438,9 @@ impl<'c, 'h: 'c> FileSystemHandler<'c, 'h> for BackupFs {
         &'h self,
         _info: &OperationInfo<'c, 'h, Self>,
     ) -> OperationResult<DiskSpaceInfo> {
+        // The filesystem is read-only, so it is full.
         Ok(DiskSpaceInfo {
-            byte_count: 0,
+            byte_count: self.windows_info.total_size,
             free_byte_count: 0,
             available_byte_count: 0,
         })
@@ -0,0 +1,97 @@
+use anyhow::anyhow;
+use serde_json::json;
+use tracing::error;
+
+use super::verify::DepotReport;
+use crate::cli::{OutputFormat, RepairBackup};
+
+impl RepairBackup {
+    pub(crate) async fn run(mut self) -> anyhow::Result<()> {
+        if self.verify.manifest_dir.is_none() {
+            return Err(anyhow!(
+                "tev backup repair requires --manifest-dir, to find the files that use each \
+                chunk",
+            ));
+        }
+
+        // Only print the list of chunks.
+        self.verify.quiet = true;
+        let report = self.verify.verify().await?;
+
+        let mut unverified = 0;
+        let mut backups = vec![];
+        for backup in &report.backups {
+            if let Some(error) = &backup.error {
+                error!("Failed to verify {}: {error}", backup.path.display());
+                unverified += 1;
+            }
+
+            let depots = backup
+                .depots
+                .iter()
+                .filter_map(|depot| {
+                    let chunks = damaged_chunks(depot);
+                    (!chunks.is_empty()).then_some((depot, chunks))
+                })
+                .collect::<Vec<_>>();
+
+            if self.verify.format == OutputFormat::Json {
+                backups.push(json!({
+                    "path": backup.path,
+                    "game": backup.game,
+                    "depots": depots.iter().map(|(depot, chunks)| json!({
+                        "depot": depot.depot,
+                        "manifest": depot.manifest,
+                        "chunks": chunks,
+                        "files": depot.incomplete_files,
+                    })).collect::<Vec<_>>(),
+                }));
+            } else {
+                for (depot, chunks) in depots {
+                    let manifest = depot.manifest.as_deref().unwrap_or("-");
+                    for sha in chunks {
+                        println!("{}\t{manifest}\tchunk\t{sha}", depot.depot);
+                    }
+                    for file in &depot.incomplete_files {
+                        println!("{}\t{manifest}\tfile\t{file}", depot.depot);
+                    }
+                }
+            }
+        }
+
+        if self.verify.format == OutputFormat::Json {
+            println!(
+                "{}",
+                serde_json::to_string_pretty(&json!({ "backups": backups }))?
+            );
+        }
+
+        if unverified > 0 {
+            Err(anyhow!(
+                "{unverified} of {} backups could not be verified",
+                report.backups.len(),
+            ))
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// Returns the SHA-1 digests of the depot's chunks that are missing or corrupt, in
+/// sorted order.
+fn damaged_chunks(depot: &DepotReport) -> Vec<&str> {
+    let mut chunks = depot
+        .missing_chunks
+        .iter()
+        .chain(
+            depot
+                .chunkstores
+                .iter()
+                .flat_map(|chunkstore| &chunkstore.corrupt_chunks),
+        )
+        .map(String::as_str)
+        .collect::<Vec<_>>();
+    chunks.sort_unstable();
+    chunks.dedup();
+    chunks
+}
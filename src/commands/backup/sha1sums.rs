@@ -0,0 +1,144 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::{Component, Path};
+use std::sync::Mutex;
+use std::thread;
+
+use anyhow::{anyhow, Context};
+use sha1::{Digest, Sha1};
+use tokio::runtime::Builder;
+use tracing::{error, warn};
+
+use super::{
+    mount::{is_dir, BackupFs, Node},
+    Backup, SEQUENTIAL_CACHE_SIZE,
+};
+use crate::{cli::BackupSha1Sums, formats::manifest::ManifestSource};
+
+impl BackupSha1Sums {
+    pub(crate) fn run(self) -> anyhow::Result<()> {
+        let jobs = self.jobs.map_or_else(
+            || thread::available_parallelism().map_or(1, |n| n.get()),
+            |jobs| jobs.get(),
+        );
+
+        let backup = Backup::open(&self.path, &self.disks)?.require_all_disks()?;
+
+        let depot_keys = self.depot_keys.load()?;
+
+        let filesystem = BackupFs::prepare(
+            backup,
+            &ManifestSource::open(&self.manifest_dir)?,
+            SEQUENTIAL_CACHE_SIZE * jobs as u64,
+            &depot_keys,
+            Builder::new_multi_thread()
+                .thread_name("tev-worker")
+                .worker_threads(jobs)
+                .build()?,
+        )
+        .context("Failed to prepare filesystem")?;
+
+        // Symlinks and directories have no contents to hash.
+        let nodes = filesystem
+            .real_nodes()
+            .filter(|node| !is_dir(node.file_mapping()) && node.link_target().is_none())
+            .enumerate();
+
+        // Hash the files on `jobs` threads, each taking the next file when it is free.
+        let nodes = Mutex::new(nodes);
+        let results = Mutex::new(vec![]);
+        thread::scope(|scope| {
+            for _ in 0..jobs {
+                scope.spawn(|| loop {
+                    let Some((i, node)) = nodes.lock().unwrap().next() else {
+                        break;
+                    };
+                    let digest = hash_node(&filesystem, node);
+                    results.lock().unwrap().push((i, node, digest));
+                });
+            }
+        });
+        let mut results = results.into_inner().unwrap();
+        results.sort_by_key(|(i, _, _)| *i);
+
+        let mut writer: Box<dyn Write> = match &self.output {
+            Some(path) => {
+                Box::new(BufWriter::new(File::create(path).with_context(|| {
+                    format!("Failed to create {}", path.display())
+                })?))
+            }
+            None => Box::new(io::stdout().lock()),
+        };
+
+        let mut failed = 0;
+        let mut mismatched = 0;
+        for (_, node, digest) in results {
+            let path = node.path().expect("real nodes have paths");
+            let digest = match digest {
+                Ok(digest) => digest,
+                Err(e) => {
+                    error!("{e:#}");
+                    failed += 1;
+                    continue;
+                }
+            };
+
+            // Depot manifests record the digest of each file's contents, but older
+            // manifests may leave it empty.
+            let expected = node
+                .file_mapping()
+                .map(|file_mapping| file_mapping.sha_content());
+            if expected.is_some_and(|expected| !expected.is_empty() && expected != digest) {
+                warn!(
+                    "{} does not match the SHA-1 digest in its manifest",
+                    path.display(),
+                );
+                mismatched += 1;
+            }
+
+            writeln!(writer, "{}", sha1sum_line(&digest, path))?;
+        }
+        writer.flush()?;
+        filesystem.log_cache_stats();
+
+        match (failed, mismatched) {
+            (0, 0) => Ok(()),
+            (0, _) => Err(anyhow!(
+                "{mismatched} files do not match the digests in their manifests"
+            )),
+            _ => Err(anyhow!("{failed} files could not be read")),
+        }
+    }
+}
+
+/// Computes the SHA-1 digest of a file's contents.
+fn hash_node(filesystem: &BackupFs, node: &Node) -> anyhow::Result<[u8; 20]> {
+    let mut hasher = Sha1::new();
+    filesystem.read_node(node, 0, None, &mut hasher)?;
+    Ok(hasher.finalize().into())
+}
+
+/// Formats a line of a `sha1sum` checksum file, with the path's components separated by
+/// `/`.
+///
+/// Like `sha1sum`, paths containing a backslash or line break are escaped, and the line
+/// is prefixed with a backslash to indicate this.
+fn sha1sum_line(digest: &[u8; 20], path: &Path) -> String {
+    let path = path
+        .components()
+        .filter_map(|component| match component {
+            Component::Normal(name) => Some(name.to_string_lossy()),
+            _ => None,
+        })
+        .collect::<Vec<_>>()
+        .join("/");
+    if path.contains(['\\', '\n', '\r']) {
+        let path = path
+            .replace('\\', "\\\\")
+            .replace('\n', "\\n")
+            .replace('\r', "\\r");
+        format!("\\{}  {path}", hex::encode(digest))
+    } else {
+        format!("{}  {path}", hex::encode(digest))
+    }
+}
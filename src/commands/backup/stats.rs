@@ -0,0 +1,73 @@
+use std::collections::HashSet;
+
+use byte_unit::{Byte, UnitType};
+
+use super::Backup;
+use crate::{
+    cli::BackupStats,
+    formats::{csd::ChunkStore, csm::ChunkStoreManifest},
+};
+
+/// Counts of chunks and their sizes.
+#[derive(Default)]
+struct Totals {
+    chunks: usize,
+    compressed: u64,
+    uncompressed: u64,
+}
+
+impl Totals {
+    fn add(&mut self, compressed: u32, uncompressed: u32) {
+        self.chunks += 1;
+        self.compressed += u64::from(compressed);
+        self.uncompressed += u64::from(uncompressed);
+    }
+
+    fn print(&self, label: &str) {
+        let compressed = Byte::from_u64(self.compressed).get_appropriate_unit(UnitType::Binary);
+        let uncompressed = Byte::from_u64(self.uncompressed).get_appropriate_unit(UnitType::Binary);
+        println!(
+            "{label}: {} chunks, {compressed:#.2} compressed, {uncompressed:#.2} uncompressed",
+            self.chunks,
+        );
+    }
+}
+
+impl BackupStats {
+    pub(crate) fn run(self) -> anyhow::Result<()> {
+        let backup = Backup::open(&self.path, &self.disks)?.require_all_disks()?;
+
+        let mut seen = HashSet::new();
+        let mut total = Totals::default();
+        let mut unique = Totals::default();
+
+        println!("Depots:");
+        for (depot, chunkstores) in &backup.sku.chunkstores {
+            let mut depot_total = Totals::default();
+            for chunkstore_index in chunkstores.keys() {
+                let csm_path = ChunkStore::find_csm(&backup.dirs, *depot, *chunkstore_index)?;
+                let csm = ChunkStoreManifest::read(&csm_path)?;
+
+                for (sha, chunk) in &csm.chunks {
+                    depot_total.add(chunk.compressed_length, chunk.uncompressed_length);
+                    total.add(chunk.compressed_length, chunk.uncompressed_length);
+                    if seen.insert(*sha) {
+                        unique.add(chunk.compressed_length, chunk.uncompressed_length);
+                    }
+                }
+            }
+            depot_total.print(&format!("- {depot}"));
+        }
+
+        total.print("Total");
+        unique.print("Unique");
+        Totals {
+            chunks: total.chunks - unique.chunks,
+            compressed: total.compressed - unique.compressed,
+            uncompressed: total.uncompressed - unique.uncompressed,
+        }
+        .print("Saved by deduplication");
+
+        Ok(())
+    }
+}
@@ -0,0 +1,184 @@
+//! Backups written to temporary folders, for tests.
+
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use sha1::{Digest, Sha1};
+use steam_vent::proto::{
+    content_manifest::{
+        content_manifest_payload::{file_mapping::ChunkData, FileMapping},
+        ContentManifestMetadata, ContentManifestPayload, ContentManifestSignature,
+    },
+    protobuf::Message,
+};
+use tokio::runtime::Builder;
+
+use super::{mount::BackupFs, Backup, SEQUENTIAL_CACHE_SIZE};
+use crate::formats::{
+    csd::compress_chunk,
+    csm::{Chunk, ChunkStoreManifest},
+    manifest::{Manifest, ManifestSource},
+    sis::StockKeepingUnit,
+};
+
+const DEPOT: u32 = 101;
+const MANIFEST_ID: u64 = 1234;
+
+/// A temporary folder, which is removed when dropped.
+pub(super) struct TempDir(PathBuf);
+
+impl TempDir {
+    pub(super) fn new() -> Self {
+        static COUNT: AtomicUsize = AtomicUsize::new(0);
+        let path = std::env::temp_dir().join(format!(
+            "tev-test-{}-{}",
+            std::process::id(),
+            COUNT.fetch_add(1, Ordering::Relaxed),
+        ));
+        fs::create_dir_all(&path).unwrap();
+        Self(path)
+    }
+
+    pub(super) fn path(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+/// A single-depot backup and its depot manifest, in a temporary folder.
+pub(super) struct TestBackup {
+    dir: TempDir,
+}
+
+impl TestBackup {
+    /// Writes a backup of the given files (paths and contents), splitting their
+    /// contents into chunks of `chunk_size` bytes.
+    pub(super) fn new(files: &[(&str, &[u8])], chunk_size: usize) -> Self {
+        let dir = TempDir::new();
+        fs::create_dir_all(dir.path().join("backup")).unwrap();
+        fs::create_dir_all(dir.path().join("manifests")).unwrap();
+
+        let mut csd = vec![];
+        let mut csm = ChunkStoreManifest {
+            is_encrypted: false,
+            depot: DEPOT,
+            chunks: vec![],
+        };
+        let mut payload = ContentManifestPayload::new();
+        for (path, contents) in files {
+            let mut file_mapping = FileMapping::new();
+            file_mapping.set_filename(path.to_string());
+            file_mapping.set_size(contents.len() as u64);
+            file_mapping.set_flags(0);
+            for (i, data) in contents.chunks(chunk_size).enumerate() {
+                let sha: [u8; 20] = Sha1::digest(data).into();
+                let body = compress_chunk(data).unwrap();
+                if !csm.chunks.iter().any(|(s, _)| *s == sha) {
+                    csm.chunks.push((
+                        sha,
+                        Chunk {
+                            offset: csd.len() as u64,
+                            uncompressed_length: data.len() as u32,
+                            compressed_length: body.len() as u32,
+                        },
+                    ));
+                    csd.extend_from_slice(&body);
+                }
+
+                let mut chunk = ChunkData::new();
+                chunk.set_sha(sha.to_vec());
+                chunk.set_crc(crc32fast::hash(data));
+                chunk.set_offset((i * chunk_size) as u64);
+                chunk.set_cb_original(data.len() as u32);
+                chunk.set_cb_compressed(body.len() as u32);
+                file_mapping.chunks.push(chunk);
+            }
+            payload.mappings.push(file_mapping);
+        }
+
+        let backup_dir = dir.path().join("backup");
+        fs::write(backup_dir.join(format!("{DEPOT}_depotcache_1.csd")), &csd).unwrap();
+        csm.write(File::create(backup_dir.join(format!("{DEPOT}_depotcache_1.csm"))).unwrap())
+            .unwrap();
+        StockKeepingUnit {
+            name: "Test Game".into(),
+            disks: 1,
+            disk: 1,
+            backup: 1,
+            contenttype: 3,
+            apps: vec![100],
+            depots: vec![DEPOT],
+            manifests: BTreeMap::from([(DEPOT, MANIFEST_ID)]),
+            chunkstores: BTreeMap::from([(DEPOT, BTreeMap::from([(1, csd.len() as i32)]))]),
+        }
+        .write(File::create(backup_dir.join("sku.sis")).unwrap())
+        .unwrap();
+
+        let mut metadata = ContentManifestMetadata::new();
+        metadata.set_depot_id(DEPOT);
+        metadata.set_gid_manifest(MANIFEST_ID);
+        metadata.set_filenames_encrypted(false);
+        metadata.set_unique_chunks(csm.chunks.len() as u32);
+        // Steam's CRC covers the payload's length followed by the payload.
+        let raw_payload = payload.write_to_bytes().unwrap();
+        let mut crc = crc32fast::Hasher::new();
+        crc.update(&(raw_payload.len() as u32).to_le_bytes());
+        crc.update(&raw_payload);
+        metadata.set_crc_clear(crc.finalize());
+        let backup = Self { dir };
+        Manifest::new(payload, metadata, ContentManifestSignature::new())
+            .write(File::create(backup.manifest_path()).unwrap())
+            .unwrap();
+
+        backup
+    }
+
+    /// Removes the chunk with the given contents from the backup's chunkstore manifest,
+    /// as if it had not been backed up.
+    pub(super) fn remove_chunk(&self, data: &[u8]) {
+        let sha: [u8; 20] = Sha1::digest(data).into();
+        let path = self
+            .dir
+            .path()
+            .join("backup")
+            .join(format!("{DEPOT}_depotcache_1.csm"));
+        let mut csm = ChunkStoreManifest::read(&path).unwrap();
+        csm.chunks.retain(|(s, _)| *s != sha);
+        csm.write(File::create(&path).unwrap()).unwrap();
+    }
+
+    /// Returns the folder containing the backup's depot manifest.
+    pub(super) fn manifest_dir(&self) -> PathBuf {
+        self.dir.path().join("manifests")
+    }
+
+    /// Returns the path of the backup's depot manifest.
+    pub(super) fn manifest_path(&self) -> PathBuf {
+        self.manifest_dir()
+            .join(format!("{DEPOT}_{MANIFEST_ID}.manifest"))
+    }
+
+    /// Returns a folder in which tests can write their output.
+    pub(super) fn output_dir(&self) -> PathBuf {
+        self.dir.path().join("output")
+    }
+
+    /// Opens the backup as a filesystem.
+    pub(super) fn open(&self) -> BackupFs {
+        BackupFs::prepare(
+            Backup::open(&self.dir.path().join("backup"), &[]).unwrap(),
+            &ManifestSource::open(&self.manifest_dir()).unwrap(),
+            SEQUENTIAL_CACHE_SIZE,
+            &Default::default(),
+            Builder::new_current_thread().build().unwrap(),
+        )
+        .unwrap()
+    }
+}
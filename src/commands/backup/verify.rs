@@ -1,180 +1,1045 @@
-use std::path::Path;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt;
+use std::fs::File;
+use std::io::{BufWriter, IsTerminal};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
 
 use anyhow::{anyhow, Context};
-use futures_util::future;
+use futures_util::{stream, StreamExt};
+use indicatif::{ProgressBar, ProgressDrawTarget, ProgressStyle};
+use rsa::RsaPublicKey;
+use serde::Serialize;
+use tokio::{runtime::Handle, sync::Semaphore};
+use tracing::debug;
 
+use super::{find_sku, manifests_agree, Backup};
+use crate::commands::{filter::FileFilter, steam::ManifestDownloader};
 use crate::{
-    cli::VerifyBackup,
-    formats::{csd::ChunkStore, manifest::Manifest, sis::StockKeepingUnit},
+    cli::{OutputFormat, VerifyBackup},
+    formats::{
+        csd::ChunkStore,
+        csm::{ChunkStoreManifest, LayoutProblem},
+        manifest::{self, ManifestSource},
+        sis::StockKeepingUnit,
+    },
 };
 
+/// The results of verifying one or more backups, as written by `--report`.
+#[derive(Serialize)]
+pub(super) struct Report {
+    valid: bool,
+    pub(super) backups: Vec<BackupReport>,
+}
+
+#[derive(Serialize)]
+pub(super) struct BackupReport {
+    pub(super) path: PathBuf,
+    pub(super) game: Option<String>,
+    /// The folders of the backup's disks that were found, in disk order.
+    disks: Vec<PathBuf>,
+    /// The disks that the SKU says exist, but that were not found.
+    missing_disks: Vec<u32>,
+    valid: bool,
+    /// Set if the backup could not be fully verified.
+    pub(super) error: Option<String>,
+    pub(super) depots: Vec<DepotReport>,
+}
+
+impl BackupReport {
+    fn has_warnings(&self) -> bool {
+        self.depots.iter().any(|depot| {
+            !depot.warnings.is_empty()
+                || depot
+                    .chunkstores
+                    .iter()
+                    .any(|chunkstore| !chunkstore.warnings.is_empty())
+        })
+    }
+}
+
+#[derive(Serialize)]
+pub(super) struct DepotReport {
+    pub(super) depot: u32,
+    /// The ID of the depot's manifest, according to the SKU.
+    pub(super) manifest: Option<String>,
+    valid: bool,
+    /// The number of chunks listed in the depot's manifest, if one was checked.
+    manifest_chunks: Option<u32>,
+    /// The number of chunks found in the depot's valid chunkstores.
+    chunks: u32,
+    /// Whether the manifest's signature is valid, if it was checked.
+    manifest_signature_valid: Option<bool>,
+    /// The SHA-1 digests of chunks in the depot's manifest that are not in any
+    /// chunkstore of the backup.
+    pub(super) missing_chunks: Vec<String>,
+    /// The files in the depot's manifest that use any missing or corrupt chunks.
+    pub(super) incomplete_files: Vec<String>,
+    pub(super) chunkstores: Vec<ChunkStoreReport>,
+    errors: Vec<String>,
+    warnings: Vec<String>,
+}
+
+#[derive(Default, Serialize)]
+pub(super) struct ChunkStoreReport {
+    index: u32,
+    valid: bool,
+    chunks: u32,
+    /// The size of the chunkstore according to the SKU, if it records one.
+    expected_bytes: Option<u64>,
+    /// The size of the chunkstore's data file.
+    actual_bytes: Option<u64>,
+    /// The SHA-1 digests of chunks that could not be read or were corrupt.
+    pub(super) corrupt_chunks: Vec<String>,
+    errors: Vec<String>,
+    warnings: Vec<String>,
+    /// The SHA-1 digests of the chunks in the chunkstore.
+    #[serde(skip)]
+    shas: Vec<[u8; 20]>,
+}
+
+/// How the chunks of each chunkstore are verified.
+#[derive(Clone)]
+struct ChunkOptions {
+    /// Skip decompressing chunks and checking their digests.
+    fast: bool,
+    /// The maximum number of chunks of a chunkstore to verify concurrently.
+    jobs: usize,
+    /// Limits the number of chunks being verified concurrently across all chunkstores
+    /// to the number of threads given by `--threads`.
+    limiter: Arc<Semaphore>,
+    /// If set, only the chunks with these digests are verified.
+    selected_chunks: Option<Arc<HashSet<[u8; 20]>>>,
+}
+
+/// Keys used during verification.
+struct Keys {
+    depot_keys: HashMap<u32, [u8; 32]>,
+    /// Steam's content manifest signing key, if signatures should be checked.
+    signing_key: Option<RsaPublicKey>,
+    /// Used to download missing manifests, if `--download-manifests` was given.
+    downloader: Option<ManifestDownloader>,
+}
+
 impl VerifyBackup {
     pub(crate) async fn run(self) -> anyhow::Result<()> {
-        for path in self.path {
-            if let Err(e) = verify_backup(&path, self.manifest_dir.as_deref(), self.fast).await {
-                println!("Failed to verify {}: {e}", path.display());
-            }
+        let report = self.verify().await?;
+
+        if self.format == OutputFormat::Json && !self.quiet {
+            println!("{}", serde_json::to_string_pretty(&report)?);
         }
 
-        Ok(())
+        let failed = report
+            .backups
+            .iter()
+            .filter(|backup| self.failed(backup))
+            .count();
+        if failed > 0 {
+            Err(anyhow!(
+                "{failed} of {} backups failed verification",
+                report.backups.len()
+            ))
+        } else {
+            Ok(())
+        }
     }
-}
 
-async fn verify_backup(path: &Path, manifest_dir: Option<&Path>, fast: bool) -> anyhow::Result<()> {
-    println!();
-
-    let base_dir = {
-        let metadata = path.metadata()?;
-        if metadata.is_dir() {
-            Ok(path.to_path_buf())
-        } else if metadata.is_file() {
-            Ok(path
-                .parent()
-                .expect("Files always have parents")
-                .to_path_buf())
+    /// Verifies every backup, and writes the results to `--report` if given.
+    pub(super) async fn verify(&self) -> anyhow::Result<Report> {
+        let filter = FileFilter::new(&self.filter)?;
+        if filter.is_active() && self.manifest_dir.is_none() {
+            return Err(anyhow!(
+                "--include and --exclude require --manifest-dir, to find the chunks of the \
+                matching files",
+            ));
+        }
+
+        let keys = Keys {
+            depot_keys: self.depot_keys.load()?,
+            signing_key: self
+                .verify_signature
+                .then(|| {
+                    let path = self.signing_key.as_ref().expect("required by clap");
+                    manifest::read_signing_key(path).with_context(|| {
+                        format!("Failed to read signing key from {}", path.display())
+                    })
+                })
+                .transpose()?,
+            downloader: self.download.downloader(),
+        };
+
+        let backups = if self.recursive {
+            self.run_recursive(&keys, &filter).await?
         } else {
-            Err(anyhow!("Path does not exist"))
-        }?
-    };
+            let mut backups = vec![];
+            for path in &self.path {
+                let backup = Backup::open(path, &[]);
+                // Several disks of the same backup may have been given.
+                if let Ok(backup) = &backup {
+                    if backups
+                        .iter()
+                        .any(|report: &BackupReport| report.disks == backup.dirs)
+                    {
+                        continue;
+                    }
+                }
+                backups.push(self.verify_backup(path, backup, &keys, &filter).await);
+            }
+            backups
+        };
 
-    let sku = StockKeepingUnit::read(&base_dir.join("sku.sis"))?;
-    println!("Game: {}", sku.name);
+        let report = Report {
+            valid: !backups.iter().any(|backup| self.failed(backup)),
+            backups,
+        };
 
-    let mut valid = true;
+        if let Some(report_path) = &self.report {
+            let file = File::create(report_path)
+                .with_context(|| format!("Failed to create {}", report_path.display()))?;
+            serde_json::to_writer_pretty(BufWriter::new(file), &report)?;
+        }
 
-    for depot in sku.depots {
-        println!("Verifying depot {depot}");
+        Ok(report)
+    }
 
-        let manifest = manifest_dir
-            .zip(sku.manifests.get(&depot))
-            .map(|(manifest_dir, manifest_id)| {
-                let manifest_path =
-                    manifest_dir.join(format!("{}_{}.manifest", depot, manifest_id));
-                let manifest = Manifest::open(&manifest_path).with_context(|| {
-                    format!(
-                        "Cannot find manifest {manifest_id} for depot {depot} in {}",
-                        manifest_dir.display()
-                    )
-                })?;
-                if manifest.metadata.depot_id() == depot {
-                    if manifest.metadata.filenames_encrypted() {
-                        println!(
-                            "Manifest {manifest_id} for depot {depot} has encrypted filenames"
+    /// Returns whether a backup failed verification.
+    fn failed(&self, backup: &BackupReport) -> bool {
+        !backup.valid || (self.strict && backup.has_warnings())
+    }
+
+    /// Returns whether to suppress the human-readable output.
+    fn quiet(&self) -> bool {
+        self.quiet || self.format == OutputFormat::Json
+    }
+
+    /// Returns the maximum number of chunkstores to verify concurrently.
+    fn jobs(&self) -> usize {
+        self.jobs
+            .or_else(|| std::thread::available_parallelism().ok())
+            .map_or(1, |jobs| jobs.get())
+    }
+
+    /// Returns the reports for every backup found.
+    async fn run_recursive(
+        &self,
+        keys: &Keys,
+        filter: &FileFilter,
+    ) -> anyhow::Result<Vec<BackupReport>> {
+        let mut backup_dirs = vec![];
+        for path in &self.path {
+            find_backups(path, &mut backup_dirs)
+                .with_context(|| format!("Failed to search {}", path.display()))?;
+        }
+
+        let backups = group_disks(
+            backup_dirs
+                .into_iter()
+                .map(|base_dir| {
+                    let sku_path = find_sku(&base_dir).expect("found by find_backups");
+                    let sku = StockKeepingUnit::read(&sku_path).ok();
+                    (base_dir, sku)
+                })
+                .collect(),
+        );
+
+        // Separate backups of the same game are told apart by their folders.
+        let mut names = HashMap::<_, usize>::new();
+        for (name, _) in &backups {
+            *names.entry(name.clone()).or_default() += 1;
+        }
+
+        let mut reports = vec![];
+        let mut summary = vec![];
+        for (name, mut disks) in backups {
+            disks.sort_by_key(|(disk, _)| *disk);
+            let name = if names[&name] > 1 {
+                format!("{name} ({})", disks[0].1.display())
+            } else {
+                name
+            };
+
+            let disks_found = disks.len();
+            let disks_expected = disks
+                .iter()
+                .find_map(|(disk, _)| disk.map(|(_, disks)| disks))
+                .unwrap_or(1);
+            let dirs = disks.into_iter().map(|(_, dir)| dir).collect::<Vec<_>>();
+
+            let report = self
+                .verify_backup(&dirs[0], Backup::from_dirs(dirs.clone()), keys, filter)
+                .await;
+
+            let mut failures = vec![];
+            if let Some(e) = &report.error {
+                failures.push(e.clone());
+            }
+            if !report.missing_disks.is_empty() {
+                failures.push(format!("found {disks_found} of {disks_expected} disks"));
+            }
+            if report.depots.iter().any(|depot| !depot.valid) {
+                failures.push("depot files are invalid".into());
+            } else if self.strict && report.has_warnings() {
+                failures.push("depot files have warnings".into());
+            }
+            reports.push(report);
+
+            summary.push((name, format!("{disks_found}/{disks_expected}"), failures));
+        }
+
+        if self.quiet() {
+            return Ok(reports);
+        }
+
+        println!();
+        println!("Summary:");
+        let name_width = summary
+            .iter()
+            .map(|(name, _, _)| name.chars().count())
+            .chain(Some(4))
+            .max()
+            .expect("non-empty");
+        println!("{:name_width$}  Disks  Result", "Game");
+        for (name, disks, failures) in summary {
+            println!(
+                "{name:name_width$}  {disks:5}  {}",
+                if failures.is_empty() { "OK" } else { "FAILED" },
+            );
+            for failure in failures {
+                println!("{:name_width$}  - {failure}", "");
+            }
+        }
+
+        Ok(reports)
+    }
+
+    /// Verifies a single backup, which was opened from `path`.
+    async fn verify_backup(
+        &self,
+        path: &Path,
+        backup: anyhow::Result<Backup>,
+        keys: &Keys,
+        filter: &FileFilter,
+    ) -> BackupReport {
+        let mut report = BackupReport {
+            path: path.to_path_buf(),
+            game: None,
+            disks: vec![],
+            missing_disks: vec![],
+            valid: false,
+            error: None,
+            depots: vec![],
+        };
+
+        let output = Output::new(self);
+        output.println("");
+        let res = match backup {
+            Ok(backup) => {
+                self.verify_depots(backup, keys, filter, &output, &mut report)
+                    .await
+            }
+            Err(e) => Err(e),
+        };
+        output.progress.finish_and_clear();
+
+        match res {
+            Ok(()) => {
+                report.valid =
+                    report.missing_disks.is_empty() && report.depots.iter().all(|depot| depot.valid)
+            }
+            Err(e) => {
+                output.println(format_args!("Failed to verify {}: {e}", path.display()));
+                report.error = Some(e.to_string());
+            }
+        }
+
+        report
+    }
+
+    async fn verify_depots(
+        &self,
+        backup: Backup,
+        keys: &Keys,
+        filter: &FileFilter,
+        output: &Output,
+        report: &mut BackupReport,
+    ) -> anyhow::Result<()> {
+        output.println(format_args!("Game: {}", backup.sku.name));
+        if !backup.missing_disks.is_empty() {
+            output.println(format_args!(
+                "- Missing {}",
+                backup.describe_missing_disks()
+            ));
+        }
+
+        let Backup {
+            sku,
+            dirs,
+            missing_disks,
+        } = backup;
+        report.game = Some(sku.name.clone());
+        report.disks = dirs;
+        report.missing_disks = missing_disks;
+
+        for depot in &self.depots {
+            if !sku.depots.contains(depot) {
+                output.println(format_args!("- Depot {depot} is not in this backup"));
+            }
+        }
+        let depots = sku
+            .depots
+            .iter()
+            .copied()
+            .filter(|depot| self.depots.is_empty() || self.depots.contains(depot))
+            .collect::<Vec<_>>();
+
+        let manifest_source = self
+            .manifest_dir
+            .as_deref()
+            .map(ManifestSource::open)
+            .transpose()?;
+
+        if let Some((downloader, manifest_source)) =
+            keys.downloader.as_ref().zip(manifest_source.as_ref())
+        {
+            for download in downloader
+                .download_missing(&sku, &depots, manifest_source)
+                .await?
+            {
+                let (depot, manifest_id) = (download.depot, download.manifest_id);
+                match download.result {
+                    Ok(()) => output.println(format_args!(
+                        "- Downloaded manifest {manifest_id} for depot {depot}"
+                    )),
+                    Err(e) => output.println(format_args!(
+                        "- Failed to download manifest {manifest_id} for depot {depot}: {e:#}"
+                    )),
+                }
+            }
+        }
+
+        // The SKU tells us up front how much data we will need to read.
+        output.start_progress(
+            depots
+                .iter()
+                .filter_map(|depot| sku.chunkstores.get(depot))
+                .flat_map(|chunkstores| chunkstores.values())
+                .filter_map(|&length| u64::try_from(length).ok())
+                .sum(),
+        );
+
+        // Chunks can be shared between depots, so we can only check that the chunks in
+        // each manifest are present after reading every chunkstore. The chunks stored
+        // for depots that aren't being verified are read from their `.csm` files.
+        let mut present_chunks = HashSet::new();
+        for (&depot, chunkstores) in &sku.chunkstores {
+            if depots.contains(&depot) {
+                continue;
+            }
+            for &chunkstore_index in chunkstores.keys() {
+                match ChunkStore::find_csm(&report.disks, depot, chunkstore_index)
+                    .and_then(|csm_path| ChunkStoreManifest::read(&csm_path))
+                {
+                    Ok(csm) => present_chunks.extend(csm.chunks.iter().map(|(sha, _)| *sha)),
+                    Err(e) => debug!(
+                        "Failed to read chunkstore {chunkstore_index} of depot {depot}: {e:#}"
+                    ),
+                }
+            }
+        }
+        // The runtime has one worker thread per `--threads`, and the same number of
+        // blocking threads on which chunks are checked.
+        let threads = Handle::current().metrics().num_workers();
+        let limiter = Arc::new(Semaphore::new(threads));
+        let mut manifests = vec![];
+
+        for depot in depots {
+            output.println(format_args!("Verifying depot {depot}"));
+
+            let mut manifest = manifest_source
+                .as_ref()
+                .zip(sku.manifests.get(&depot))
+                .map(|(manifest_source, manifest_id)| {
+                    let manifest = manifest_source.read(depot, *manifest_id)?;
+                    if manifest.metadata.depot_id() == depot {
+                        if manifest.metadata.filenames_encrypted() {
+                            output.println(format_args!(
+                                "Manifest {manifest_id} for depot {depot} has encrypted filenames"
+                            ));
+                        }
+                        Ok(manifest)
+                    } else {
+                        Err(anyhow!(
+                            "Manifest {manifest_id} in {} does not belong to depot {depot}",
+                            manifest_source.path().display()
+                        ))
+                    }
+                })
+                .transpose()?;
+
+            let chunkstores = sku
+                .chunkstores
+                .get(&depot)
+                .ok_or(anyhow!("Missing chunkstore for depot {depot}"))?;
+
+            let mut depot_report = DepotReport {
+                depot,
+                manifest: sku.manifests.get(&depot).map(|id| id.to_string()),
+                valid: true,
+                manifest_chunks: None,
+                manifest_signature_valid: None,
+                missing_chunks: vec![],
+                incomplete_files: vec![],
+                chunks: 0,
+                chunkstores: vec![],
+                errors: vec![],
+                warnings: vec![],
+            };
+
+            if let Some(manifest) = &manifest {
+                if manifest.check_crc() == Some(false) {
+                    let error = format!(
+                        "Manifest {} for depot {depot} does not match its CRC",
+                        manifest.metadata.gid_manifest(),
+                    );
+                    output.println(format_args!("- {error}"));
+                    depot_report.errors.push(error);
+                    depot_report.valid = false;
+                }
+
+                if let Some(signing_key) = &keys.signing_key {
+                    let valid = manifest.check_signature(signing_key);
+                    depot_report.manifest_signature_valid = valid;
+                    let error = match valid {
+                        Some(true) => None,
+                        Some(false) => Some("has an invalid signature"),
+                        None => Some("is not signed"),
+                    };
+                    if let Some(error) = error {
+                        let error = format!(
+                            "Manifest {} for depot {depot} {error}",
+                            manifest.metadata.gid_manifest(),
                         );
+                        output.println(format_args!("- {error}"));
+                        depot_report.errors.push(error);
+                        depot_report.valid = false;
                     }
-                    Ok(manifest)
-                } else {
-                    Err(anyhow!(
-                        "{} does not belong to depot {depot}",
-                        manifest_path.display()
-                    ))
                 }
-            })
-            .transpose()?;
+            }
 
-        let chunkstores = sku
-            .chunkstores
-            .get(&depot)
-            .ok_or(anyhow!("Missing chunkstore for depot {depot}"))?;
+            // Decrypt the filenames (now that the CRC and signature have been checked),
+            // to match them against `--include` and `--exclude`, and to report them.
+            if let Some((manifest, depot_key)) = manifest.as_mut().zip(keys.depot_keys.get(&depot))
+            {
+                manifest.decrypt_filenames(depot_key).with_context(|| {
+                    format!("Failed to decrypt filenames of the manifest for depot {depot}")
+                })?;
+            }
 
-        let mut depot_chunks = 0;
+            // With `--include` or `--exclude`, only the chunks of matching files are
+            // verified.
+            let selected_chunks = match &mut manifest {
+                Some(manifest) if filter.is_active() => {
+                    if manifest.metadata.filenames_encrypted() {
+                        return Err(anyhow!(
+                            "Cannot match the files of depot {depot} against --include or \
+                            --exclude, as its manifest's filenames are encrypted; pass its \
+                            depot key",
+                        ));
+                    }
 
-        for res in future::join_all(chunkstores.iter().map(
-            |(&chunkstore_index, &chunkstore_length)| {
-                if let Ok(chunkstore_length) = u64::try_from(chunkstore_length) {
-                    let base_dir = base_dir.clone();
+                    // Only the matching files are checked for missing chunks later.
+                    let total_files = manifest.payload.mappings.len();
+                    manifest
+                        .payload
+                        .mappings
+                        .retain(|file_mapping| filter.matches(file_mapping.filename()));
+                    output.println(format_args!(
+                        "- Verifying the chunks of {} of {total_files} files",
+                        manifest.payload.mappings.len(),
+                    ));
+                    Some(Arc::new(
+                        manifest
+                            .payload
+                            .mappings
+                            .iter()
+                            .flat_map(|file_mapping| &file_mapping.chunks)
+                            .filter_map(|chunk| <[u8; 20]>::try_from(chunk.sha()).ok())
+                            .collect::<HashSet<_>>(),
+                    ))
+                }
+                _ => None,
+            };
+
+            let chunk_options = ChunkOptions {
+                fast: self.fast,
+                jobs: threads,
+                limiter: limiter.clone(),
+                selected_chunks,
+            };
+            let mut results = stream::iter(chunkstores.iter().map(
+                |(&chunkstore_index, &chunkstore_length)| {
+                    // Some SKUs record a chunkstore length of -1, meaning it is unknown.
+                    let chunkstore_length = u64::try_from(chunkstore_length).ok();
+                    let base_dirs = report.disks.clone();
+                    let depot_key = keys.depot_keys.get(&depot).copied();
+                    let chunk_options = chunk_options.clone();
+                    let progress = output.progress.clone();
                     tokio::spawn(async move {
                         verify_chunkstore(
-                            &base_dir,
+                            &base_dirs,
                             depot,
                             chunkstore_index,
                             chunkstore_length,
-                            fast,
+                            depot_key,
+                            &chunk_options,
+                            &progress,
                         )
                         .await
                     })
+                },
+            ))
+            .buffer_unordered(self.jobs());
+
+            while let Some(res) = results.next().await {
+                let mut chunkstore_report = res?;
+                present_chunks.extend(std::mem::take(&mut chunkstore_report.shas));
+                for problem in chunkstore_report
+                    .errors
+                    .iter()
+                    .chain(&chunkstore_report.warnings)
+                {
+                    output.println(format_args!("- {problem}"));
+                }
+                if chunkstore_report.valid {
+                    depot_report.chunks += chunkstore_report.chunks;
                 } else {
-                    // Chunkstore length is -1; no idea what that means.
-                    tokio::spawn(std::future::ready(Some(0)))
+                    depot_report.valid = false;
+                }
+                depot_report.chunkstores.push(chunkstore_report);
+            }
+            depot_report
+                .chunkstores
+                .sort_by_key(|chunkstore| chunkstore.index);
+
+            if let Some(manifest) = manifest {
+                let unique_chunks = manifest.metadata.unique_chunks();
+                depot_report.manifest_chunks = Some(unique_chunks);
+                if unique_chunks != depot_report.chunks {
+                    let warning = format!(
+                        "Depot {depot} has {unique_chunks} chunks in manifest but {} chunks on disk",
+                        depot_report.chunks,
+                    );
+                    output.println(&warning);
+                    depot_report.warnings.push(warning);
+                }
+
+                manifests.push((report.depots.len(), manifest));
+            }
+
+            report.depots.push(depot_report);
+        }
+
+        // Files that use corrupt chunks can't be reconstructed either.
+        let mut usable_chunks = present_chunks.clone();
+        for chunkstore in report.depots.iter().flat_map(|depot| &depot.chunkstores) {
+            for corrupt_chunk in &chunkstore.corrupt_chunks {
+                let mut sha = [0; 20];
+                if hex::decode_to_slice(corrupt_chunk, &mut sha).is_ok() {
+                    usable_chunks.remove(&sha);
                 }
-            },
-        ))
-        .await
-        {
-            if let Some(chunks_read) = res? {
-                depot_chunks += chunks_read;
-            } else {
-                valid = false;
             }
         }
 
-        if let Some(manifest) = manifest {
-            let unique_chunks = manifest.metadata.unique_chunks();
-            if unique_chunks != depot_chunks {
-                println!("Depot {depot} has {unique_chunks} chunks in manifest but {depot_chunks} chunks on disk");
+        for (i, manifest) in manifests {
+            let depot_report = &mut report.depots[i];
+            depot_report.incomplete_files = manifest.verify_chunks_present(&usable_chunks).files;
+            let missing = manifest.verify_chunks_present(&present_chunks);
+            if missing.is_empty() {
+                continue;
+            }
+
+            let error = format!(
+                "Depot {} is missing {} chunks referenced by manifest {}, used by {} files",
+                depot_report.depot,
+                missing.chunks.len(),
+                manifest.metadata.gid_manifest(),
+                missing.files.len(),
+            );
+            output.println(format_args!("- {error}:"));
+            for sha in &missing.chunks {
+                output.println(format_args!("  - {}", hex::encode(sha)));
+            }
+            depot_report.errors.push(error);
+            depot_report.missing_chunks = missing.chunks.iter().map(hex::encode).collect();
+            depot_report.valid = false;
+        }
+
+        if report.depots.iter().all(|depot| depot.valid) {
+            output.println("Depot files match SKU!");
+        }
+
+        Ok(())
+    }
+}
+
+/// Prints human-readable output for a single backup, alongside its progress bar.
+struct Output {
+    quiet: bool,
+    show_progress: bool,
+    progress: ProgressBar,
+}
+
+impl Output {
+    fn new(command: &VerifyBackup) -> Self {
+        Self {
+            quiet: command.quiet(),
+            // Only show a progress bar when a human is watching.
+            show_progress: !command.quiet()
+                && !command.no_progress
+                && std::io::stdout().is_terminal(),
+            progress: ProgressBar::hidden().with_style(
+                ProgressStyle::with_template(
+                    "{wide_bar} {bytes}/{total_bytes} ({bytes_per_sec}, {eta} remaining)",
+                )
+                .expect("valid template"),
+            ),
+        }
+    }
+
+    /// Starts showing the progress bar (if enabled), now that we know how many bytes
+    /// will be read.
+    fn start_progress(&self, total_bytes: u64) {
+        self.progress.set_length(total_bytes);
+        if self.show_progress {
+            self.progress.set_draw_target(ProgressDrawTarget::stdout());
+        }
+    }
+
+    fn println(&self, msg: impl fmt::Display) {
+        if !self.quiet {
+            self.progress.suspend(|| println!("{msg}"));
+        }
+    }
+}
+
+/// The folder of one disk of a backup, along with its disk number and the number of
+/// disks in the backup (if its SKU could be read).
+type DiskDir = (Option<(u32, u32)>, PathBuf);
+
+/// Groups the disks of multi-disk backups together, returning the name and disk
+/// folders of each backup in order of name.
+///
+/// Folders are grouped in the same way that [`Backup::open`] merges disks: their SKUs
+/// must have the same game name and number of disks, and must not disagree on the
+/// manifest of any depot they share. Separate backups of a game (such as of different
+/// builds) are therefore verified separately. A group never contains the same disk
+/// twice, so copies of a backup are also kept apart. Folders whose SKU could not be
+/// read are each verified on their own, so that the failure is attributed to that
+/// folder.
+fn group_disks(
+    backup_dirs: Vec<(PathBuf, Option<StockKeepingUnit>)>,
+) -> Vec<(String, Vec<DiskDir>)> {
+    // The merged depot manifests and disk folders of each backup.
+    let mut groups = BTreeMap::<_, Vec<(BTreeMap<u32, u64>, Vec<DiskDir>)>>::new();
+    for (base_dir, sku) in backup_dirs {
+        match sku {
+            Some(sku) => {
+                let disk = Some((sku.disk, sku.disks));
+                let backups = groups.entry((sku.name, sku.disks)).or_default();
+                match backups.iter_mut().find(|(manifests, dirs)| {
+                    dirs.iter().all(|(d, _)| *d != disk)
+                        && manifests_agree(manifests, &sku.manifests)
+                }) {
+                    Some((manifests, dirs)) => {
+                        manifests.extend(sku.manifests);
+                        dirs.push((disk, base_dir));
+                    }
+                    None => backups.push((sku.manifests, vec![(disk, base_dir)])),
+                }
             }
+            None => groups
+                .entry((base_dir.display().to_string(), 0))
+                .or_default()
+                .push((BTreeMap::new(), vec![(None, base_dir)])),
         }
     }
 
-    if valid {
-        println!("Depot files match SKU!");
+    groups
+        .into_iter()
+        .flat_map(|((name, _), backups)| {
+            backups
+                .into_iter()
+                .map(move |(_, dirs)| (name.clone(), dirs))
+        })
+        .collect()
+}
+
+/// Searches `path` for backup folders, which are identified by containing `sku.sis`
+/// (ignoring case).
+fn find_backups(path: &Path, backups: &mut Vec<PathBuf>) -> std::io::Result<()> {
+    if find_sku(path).is_some() {
+        // Backup folders don't contain other backups.
+        backups.push(path.to_path_buf());
+        return Ok(());
+    }
+
+    let mut entries = std::fs::read_dir(path)?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<Result<Vec<_>, _>>()?;
+    entries.sort();
+
+    for entry in entries {
+        if entry.is_dir() {
+            find_backups(&entry, backups)?;
+        }
     }
 
     Ok(())
 }
 
+/// The number of overlapping or unused ranges to report for each chunkstore.
+const MAX_LAYOUT_PROBLEMS: usize = 10;
+
 async fn verify_chunkstore(
-    base_dir: &Path,
+    base_dirs: &[PathBuf],
     depot: u32,
     chunkstore_index: u32,
-    chunkstore_length: u64,
-    fast: bool,
-) -> Option<u32> {
-    let mut valid = true;
+    chunkstore_length: Option<u64>,
+    depot_key: Option<[u8; 32]>,
+    chunk_options: &ChunkOptions,
+    progress: &ProgressBar,
+) -> ChunkStoreReport {
+    let mut report = ChunkStoreReport {
+        index: chunkstore_index,
+        expected_bytes: chunkstore_length,
+        ..Default::default()
+    };
+
+    check_chunkstore(
+        base_dirs,
+        depot,
+        depot_key,
+        chunk_options,
+        progress,
+        &mut report,
+    )
+    .await;
+
+    report.valid = report.errors.is_empty();
+    report
+}
 
-    let mut chunkstore = match ChunkStore::open(base_dir, depot, chunkstore_index).await {
+async fn check_chunkstore(
+    base_dirs: &[PathBuf],
+    depot: u32,
+    depot_key: Option<[u8; 32]>,
+    chunk_options: &ChunkOptions,
+    progress: &ProgressBar,
+    report: &mut ChunkStoreReport,
+) {
+    let chunkstore = match ChunkStore::open(base_dirs, depot, report.index, depot_key).await {
         Ok(chunkstore) => chunkstore,
         Err(e) => {
-            println!("- {e}");
-            return None;
+            report.errors.push(e.to_string());
+            return;
         }
     };
 
-    if chunkstore.csd_metadata.len() != chunkstore_length {
-        valid = false;
-        println!(
-            "- {} should be {} bytes according to the SKU, but is actually {} bytes",
-            chunkstore.csm_filename,
-            chunkstore_length,
-            chunkstore.csd_metadata.len(),
-        );
-    }
+    report.actual_bytes = Some(chunkstore.csd_metadata.len());
+    let chunkstore_length = match report.expected_bytes {
+        Some(chunkstore_length) => {
+            if chunkstore.csd_metadata.len() != chunkstore_length {
+                report.errors.push(format!(
+                    "{} should be {} bytes according to the SKU, but is actually {} bytes",
+                    chunkstore.csm_filename,
+                    chunkstore_length,
+                    chunkstore.csd_metadata.len(),
+                ));
+            }
+            chunkstore_length
+        }
+        None => {
+            report.warnings.push(format!(
+                "The SKU does not record the size of {}; verifying all {} bytes of it",
+                chunkstore.csd_filename,
+                chunkstore.csd_metadata.len(),
+            ));
+            // The progress bar's total only included chunkstores of known size.
+            progress.inc_length(chunkstore.csd_metadata.len());
+            chunkstore.csd_metadata.len()
+        }
+    };
+
+    let chunks = &chunkstore.csm.chunks;
+    report.chunks = chunks.len() as u32;
+    report.shas = chunks.iter().map(|(sha, _)| *sha).collect();
 
-    let mut bytes_read = 0;
-    let chunks = chunkstore.csm.chunks.clone();
-    let num_chunks = chunks.len();
+    // Overlapping chunks share bytes that can only be valid for one of them, while gaps
+    // are bytes that no chunk uses.
+    let layout_problems = chunkstore.csm.check_layout(chunkstore.csd_metadata.len());
+    for problem in layout_problems.iter().take(MAX_LAYOUT_PROBLEMS) {
+        match problem {
+            LayoutProblem::Overlap {
+                offset,
+                length,
+                chunks: [first, second],
+            } => report.errors.push(format!(
+                "Chunks {} and {} in {} overlap by {length} bytes at offset {offset}",
+                hex::encode(first),
+                hex::encode(second),
+                chunkstore.csd_filename,
+            )),
+            LayoutProblem::Gap { offset, length } => report.warnings.push(format!(
+                "{} has {length} bytes at offset {offset} that are not used by any chunk",
+                chunkstore.csd_filename,
+            )),
+        }
+    }
+    if layout_problems.len() > MAX_LAYOUT_PROBLEMS {
+        report.warnings.push(format!(
+            "{} has {} more overlapping or unused ranges",
+            chunkstore.csd_filename,
+            layout_problems.len() - MAX_LAYOUT_PROBLEMS,
+        ));
+    }
 
-    if fast {
-        return valid.then_some(num_chunks as u32);
+    if chunk_options.fast {
+        progress.inc(chunkstore_length);
+        return;
     }
 
-    for (sha, chunk) in chunks {
-        if let Err(e) = chunkstore.chunk_data(sha).await {
-            valid = false;
-            println!("- {e}");
+    // Decompressing chunks is CPU-bound, so we check several at once (limited across
+    // all chunkstores by the shared semaphore).
+    // Iterating over indices avoids a higher-ranked lifetime in the stream's type,
+    // which would prevent this future from being spawned.
+    let mut results = stream::iter((0..chunks.len()).map(|i| {
+        let (sha, chunk) = &chunks[i];
+        let chunkstore = &chunkstore;
+        async move {
+            if chunk_options
+                .selected_chunks
+                .as_ref()
+                .is_some_and(|selected_chunks| !selected_chunks.contains(sha))
+            {
+                return (sha, chunk, None);
+            }
+            let _permit = chunk_options
+                .limiter
+                .acquire()
+                .await
+                .expect("semaphore is never closed");
+            (sha, chunk, Some(chunkstore.verify_chunk(*sha).await))
+        }
+    }))
+    .buffered(chunk_options.jobs);
+
+    while let Some((sha, chunk, result)) = results.next().await {
+        if let Some(Err(e)) = result {
+            debug!(
+                "Chunk {} in {} is invalid: {e:#}",
+                hex::encode(sha),
+                chunkstore.csd_filename,
+            );
+            report.corrupt_chunks.push(hex::encode(sha));
+            report.errors.push(e.to_string());
         };
-        bytes_read += u64::from(chunk.compressed_length);
+        progress.inc(chunk.compressed_length.into());
     }
+}
 
-    if bytes_read != chunkstore_length {
-        match chunkstore_length.checked_sub(bytes_read) {
-            Some(excess) => println!(
-                "- {} contains {} bytes that do not correspond to chunks in {}",
-                chunkstore.csd_filename, excess, chunkstore.csm_filename,
-            ),
-            None => println!("- {} was read duplicatively", chunkstore.csd_filename),
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+    use std::path::PathBuf;
+
+    use super::group_disks;
+    use crate::formats::sis::StockKeepingUnit;
+
+    fn sku(name: &str, disk: u32, disks: u32, manifest: u64) -> StockKeepingUnit {
+        StockKeepingUnit {
+            name: name.into(),
+            disks,
+            disk,
+            backup: 1,
+            contenttype: 3,
+            apps: vec![100],
+            depots: vec![101],
+            manifests: BTreeMap::from([(101, manifest)]),
+            chunkstores: BTreeMap::new(),
         }
     }
 
-    valid.then_some(num_chunks as u32)
+    fn groups(backup_dirs: Vec<(&str, Option<StockKeepingUnit>)>) -> Vec<(String, Vec<PathBuf>)> {
+        group_disks(
+            backup_dirs
+                .into_iter()
+                .map(|(dir, sku)| (PathBuf::from(dir), sku))
+                .collect(),
+        )
+        .into_iter()
+        .map(|(name, dirs)| (name, dirs.into_iter().map(|(_, dir)| dir).collect()))
+        .collect()
+    }
+
+    #[test]
+    fn groups_disks_of_one_backup() {
+        assert_eq!(
+            groups(vec![
+                ("a/Disk_1", Some(sku("Game", 1, 2, 7))),
+                ("a/Disk_2", Some(sku("Game", 2, 2, 7))),
+            ]),
+            vec![("Game".into(), vec!["a/Disk_1".into(), "a/Disk_2".into()])],
+        );
+    }
+
+    #[test]
+    fn separates_backups_of_the_same_game() {
+        // Two builds of the same game.
+        assert_eq!(
+            groups(vec![
+                ("old", Some(sku("Game", 1, 1, 7))),
+                ("new", Some(sku("Game", 1, 1, 8))),
+            ]),
+            vec![
+                ("Game".into(), vec!["old".into()]),
+                ("Game".into(), vec!["new".into()]),
+            ],
+        );
+
+        // Two copies of the same backup.
+        assert_eq!(
+            groups(vec![
+                ("a", Some(sku("Game", 1, 1, 7))),
+                ("b", Some(sku("Game", 1, 1, 7))),
+            ]),
+            vec![
+                ("Game".into(), vec!["a".into()]),
+                ("Game".into(), vec!["b".into()]),
+            ],
+        );
+    }
+
+    #[test]
+    fn groups_disks_listing_different_depots() {
+        // Each disk only lists the depots it contains, so their manifest maps differ.
+        let mut disk_1 = sku("Game", 1, 2, 7);
+        disk_1.manifests.insert(102, 9);
+        let mut disk_2 = sku("Game", 2, 2, 7);
+        disk_2.manifests = BTreeMap::from([(103, 5)]);
+        assert_eq!(
+            groups(vec![("a/Disk_1", Some(disk_1)), ("a/Disk_2", Some(disk_2))]),
+            vec![("Game".into(), vec!["a/Disk_1".into(), "a/Disk_2".into()])],
+        );
+    }
+
+    #[test]
+    fn keeps_unreadable_skus_apart() {
+        assert_eq!(
+            groups(vec![("a", None), ("b", None)]),
+            vec![
+                ("a".into(), vec!["a".into()]),
+                ("b".into(), vec!["b".into()])
+            ],
+        );
+    }
 }
@@ -0,0 +1,14 @@
+use std::io;
+
+use clap::CommandFactory;
+
+use crate::cli::{Completion, Options};
+
+impl Completion {
+    pub(crate) fn run(self) -> anyhow::Result<()> {
+        let mut command = Options::command();
+        let name = command.get_name().to_string();
+        clap_complete::generate(self.shell, &mut command, name, &mut io::stdout());
+        Ok(())
+    }
+}
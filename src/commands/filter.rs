@@ -0,0 +1,52 @@
+use globset::{GlobBuilder, GlobSet, GlobSetBuilder};
+
+use crate::cli::FilterArgs;
+
+/// Filters depot manifest filenames using `--include` and `--exclude` glob patterns.
+pub(crate) struct FileFilter {
+    include: Option<GlobSet>,
+    exclude: Option<GlobSet>,
+}
+
+impl FileFilter {
+    pub(crate) fn new(args: &FilterArgs) -> anyhow::Result<Self> {
+        Ok(Self {
+            include: build_glob_set(&args.include)?,
+            exclude: build_glob_set(&args.exclude)?,
+        })
+    }
+
+    /// Returns `true` if any patterns were provided.
+    pub(crate) fn is_active(&self) -> bool {
+        self.include.is_some() || self.exclude.is_some()
+    }
+
+    /// Returns `true` if the given manifest filename is matched by at least one include
+    /// pattern (if any were provided), and no exclude patterns.
+    ///
+    /// Both `/` and `\` are treated as path separators, so patterns should always use
+    /// `/`.
+    pub(crate) fn matches(&self, filename: &str) -> bool {
+        let filename = filename.replace('\\', "/");
+        self.include
+            .as_ref()
+            .is_none_or(|include| include.is_match(&filename))
+            && !self
+                .exclude
+                .as_ref()
+                .is_some_and(|exclude| exclude.is_match(&filename))
+    }
+}
+
+fn build_glob_set(patterns: &[String]) -> anyhow::Result<Option<GlobSet>> {
+    if patterns.is_empty() {
+        return Ok(None);
+    }
+
+    let mut builder = GlobSetBuilder::new();
+    for pattern in patterns {
+        // `*` and `?` don't match path separators; `**` does.
+        builder.add(GlobBuilder::new(pattern).literal_separator(true).build()?);
+    }
+    Ok(Some(builder.build()?))
+}
@@ -1,49 +1,195 @@
-use anyhow::anyhow;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::Path;
+
+use anyhow::{anyhow, Context};
 use byte_unit::{Byte, UnitType};
+use serde_json::json;
+use steam_vent::proto::content_manifest::content_manifest_payload::FileMapping;
+use tokio::runtime::Builder;
+use tracing::debug;
+
+use super::backup::{manifest_path, Backup};
+use super::filter::FileFilter;
+use super::steam::Names;
+use crate::{
+    cli::{Inspect, InspectFormat, SortKey},
+    formats::{self, csd::ChunkStore},
+};
+
+/// The kinds of file that `tev inspect` understands.
+#[derive(Debug)]
+enum FileKind {
+    Sku,
+    AppManifest,
+    Backup,
+    ChunkStoreManifest,
+    ChunkStoreData,
+    Manifest,
+}
+
+impl FileKind {
+    /// Detects the kind of file at `path` from its extension, falling back to its
+    /// contents if the extension is not recognized. Folders are treated as backups.
+    fn detect(path: &Path) -> anyhow::Result<Option<Self>> {
+        if path.is_dir() {
+            return Ok(Some(FileKind::Backup));
+        }
+
+        let kind = path.extension().and_then(|ext| {
+            if ext.eq_ignore_ascii_case("sis") {
+                Some(FileKind::Sku)
+            } else if ext.eq_ignore_ascii_case("acf") {
+                Some(FileKind::AppManifest)
+            } else if ext.eq_ignore_ascii_case("csm") {
+                Some(FileKind::ChunkStoreManifest)
+            } else if ext.eq_ignore_ascii_case("csd") {
+                Some(FileKind::ChunkStoreData)
+            } else if ext.eq_ignore_ascii_case("manifest")
+                || (ext.eq_ignore_ascii_case("gz")
+                    && Path::new(path.file_stem().expect("has extension"))
+                        .extension()
+                        .is_some_and(|ext| ext.eq_ignore_ascii_case("manifest")))
+            {
+                Some(FileKind::Manifest)
+            } else {
+                None
+            }
+        });
+        if kind.is_some() {
+            return Ok(kind);
+        }
+
+        let mut header = vec![];
+        File::open(path)?.take(64).read_to_end(&mut header)?;
+        let kind = Self::sniff(&header);
+        if let Some(kind) = &kind {
+            debug!("Detected {} as {kind:?} from its contents", path.display());
+        }
+        Ok(kind)
+    }
 
-use crate::{cli::Inspect, formats};
+    /// Detects the kind of file from the first bytes of its contents.
+    fn sniff(header: &[u8]) -> Option<Self> {
+        if header.starts_with(b"SCFS") {
+            Some(FileKind::ChunkStoreManifest)
+        } else if header.starts_with(&formats::manifest::PROTOBUF_PAYLOAD_MAGIC.to_le_bytes()) {
+            Some(FileKind::Manifest)
+        } else if header.starts_with(b"PK\x03\x04")
+            || header.starts_with(b"VSZa")
+            || header.starts_with(b"VZa")
+            || header.starts_with(&formats::csd::ZSTD_MAGIC)
+        {
+            // The first chunk of an unencrypted chunkstore.
+            Some(FileKind::ChunkStoreData)
+        } else {
+            let text = String::from_utf8_lossy(header);
+            let text = text.trim_start_matches('\u{feff}').trim_start();
+            let starts_with = |section: &str| {
+                text.get(..section.len())
+                    .is_some_and(|s| s.eq_ignore_ascii_case(section))
+            };
+            if starts_with("\"SKU\"") {
+                Some(FileKind::Sku)
+            } else if starts_with("\"AppState\"") {
+                Some(FileKind::AppManifest)
+            } else {
+                None
+            }
+        }
+    }
+}
 
 impl Inspect {
     pub(crate) fn run(&self) -> anyhow::Result<()> {
-        match self.path.extension() {
-            Some(s) if s.eq_ignore_ascii_case("sis") => {
-                let sku = formats::sis::StockKeepingUnit::read(&self.path)?;
+        // Stdin can't be sought, so read it up front and detect its kind from its
+        // contents.
+        let stdin = if self.path == Path::new("-") {
+            let mut data = vec![];
+            io::stdin().lock().read_to_end(&mut data)?;
+            Some(data)
+        } else {
+            None
+        };
+        let kind = match &stdin {
+            Some(data) => match FileKind::sniff(&data[..data.len().min(64)]) {
+                Some(FileKind::Manifest) => Some(FileKind::Manifest),
+                _ => return Err(anyhow!("Only depot manifests can be read from stdin")),
+            },
+            None => FileKind::detect(&self.path)?,
+        };
+        if self.format == InspectFormat::Csv
+            && kind
+                .as_ref()
+                .is_some_and(|kind| !matches!(kind, FileKind::Manifest))
+        {
+            return Err(anyhow!(
+                "--format csv is only supported for depot manifests"
+            ));
+        }
+
+        match kind {
+            Some(FileKind::Sku) => {
+                let sku =
+                    formats::sis::StockKeepingUnit::parse(&std::fs::read_to_string(&self.path)?)?;
+                let names = self.names(&sku)?;
+                if self.format == InspectFormat::Json {
+                    let mut value = sku_json(&sku, &names);
+                    value["type"] = "sku".into();
+                    return print_json(value);
+                }
+
                 println!("SKU: {} (Disk {}/{})", sku.name, sku.disk, sku.disks);
-                println!("Backup: {}", sku.backup);
-                println!("Content type: {}", sku.contenttype);
-                println!("Apps:");
-                for app in sku.apps {
-                    println!("- {app}");
-                }
-                println!("Depots:");
-                for depot in sku.depots {
-                    print!("- {depot}");
-                    if let Some(manifest) = sku.manifests.get(&depot) {
-                        print!(", manifest: {manifest}");
-                    } else {
-                        println!(", missing manifest");
-                    }
-                    if let Some(chunkstores) = sku.chunkstores.get(&depot) {
-                        let size = Byte::from_u64(
-                            chunkstores
-                                .values()
-                                .copied()
-                                .filter_map(|i| u64::try_from(i).ok())
-                                .sum::<u64>(),
-                        )
-                        .get_appropriate_unit(UnitType::Binary);
-                        println!(", Size: {size:#.2}");
-                    } else {
-                        println!(", missing chunkstores");
+                print_sku(&sku, &names);
+            }
+            Some(FileKind::AppManifest) => {
+                let app = formats::acf::AppState::parse(&std::fs::read_to_string(&self.path)?)?;
+                if self.format == InspectFormat::Json {
+                    return print_json(json!({
+                        "type": "app_manifest",
+                        "app": app.app_id,
+                        "name": app.name,
+                        "install_dir": app.install_dir,
+                        "build": app.build_id,
+                        "size_on_disk": app.size_on_disk,
+                        "depots": app.installed_depots.iter().map(|(depot, installed)| json!({
+                            "depot": depot,
+                            // Manifest IDs don't fit in a JavaScript number.
+                            "manifest": installed.manifest.to_string(),
+                            "size": installed.size,
+                            "dlc_app": installed.dlc_app_id,
+                        })).collect::<Vec<_>>(),
+                    }));
+                }
+
+                println!("App: {} ({})", app.app_id, app.name);
+                println!("Install folder: {}", app.install_dir);
+                if let Some(build_id) = app.build_id {
+                    println!("Build: {build_id}");
+                }
+                if let Some(size) = app.size_on_disk {
+                    let size = Byte::from_u64(size).get_appropriate_unit(UnitType::Binary);
+                    println!("Size on disk: {size:#.2}");
+                }
+                println!("Installed depots:");
+                for (depot, installed) in &app.installed_depots {
+                    let size =
+                        Byte::from_u64(installed.size).get_appropriate_unit(UnitType::Binary);
+                    print!(
+                        "- {depot}, manifest: {}, Size: {size:#.2}",
+                        installed.manifest
+                    );
+                    match installed.dlc_app_id {
+                        Some(dlc) => println!(" (DLC {dlc})"),
+                        None => println!(),
                     }
                 }
             }
-            Some(s) if s.eq_ignore_ascii_case("csm") => {
-                let manifest = formats::csm::ChunkStoreManifest::read(&self.path)?;
-                println!("ChunkStore manifest");
-                println!("Encrypted: {}", manifest.is_encrypted);
-                println!("Depot: {}", manifest.depot);
-                println!("Chunks: {}", manifest.chunks.len());
+            Some(FileKind::Backup) => self.inspect_backup()?,
+            Some(FileKind::ChunkStoreManifest) => {
+                let manifest =
+                    formats::csm::ChunkStoreManifest::parse(&std::fs::read(&self.path)?)?;
 
                 let (compressed_size, uncompressed_size) = manifest
                     .chunks
@@ -58,6 +204,22 @@ impl Inspect {
                         (acc_c + c_len, acc_u + u_len)
                     });
 
+                if self.format == InspectFormat::Json {
+                    return print_json(json!({
+                        "type": "csm",
+                        "encrypted": manifest.is_encrypted,
+                        "depot": manifest.depot,
+                        "chunks": manifest.chunks.len(),
+                        "compressed_size": compressed_size,
+                        "uncompressed_size": uncompressed_size,
+                    }));
+                }
+
+                println!("ChunkStore manifest");
+                println!("Encrypted: {}", manifest.is_encrypted);
+                println!("Depot: {}", manifest.depot);
+                println!("Chunks: {}", manifest.chunks.len());
+
                 let compressed_size =
                     Byte::from_u64(compressed_size).get_appropriate_unit(UnitType::Binary);
                 println!("Compressed size: {compressed_size:#.2}");
@@ -66,25 +228,185 @@ impl Inspect {
                     Byte::from_u64(uncompressed_size).get_appropriate_unit(UnitType::Binary);
                 println!("Uncompressed size: {uncompressed_size:#.2}");
             }
-            Some(s) if s.eq_ignore_ascii_case("csd") => {
+            Some(FileKind::ChunkStoreData) => {
                 let filename = self.path.file_stem().expect("present").to_string_lossy();
+                // The depot can't be determined if the file has been renamed.
                 let depot = filename
                     .split('_')
                     .next()
-                    .and_then(|s| s.parse::<u32>().ok())
-                    .ok_or(anyhow!("Invalid CSD name"))?;
+                    .and_then(|s| s.parse::<u32>().ok());
+                let chunkstore_index = filename
+                    .strip_prefix(&format!("{}_depotcache_", depot.unwrap_or_default()))
+                    .and_then(|s| s.parse::<u32>().ok());
 
                 let metadata = std::fs::metadata(&self.path)?;
 
+                // The chunks are listed in the matching chunkstore manifest.
+                let csm_path = self.path.with_extension("csm");
+                let chunkstore = if self.verify {
+                    let (depot, chunkstore_index) =
+                        depot.zip(chunkstore_index).ok_or_else(|| {
+                            anyhow!(
+                                "Cannot determine the depot and chunkstore index of {}; \
+                            --verify requires its original filename",
+                                self.path.display(),
+                            )
+                        })?;
+                    let base_dir = self.path.parent().expect("is a file").to_path_buf();
+                    let depot_key = self.depot_keys.load()?.get(&depot).copied();
+                    let runtime = Builder::new_current_thread().build()?;
+                    Some(runtime.block_on(async {
+                        let chunkstore =
+                            ChunkStore::open(&[base_dir], depot, chunkstore_index, depot_key)
+                                .await?;
+                        verify_chunks(&chunkstore).await?;
+                        Ok::<_, anyhow::Error>(chunkstore)
+                    })?)
+                } else {
+                    None
+                };
+                let csm_read;
+                let csm = if let Some(chunkstore) = &chunkstore {
+                    Some(&chunkstore.csm)
+                } else if csm_path.exists() {
+                    csm_read = formats::csm::ChunkStoreManifest::read(&csm_path)?;
+                    Some(&csm_read)
+                } else {
+                    None
+                };
+                let chunks = csm.map(|csm| {
+                    let mut chunks = csm.chunks.iter().collect::<Vec<_>>();
+                    chunks.sort_by_key(|(_, chunk)| chunk.offset);
+                    chunks
+                });
+                let past_end = |chunk: &formats::csm::Chunk| {
+                    chunk.offset + u64::from(chunk.compressed_length) > metadata.len()
+                };
+
+                if self.format == InspectFormat::Json {
+                    return print_json(json!({
+                        "type": "csd",
+                        "depot": depot,
+                        "compressed_size": metadata.len(),
+                        "chunks": chunks.map(|chunks| {
+                            chunks
+                                .into_iter()
+                                .map(|(sha, chunk)| json!({
+                                    "sha": hex::encode(sha),
+                                    "offset": chunk.offset,
+                                    "compressed_length": chunk.compressed_length,
+                                    "uncompressed_length": chunk.uncompressed_length,
+                                    "past_end": past_end(chunk),
+                                }))
+                                .collect::<Vec<_>>()
+                        }),
+                        "verified": self.verify,
+                    }));
+                }
+
                 println!("ChunkStore data");
-                println!("Depot: {}", depot);
+                match depot {
+                    Some(depot) => println!("Depot: {depot}"),
+                    None => println!("Depot: unknown"),
+                }
 
                 let compressed_size =
                     Byte::from_u64(metadata.len()).get_appropriate_unit(UnitType::Binary);
                 println!("Compressed size: {compressed_size:#.2}");
+
+                match chunks {
+                    Some(chunks) => {
+                        println!("Chunks: {}", chunks.len());
+                        println!(
+                            "  {:40}  {:>12}  {:>10}  {:>12}",
+                            "SHA-1", "Offset", "Compressed", "Uncompressed",
+                        );
+                        for (sha, chunk) in chunks {
+                            print!(
+                                "  {}  {:>12}  {:>10}  {:>12}",
+                                hex::encode(sha),
+                                chunk.offset,
+                                chunk.compressed_length,
+                                chunk.uncompressed_length,
+                            );
+                            if past_end(chunk) {
+                                print!("  (past end of file)");
+                            }
+                            println!();
+                        }
+                        if self.verify {
+                            println!("All chunks are valid");
+                        }
+                    }
+                    None => println!("Chunks: unknown (cannot find {})", csm_path.display()),
+                }
             }
-            Some(s) if s.eq_ignore_ascii_case("manifest") => {
-                let manifest = formats::manifest::Manifest::open(&self.path)?;
+            Some(FileKind::Manifest) => {
+                let mut manifest = match stdin {
+                    Some(data) => formats::manifest::Manifest::read(data.as_slice())?,
+                    None => formats::manifest::Manifest::read_file(File::open(&self.path)?)?,
+                };
+                if let Some(depot_key) = self.depot_keys.load()?.get(&manifest.metadata.depot_id())
+                {
+                    manifest
+                        .decrypt_filenames(depot_key)
+                        .context("Failed to decrypt filenames; is the depot key correct?")?;
+                }
+
+                let filter = FileFilter::new(&self.filter)?;
+                let filename = |file_mapping: &FileMapping| {
+                    if manifest.metadata.filenames_encrypted() {
+                        hex::encode(file_mapping.sha_filename())
+                    } else {
+                        file_mapping.filename().into()
+                    }
+                };
+                let mut files = manifest
+                    .payload
+                    .mappings
+                    .iter()
+                    .filter(|file_mapping| filter.matches(&filename(file_mapping)))
+                    .collect::<Vec<_>>();
+                let min_size = self.min_size.map_or(0, |min_size| min_size.as_u64());
+                let unfiltered_files = files.len();
+                files.retain(|file_mapping| file_mapping.size() >= min_size);
+                let hidden_files = unfiltered_files - files.len();
+                if let Some(prefix) = &self.find_chunk {
+                    return self.find_chunk(&files, filename, prefix);
+                }
+                let summary = ManifestSummary::of(&files);
+                let matched_files = files.len();
+                self.arrange(&mut files, filename);
+
+                if self.format == InspectFormat::Json {
+                    let mut value = manifest.to_json();
+                    value["type"] = "manifest".into();
+                    value["total_files"] = manifest.payload.mappings.len().into();
+                    value["summary"] = json!({
+                        "files": summary.files,
+                        "directories": summary.directories,
+                        "symlinks": summary.symlinks,
+                        "original_size": summary.original_size,
+                        "unique_chunks": summary.unique_chunks,
+                    });
+                    // List the files in the same order as the text output.
+                    let positions = files
+                        .iter()
+                        .enumerate()
+                        .map(|(i, file_mapping)| (filename(file_mapping), i))
+                        .collect::<HashMap<_, _>>();
+                    if let Some(files) = value["files"].as_array_mut() {
+                        let position =
+                            |file: &serde_json::Value| positions.get(file["filename"].as_str()?);
+                        files.retain(|file| position(file).is_some());
+                        files.sort_by_key(|file| position(file).copied());
+                    }
+                    return print_json(value);
+                }
+                if self.format == InspectFormat::Csv {
+                    print_csv(&files, filename);
+                    return Ok(());
+                }
 
                 println!("Manifest: {}", manifest.metadata.gid_manifest());
                 println!("Depot: {}", manifest.metadata.depot_id());
@@ -113,39 +435,557 @@ impl Inspect {
                 }
 
                 println!("Files:");
-                for file_mapping in manifest.payload.mappings {
-                    let d = if file_mapping.flags() & 0b0100_0000 != 0 {
-                        "d"
-                    } else {
-                        "-"
-                    };
-                    let x = if file_mapping.flags() & 0b1_0000_0000 != 0 {
-                        "x"
-                    } else {
-                        "-"
-                    };
+                if self.tree {
+                    FileTree::of(&files, filename).print("");
+                } else {
+                    for file_mapping in &files {
+                        let d = if file_mapping.flags() & 0b0100_0000 != 0 {
+                            "d"
+                        } else if !file_mapping.linktarget().is_empty() {
+                            "l"
+                        } else {
+                            "-"
+                        };
+                        let x = if file_mapping.flags() & 0b1_0000_0000 != 0 {
+                            "x"
+                        } else {
+                            "-"
+                        };
 
-                    let file_size =
-                        Byte::from_u64(file_mapping.size()).get_appropriate_unit(UnitType::Binary);
+                        let file_size = Byte::from_u64(file_mapping.size())
+                            .get_appropriate_unit(UnitType::Binary);
 
+                        println!(
+                            "{d}r-{x} {file_size:>+10.2} {}{}",
+                            filename(file_mapping),
+                            if file_mapping.linktarget().is_empty() {
+                                "".into()
+                            } else {
+                                format!(" {}", file_mapping.linktarget())
+                            },
+                        );
+                    }
+                }
+                if filter.is_active() {
                     println!(
-                        "{d}r-{x} {file_size:>+10.2} {}{}",
-                        if manifest.metadata.filenames_encrypted() {
-                            hex::encode(file_mapping.sha_filename())
-                        } else {
-                            file_mapping.filename().into()
-                        },
-                        if file_mapping.linktarget().is_empty() {
-                            "".into()
-                        } else {
-                            format!(" {}", file_mapping.linktarget())
-                        },
+                        "Matched {unfiltered_files} of {} files",
+                        manifest.payload.mappings.len(),
+                    );
+                }
+                if let Some(min_size) = self.min_size {
+                    println!(
+                        "Hid {hidden_files} files smaller than {:#.2}",
+                        min_size.get_appropriate_unit(UnitType::Binary),
                     );
                 }
+                if files.len() < matched_files {
+                    println!("Listed {} of {matched_files} files", files.len());
+                }
+
+                let original_size =
+                    Byte::from_u64(summary.original_size).get_appropriate_unit(UnitType::Binary);
+                println!(
+                    "Total: {} files, {} directories, {} symlinks, {original_size:#.2}, \
+                    {} unique chunks",
+                    summary.files, summary.directories, summary.symlinks, summary.unique_chunks,
+                );
             }
-            _ => println!("Unknown format"),
+            None => match self.format {
+                InspectFormat::Text => println!("Unknown format"),
+                InspectFormat::Json | InspectFormat::Csv => return Err(anyhow!("Unknown format")),
+            },
         }
 
         Ok(())
     }
 }
+
+/// Totals for the files listed from a depot manifest.
+struct ManifestSummary {
+    files: usize,
+    directories: usize,
+    symlinks: usize,
+    /// The total size of the files.
+    original_size: u64,
+    unique_chunks: usize,
+}
+
+impl ManifestSummary {
+    fn of(files: &[&FileMapping]) -> Self {
+        let mut summary = ManifestSummary {
+            files: 0,
+            directories: 0,
+            symlinks: 0,
+            original_size: 0,
+            unique_chunks: 0,
+        };
+        let mut chunks = HashSet::new();
+        for file_mapping in files {
+            if file_mapping.flags() & 0b0100_0000 != 0 {
+                summary.directories += 1;
+            } else if !file_mapping.linktarget().is_empty() {
+                summary.symlinks += 1;
+            } else {
+                summary.files += 1;
+                summary.original_size += file_mapping.size();
+                chunks.extend(file_mapping.chunks.iter().map(|chunk| chunk.sha()));
+            }
+        }
+        summary.unique_chunks = chunks.len();
+        summary
+    }
+}
+
+/// The files listed from a depot manifest, arranged by their path components.
+#[derive(Default)]
+struct FileTree<'a> {
+    /// The file mapping for this path, if the manifest has one (it may only be implied
+    /// by the paths of its contents).
+    file_mapping: Option<&'a FileMapping>,
+    children: BTreeMap<String, FileTree<'a>>,
+}
+
+impl<'a> FileTree<'a> {
+    fn of(files: &[&'a FileMapping], filename: impl Fn(&FileMapping) -> String) -> Self {
+        let mut root = FileTree::default();
+        for file_mapping in files {
+            let node = manifest_path(&filename(file_mapping)).components().fold(
+                &mut root,
+                |node, component| {
+                    node.children
+                        .entry(component.as_os_str().to_string_lossy().into_owned())
+                        .or_default()
+                },
+            );
+            node.file_mapping = Some(file_mapping);
+        }
+        root
+    }
+
+    fn is_dir(&self) -> bool {
+        self.file_mapping
+            .is_none_or(|file_mapping| file_mapping.flags() & 0b0100_0000 != 0)
+    }
+
+    /// Prints the children of this node, each line starting with `prefix`.
+    fn print(&self, prefix: &str) {
+        for (i, (name, mut child)) in self.children.iter().enumerate() {
+            let last = i + 1 == self.children.len();
+
+            // Collapse chains of directories that only contain one directory.
+            let mut name = name.clone();
+            while child.children.len() == 1 {
+                let (grandchild_name, grandchild) =
+                    child.children.iter().next().expect("has one child");
+                if !grandchild.is_dir() {
+                    break;
+                }
+                name = format!("{name}/{grandchild_name}");
+                child = grandchild;
+            }
+
+            let branch = if last { "└── " } else { "├── " };
+            match child.file_mapping {
+                Some(file_mapping) if !file_mapping.linktarget().is_empty() => {
+                    println!("{prefix}{branch}{name} -> {}", file_mapping.linktarget())
+                }
+                Some(file_mapping) if !child.is_dir() => {
+                    let file_size =
+                        Byte::from_u64(file_mapping.size()).get_appropriate_unit(UnitType::Binary);
+                    println!("{prefix}{branch}{name} ({file_size:#.2})");
+                }
+                _ => println!("{prefix}{branch}{name}/"),
+            }
+
+            child.print(&format!("{prefix}{}", if last { "    " } else { "│   " }));
+        }
+    }
+}
+
+/// The files of a chunkstore found in a backup folder.
+struct ChunkStoreFiles {
+    index: u32,
+    /// The size recorded in the SKU.
+    size: i32,
+    csm: Option<formats::csm::ChunkStoreManifest>,
+    csd_size: Option<u64>,
+}
+
+impl ChunkStoreFiles {
+    /// Returns the total compressed and uncompressed sizes of the chunks in the `.csm`.
+    fn chunk_sizes(&self) -> Option<(u64, u64)> {
+        self.csm.as_ref().map(|csm| {
+            csm.chunks
+                .iter()
+                .fold((0, 0), |(acc_c, acc_u), (_, chunk)| {
+                    (
+                        acc_c + u64::from(chunk.compressed_length),
+                        acc_u + u64::from(chunk.uncompressed_length),
+                    )
+                })
+        })
+    }
+}
+
+impl Inspect {
+    /// Summarizes a backup folder, cross-referencing its SKU with the chunkstore files
+    /// present, and with the cached manifests in `--manifest-dir` if given.
+    fn inspect_backup(&self) -> anyhow::Result<()> {
+        let backup = Backup::open(&self.path, &[])?;
+        let sku = &backup.sku;
+        let names = self.names(sku)?;
+        let manifests = self
+            .manifest_dir
+            .as_deref()
+            .map(formats::manifest::ManifestSource::open)
+            .transpose()?;
+
+        let mut depots = vec![];
+        for depot in &sku.depots {
+            let chunkstores = sku
+                .chunkstores
+                .get(depot)
+                .into_iter()
+                .flatten()
+                .map(|(index, size)| {
+                    Ok(ChunkStoreFiles {
+                        index: *index,
+                        size: *size,
+                        csm: ChunkStore::find_csm(&backup.dirs, *depot, *index)
+                            .ok()
+                            .map(|path| formats::csm::ChunkStoreManifest::read(&path))
+                            .transpose()?,
+                        csd_size: ChunkStore::find_csd(&backup.dirs, *depot, *index)
+                            .ok()
+                            .map(|path| path.metadata().map(|metadata| metadata.len()))
+                            .transpose()?,
+                    })
+                })
+                .collect::<anyhow::Result<Vec<_>>>()?;
+
+            // The number of files in the depot's cached manifest, if it can be found.
+            let manifest_files = manifests
+                .as_ref()
+                .zip(sku.manifests.get(depot))
+                .and_then(|(manifests, manifest_id)| manifests.read(*depot, *manifest_id).ok())
+                .map(|manifest| {
+                    manifest
+                        .payload
+                        .mappings
+                        .iter()
+                        .filter(|file_mapping| file_mapping.flags() & 0b0100_0000 == 0)
+                        .count()
+                });
+
+            depots.push((*depot, chunkstores, manifest_files));
+        }
+
+        if self.format == InspectFormat::Json {
+            let mut value = sku_json(sku, &names);
+            value["type"] = "backup".into();
+            value["dirs"] = json!(backup.dirs);
+            value["missing_disks"] = json!(backup.missing_disks);
+            value["depots"] = depots
+                .iter()
+                .map(|(depot, chunkstores, manifest_files)| {
+                    json!({
+                        "depot": depot,
+                        "name": names.depot(*depot),
+                        // Manifest IDs don't fit in a JavaScript number.
+                        "manifest": sku.manifests.get(depot).map(|m| m.to_string()),
+                        "manifest_files": manifest_files,
+                        "chunkstores": chunkstores.iter().map(|chunkstore| {
+                            let sizes = chunkstore.chunk_sizes();
+                            json!({
+                                "index": chunkstore.index,
+                                "size": chunkstore.size,
+                                "csm": chunkstore.csm.is_some(),
+                                "csd": chunkstore.csd_size.is_some(),
+                                "chunks": chunkstore.csm.as_ref().map(|csm| csm.chunks.len()),
+                                "compressed_size": sizes.map(|(c, _)| c),
+                                "uncompressed_size": sizes.map(|(_, u)| u),
+                            })
+                        }).collect::<Vec<_>>(),
+                    })
+                })
+                .collect();
+            return print_json(value);
+        }
+
+        let mut problems = 0;
+
+        println!("SKU: {} ({} disk(s))", sku.name, sku.disks);
+        if !backup.missing_disks.is_empty() {
+            println!("Missing {}", backup.describe_missing_disks());
+            problems += backup.missing_disks.len();
+        }
+        print_sku(sku, &names);
+
+        println!("Chunkstores:");
+        for (depot, chunkstores, _) in &depots {
+            if !sku.chunkstores.contains_key(depot) {
+                problems += 1;
+            }
+            for chunkstore in chunkstores {
+                print!("- {depot}_depotcache_{}", chunkstore.index);
+                match (&chunkstore.csm, chunkstore.chunk_sizes()) {
+                    (Some(csm), Some((compressed_size, uncompressed_size))) => {
+                        let compressed_size =
+                            Byte::from_u64(compressed_size).get_appropriate_unit(UnitType::Binary);
+                        let uncompressed_size = Byte::from_u64(uncompressed_size)
+                            .get_appropriate_unit(UnitType::Binary);
+                        print!(
+                            ": {} chunks, {compressed_size:#.2} compressed, \
+                            {uncompressed_size:#.2} uncompressed",
+                            csm.chunks.len(),
+                        );
+                    }
+                    _ => {
+                        print!(", missing .csm");
+                        problems += 1;
+                    }
+                }
+                if chunkstore.csd_size.is_none() {
+                    print!(", missing .csd");
+                    problems += 1;
+                }
+                println!();
+            }
+        }
+
+        if let Some(manifests) = &manifests {
+            println!("Manifests:");
+            for (depot, _, manifest_files) in &depots {
+                let Some(manifest_id) = sku.manifests.get(depot) else {
+                    continue;
+                };
+                match manifest_files {
+                    Some(files) => println!("- {depot}_{manifest_id}.manifest: {files} files"),
+                    None => {
+                        println!(
+                            "- {depot}_{manifest_id}.manifest: not found in {}",
+                            manifests.path().display(),
+                        );
+                        problems += 1;
+                    }
+                }
+            }
+        }
+        problems += sku
+            .depots
+            .iter()
+            .filter(|depot| !sku.manifests.contains_key(depot))
+            .count();
+
+        if problems == 0 {
+            println!("No problems found");
+        } else {
+            println!("Found {problems} problem(s)");
+        }
+
+        Ok(())
+    }
+
+    /// Prints the files listed from a depot manifest that use chunks whose hex SHA-1
+    /// digest starts with `prefix`, and the offsets at which they use them.
+    fn find_chunk(
+        &self,
+        files: &[&FileMapping],
+        filename: impl Fn(&FileMapping) -> String,
+        prefix: &str,
+    ) -> anyhow::Result<()> {
+        let matches = files
+            .iter()
+            .flat_map(|file_mapping| {
+                file_mapping
+                    .chunks
+                    .iter()
+                    .map(|chunk| (hex::encode(chunk.sha()), chunk.offset()))
+                    .filter(|(sha, _)| sha.starts_with(prefix))
+                    .map(|(sha, offset)| (filename(file_mapping), offset, sha))
+            })
+            .collect::<Vec<_>>();
+        if matches.is_empty() {
+            return Err(anyhow!("No files use a chunk matching {prefix}"));
+        }
+
+        match self.format {
+            InspectFormat::Text => {
+                for (filename, offset, sha) in matches {
+                    println!("{filename} at offset {offset} (chunk {sha})");
+                }
+            }
+            InspectFormat::Json => {
+                return print_json(json!({
+                    "type": "chunk_matches",
+                    "chunk": prefix,
+                    "matches": matches
+                        .iter()
+                        .map(|(filename, offset, sha)| json!({
+                            "filename": filename,
+                            "offset": offset,
+                            "sha": sha,
+                        }))
+                        .collect::<Vec<_>>(),
+                }));
+            }
+            InspectFormat::Csv => {
+                println!("filename,offset,sha");
+                for (filename, offset, sha) in matches {
+                    println!("{},{offset},{sha}", csv_field(&filename));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Sorts the files listed from a depot manifest according to `--sort` and
+    /// `--reverse`, and then applies `--limit`.
+    fn arrange(&self, files: &mut Vec<&FileMapping>, filename: impl Fn(&FileMapping) -> String) {
+        match self.sort {
+            Some(SortKey::Name) => files.sort_by_cached_key(|file_mapping| filename(file_mapping)),
+            Some(SortKey::Size) => files.sort_by_key(|file_mapping| file_mapping.size()),
+            None => (),
+        }
+        if self.reverse {
+            files.reverse();
+        }
+        if let Some(limit) = self.limit {
+            files.truncate(limit);
+        }
+    }
+
+    /// Looks up the names of the SKU's apps and depots, if `--names` was given.
+    fn names(&self, sku: &formats::sis::StockKeepingUnit) -> anyhow::Result<Names> {
+        if !self.names {
+            return Ok(Names::default());
+        }
+        let runtime = Builder::new_current_thread().enable_all().build()?;
+        Ok(runtime.block_on(Names::lookup(&sku.apps)))
+    }
+}
+
+/// Returns a JSON representation of a SKU.
+fn sku_json(sku: &formats::sis::StockKeepingUnit, names: &Names) -> serde_json::Value {
+    json!({
+        "name": sku.name,
+        "disk": sku.disk,
+        "disks": sku.disks,
+        "backup": sku.backup,
+        "contenttype": sku.contenttype,
+        "apps": sku.apps,
+        "app_names": sku
+            .apps
+            .iter()
+            .filter_map(|app| Some((app, names.app(*app)?)))
+            .collect::<BTreeMap<_, _>>(),
+        "depots": sku.depots.iter().map(|depot| {
+            let chunkstores = sku.chunkstores.get(depot);
+            json!({
+                "depot": depot,
+                "name": names.depot(*depot),
+                // Manifest IDs don't fit in a JavaScript number.
+                "manifest": sku.manifests.get(depot).map(|m| m.to_string()),
+                "chunkstores": chunkstores.map(|chunkstores| {
+                    chunkstores
+                        .iter()
+                        .map(|(index, size)| json!({"index": index, "size": size}))
+                        .collect::<Vec<_>>()
+                }),
+                "size": chunkstores.map(|chunkstores| {
+                    chunkstores
+                        .values()
+                        .copied()
+                        .filter_map(|i| u64::try_from(i).ok())
+                        .sum::<u64>()
+                }),
+            })
+        }).collect::<Vec<_>>(),
+    })
+}
+
+/// Prints the contents of a SKU after its name, flagging depots with missing manifests
+/// or chunkstores.
+fn print_sku(sku: &formats::sis::StockKeepingUnit, names: &Names) {
+    println!("Backup: {}", sku.backup);
+    println!("Content type: {}", sku.contenttype);
+    println!("Apps:");
+    for app in &sku.apps {
+        match names.app(*app) {
+            Some(name) => println!("- {app} ({name})"),
+            None => println!("- {app}"),
+        }
+    }
+    println!("Depots:");
+    for depot in &sku.depots {
+        print!("- {depot}");
+        if let Some(name) = names.depot(*depot) {
+            print!(" ({name})");
+        }
+        if let Some(manifest) = sku.manifests.get(depot) {
+            print!(", manifest: {manifest}");
+        } else {
+            print!(", missing manifest");
+        }
+        if let Some(chunkstores) = sku.chunkstores.get(depot) {
+            let size = Byte::from_u64(
+                chunkstores
+                    .values()
+                    .copied()
+                    .filter_map(|i| u64::try_from(i).ok())
+                    .sum::<u64>(),
+            )
+            .get_appropriate_unit(UnitType::Binary);
+            println!(", Size: {size:#.2}");
+        } else {
+            println!(", missing chunkstores");
+        }
+    }
+}
+
+/// Prints the files listed from a depot manifest as CSV, with a header row.
+fn print_csv(files: &[&FileMapping], filename: impl Fn(&FileMapping) -> String) {
+    println!("filename,size,is_dir,is_executable,is_symlink,linktarget,chunks");
+    for file_mapping in files {
+        println!(
+            "{},{},{},{},{},{},{}",
+            csv_field(&filename(file_mapping)),
+            file_mapping.size(),
+            file_mapping.flags() & 0b0100_0000 != 0,
+            file_mapping.flags() & 0b1_0000_0000 != 0,
+            !file_mapping.linktarget().is_empty(),
+            csv_field(file_mapping.linktarget()),
+            file_mapping.chunks.len(),
+        );
+    }
+}
+
+/// Quotes a CSV field if it contains a separator, quote, or line break.
+fn csv_field(field: &str) -> std::borrow::Cow<'_, str> {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\"")).into()
+    } else {
+        field.into()
+    }
+}
+
+fn print_json(value: serde_json::Value) -> anyhow::Result<()> {
+    println!("{}", serde_json::to_string_pretty(&value)?);
+    Ok(())
+}
+
+/// Decompresses every chunk in a chunkstore and checks its SHA-1 digest, in the order
+/// they are stored, stopping at the first failure.
+async fn verify_chunks(chunkstore: &ChunkStore) -> anyhow::Result<()> {
+    let mut chunks = chunkstore.csm.chunks.iter().collect::<Vec<_>>();
+    chunks.sort_by_key(|(_, chunk)| chunk.offset);
+
+    for (sha, _) in chunks {
+        chunkstore
+            .verify_chunk(*sha)
+            .await
+            .with_context(|| format!("Chunk {} is invalid", hex::encode(sha)))?;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,29 @@
+use std::collections::HashMap;
+
+use anyhow::Context;
+
+use crate::{
+    cli::DepotKeyArgs,
+    formats::{configvdf, keys},
+};
+
+impl DepotKeyArgs {
+    /// Returns the depot keys from `--steam-config`, `--keys`, and `--depot-key`, with
+    /// later sources taking precedence.
+    pub(crate) fn load(&self) -> anyhow::Result<HashMap<u32, [u8; 32]>> {
+        let mut depot_keys = match &self.steam_config {
+            Some(path) => configvdf::read_depot_keys(path)
+                .with_context(|| format!("Failed to read depot keys from {}", path.display()))?,
+            None => HashMap::new(),
+        };
+        if let Some(path) = &self.keys_file {
+            depot_keys.extend(
+                keys::read(path).with_context(|| {
+                    format!("Failed to read depot keys from {}", path.display())
+                })?,
+            );
+        }
+        depot_keys.extend(self.depot_keys.iter().copied());
+        Ok(depot_keys)
+    }
+}
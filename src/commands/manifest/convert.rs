@@ -0,0 +1,134 @@
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+
+use anyhow::Context;
+
+use crate::{
+    cli::{ConvertManifest, ManifestFormat},
+    formats::manifest::Manifest,
+};
+
+impl ConvertManifest {
+    pub(crate) fn run(self) -> anyhow::Result<()> {
+        let mut manifest = Manifest::open(&self.path)?;
+        if let Some(depot_key) = self.depot_keys.load()?.get(&manifest.metadata.depot_id()) {
+            manifest
+                .decrypt_filenames(depot_key)
+                .context("Failed to decrypt filenames; is the depot key correct?")?;
+        }
+
+        let writer: Box<dyn Write> = match &self.output {
+            Some(path) => Box::new(File::create(path)?),
+            None => Box::new(io::stdout().lock()),
+        };
+        let mut writer = BufWriter::new(writer);
+
+        match self.format {
+            ManifestFormat::Text => write_text(&manifest, &mut writer)?,
+        }
+
+        writer.flush()?;
+        Ok(())
+    }
+}
+
+/// Writes the manifest in the text layout used by DepotDownloader.
+fn write_text<W: Write>(manifest: &Manifest, writer: &mut W) -> io::Result<()> {
+    let unique_chunks = manifest
+        .payload
+        .mappings
+        .iter()
+        .flat_map(|file_mapping| file_mapping.chunks.iter().map(|chunk| chunk.sha()))
+        .collect::<HashSet<_>>();
+
+    writeln!(
+        writer,
+        "Content Manifest for Depot {}",
+        manifest.metadata.depot_id(),
+    )?;
+    writeln!(writer)?;
+    writeln!(
+        writer,
+        "Manifest ID / date     : {} / {}",
+        manifest.metadata.gid_manifest(),
+        format_utc(manifest.metadata.creation_time()),
+    )?;
+    writeln!(
+        writer,
+        "Total number of files  : {}",
+        manifest.payload.mappings.len(),
+    )?;
+    writeln!(writer, "Total number of chunks : {}", unique_chunks.len())?;
+    writeln!(
+        writer,
+        "Total bytes on disk    : {}",
+        manifest.metadata.cb_disk_original(),
+    )?;
+    writeln!(
+        writer,
+        "Total bytes compressed : {}",
+        manifest.metadata.cb_disk_compressed(),
+    )?;
+    writeln!(writer)?;
+    writeln!(writer)?;
+    writeln!(
+        writer,
+        "{:>14} {:>6} {:<40} {:>5} Name",
+        "Size", "Chunks", "File SHA", "Flags",
+    )?;
+
+    let mut files = manifest
+        .payload
+        .mappings
+        .iter()
+        .map(|file_mapping| {
+            let name = if manifest.metadata.filenames_encrypted() {
+                hex::encode(file_mapping.sha_filename())
+            } else {
+                file_mapping.filename().into()
+            };
+            (name, file_mapping)
+        })
+        .collect::<Vec<_>>();
+    files.sort_by(|(a, _), (b, _)| a.cmp(b));
+
+    for (name, file_mapping) in files {
+        writeln!(
+            writer,
+            "{:>14} {:>6} {:<40} {:>5x} {}",
+            file_mapping.size(),
+            file_mapping.chunks.len(),
+            hex::encode(file_mapping.sha_content()),
+            file_mapping.flags(),
+            name,
+        )?;
+    }
+
+    Ok(())
+}
+
+/// Formats a Unix timestamp as a UTC date and time.
+fn format_utc(timestamp: u32) -> String {
+    let days = i64::from(timestamp / 86400);
+    let seconds = timestamp % 86400;
+
+    // Convert days since the Unix epoch into a civil date.
+    // http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    format!(
+        "{year:04}-{month:02}-{day:02} {:02}:{:02}:{:02} UTC",
+        seconds / 3600,
+        (seconds / 60) % 60,
+        seconds % 60,
+    )
+}
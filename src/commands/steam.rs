@@ -0,0 +1,394 @@
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::io::{Cursor, Read};
+use std::path::Path;
+
+use anyhow::{anyhow, Context};
+use directories::ProjectDirs;
+use serde::{Deserialize, Serialize};
+use steam_vent::{
+    auth::{
+        AuthConfirmationHandler, ConsoleAuthConfirmationHandler, DeviceConfirmationHandler,
+        FileGuardDataStore,
+    },
+    proto::{
+        steammessages_clientserver_appinfo::{
+            cmsg_client_picsproduct_info_request, CMsgClientPICSProductInfoRequest,
+            CMsgClientPICSProductInfoResponse,
+        },
+        steammessages_contentsystem_steamclient::{
+            CContentServerDirectory_GetManifestRequestCode_Request,
+            CContentServerDirectory_GetServersForSteamPipe_Request,
+        },
+    },
+    Connection, ConnectionTrait, EResult, NetworkError, ServerList,
+};
+use tokio::sync::OnceCell;
+use tracing::{debug, warn};
+
+use crate::{
+    cli::DownloadArgs,
+    formats::{
+        manifest::{Manifest, ManifestSource},
+        sis::StockKeepingUnit,
+        vdf::{self, Value},
+    },
+};
+
+/// The environment variable from which the password for `--steam-user` is read.
+const PASSWORD_VAR: &str = "TEV_STEAM_PASSWORD";
+
+impl DownloadArgs {
+    /// Returns a downloader for missing depot manifests, if `--download-manifests` was
+    /// given.
+    ///
+    /// Steam is only connected to once a manifest needs to be downloaded.
+    pub(crate) fn downloader(&self) -> Option<ManifestDownloader> {
+        self.download_manifests.then(|| ManifestDownloader {
+            user: self.steam_user.clone(),
+            steam: OnceCell::new(),
+        })
+    }
+}
+
+/// Downloads depot manifests from Steam's content servers.
+pub(crate) struct ManifestDownloader {
+    /// The account to log in with, or `None` for an anonymous session.
+    user: Option<String>,
+    steam: OnceCell<Steam>,
+}
+
+struct Steam {
+    connection: Connection,
+    /// Base URLs of the content servers to download manifests from.
+    servers: Vec<String>,
+    client: reqwest::Client,
+}
+
+/// Connects to Steam, logging in to the account `user` if given, or starting an
+/// anonymous session otherwise.
+async fn connect(user: Option<&str>) -> anyhow::Result<Connection> {
+    let password = user
+        .map(|user| {
+            env::var(PASSWORD_VAR)
+                .map_err(|_| anyhow!("Set {PASSWORD_VAR} to the password of Steam account {user}"))
+        })
+        .transpose()?;
+
+    let server_list = ServerList::discover().await?;
+    Ok(match user.zip(password) {
+        Some((user, password)) => {
+            Connection::login(
+                &server_list,
+                user,
+                &password,
+                FileGuardDataStore::user_cache(),
+                ConsoleAuthConfirmationHandler::default().or(DeviceConfirmationHandler),
+            )
+            .await?
+        }
+        None => Connection::anonymous(&server_list).await?,
+    })
+}
+
+/// The outcome of downloading one depot manifest.
+pub(crate) struct Download {
+    pub(crate) depot: u32,
+    pub(crate) manifest_id: u64,
+    pub(crate) result: anyhow::Result<()>,
+}
+
+impl ManifestDownloader {
+    /// Downloads the manifests of `depots` listed in `sku` that are not already in
+    /// `manifests`, and saves them there.
+    ///
+    /// Returns an error if Steam could not be connected to. Manifests that could not be
+    /// downloaded (for example, because the account does not own their depot) are
+    /// reported in the returned list, so the caller can carry on without them.
+    pub(crate) async fn download_missing(
+        &self,
+        sku: &StockKeepingUnit,
+        depots: &[u32],
+        manifests: &ManifestSource,
+    ) -> anyhow::Result<Vec<Download>> {
+        let missing = sku
+            .manifests
+            .iter()
+            .filter(|(depot, manifest_id)| {
+                depots.contains(depot) && !manifests.contains(**depot, **manifest_id)
+            })
+            .collect::<Vec<_>>();
+        if missing.is_empty() {
+            return Ok(vec![]);
+        }
+        let manifest_dir = manifests.dir().ok_or_else(|| {
+            anyhow!("Manifests can only be downloaded into a folder, not a zip archive")
+        })?;
+
+        let steam = self
+            .steam
+            .get_or_try_init(|| Steam::connect(self.user.as_deref()))
+            .await
+            .map_err(|e| anyhow!("Failed to connect to Steam: {e:#}"))?;
+
+        let mut downloads = vec![];
+        for (&depot, &manifest_id) in missing {
+            let result = steam
+                .download(&sku.apps, depot, manifest_id, manifest_dir)
+                .await
+                .map_err(|e| match e.downcast_ref::<NetworkError>() {
+                    Some(NetworkError::ApiError(EResult::AccessDenied)) if self.user.is_none() => e
+                        .context(
+                            "Steam denied access to the depot; use --steam-user to log in \
+                            to an account that owns it",
+                        ),
+                    _ => e,
+                });
+            downloads.push(Download {
+                depot,
+                manifest_id,
+                result,
+            });
+        }
+
+        Ok(downloads)
+    }
+}
+
+impl Steam {
+    async fn connect(user: Option<&str>) -> anyhow::Result<Self> {
+        let connection = connect(user).await?;
+
+        let response = connection
+            .service_method(CContentServerDirectory_GetServersForSteamPipe_Request {
+                cell_id: Some(connection.cell_id()),
+                max_servers: Some(20),
+                ..Default::default()
+            })
+            .await
+            .context("Failed to find content servers")?;
+        let servers = response
+            .servers
+            .iter()
+            .filter(|server| matches!(server.type_(), "SteamCache" | "CDN"))
+            .map(|server| {
+                let scheme = if server.https_support() == "mandatory" {
+                    "https"
+                } else {
+                    "http"
+                };
+                format!("{scheme}://{}", server.host())
+            })
+            .collect::<Vec<_>>();
+        if servers.is_empty() {
+            return Err(anyhow!("Steam did not provide any content servers"));
+        }
+        debug!(?servers, "Found content servers");
+
+        Ok(Steam {
+            connection,
+            servers,
+            client: reqwest::Client::new(),
+        })
+    }
+
+    /// Downloads a depot manifest into `manifest_dir`.
+    async fn download(
+        &self,
+        apps: &[u32],
+        depot: u32,
+        manifest_id: u64,
+        manifest_dir: &Path,
+    ) -> anyhow::Result<()> {
+        // Content servers require a short-lived code to download a manifest, which Steam
+        // only hands out to sessions with access to the depot. The SKU doesn't record
+        // which of its apps each depot belongs to, so try them in turn.
+        let mut request_code = Err(anyhow!("The backup does not list any apps"));
+        for &app_id in apps {
+            request_code = self
+                .connection
+                .service_method(CContentServerDirectory_GetManifestRequestCode_Request {
+                    app_id: Some(app_id),
+                    depot_id: Some(depot),
+                    manifest_id: Some(manifest_id),
+                    app_branch: Some("public".into()),
+                    ..Default::default()
+                })
+                .await
+                .map_err(anyhow::Error::from)
+                .and_then(|response| match response.manifest_request_code() {
+                    // Steam returns no code (rather than an error) when it won't give us
+                    // access.
+                    0 => Err(NetworkError::ApiError(EResult::AccessDenied).into()),
+                    code => Ok(code),
+                });
+            if request_code.is_ok() {
+                break;
+            }
+        }
+        let request_code = request_code.context("Failed to get a manifest request code")?;
+
+        let mut last_err = None;
+        for server in &self.servers {
+            let url = format!("{server}/depot/{depot}/manifest/{manifest_id}/5/{request_code}");
+            debug!(url, "Downloading manifest");
+            match self.fetch(&url).await {
+                Ok(data) => {
+                    let manifest = Manifest::read(data.as_slice())
+                        .context("Content server returned an invalid manifest")?;
+                    if manifest.metadata.depot_id() != depot
+                        || manifest.metadata.gid_manifest() != manifest_id
+                    {
+                        return Err(anyhow!(
+                            "Content server returned the wrong manifest ({} for depot {})",
+                            manifest.metadata.gid_manifest(),
+                            manifest.metadata.depot_id(),
+                        ));
+                    }
+
+                    // Save the manifest as it was served, so that its CRC and signature
+                    // can still be checked.
+                    let path = manifest_dir.join(format!("{depot}_{manifest_id}.manifest"));
+                    fs::write(&path, data)
+                        .with_context(|| format!("Failed to write {}", path.display()))?;
+                    return Ok(());
+                }
+                Err(e) => {
+                    debug!(url, "Failed to download manifest: {e:#}");
+                    last_err = Some(e);
+                }
+            }
+        }
+        Err(last_err
+            .expect("at least one server")
+            .context("Failed to download manifest"))
+    }
+
+    /// Fetches a manifest from a content server, which serves it in a zip archive.
+    async fn fetch(&self, url: &str) -> anyhow::Result<Vec<u8>> {
+        let response = self.client.get(url).send().await?.error_for_status()?;
+        let body = response.bytes().await?;
+
+        let mut archive = zip::ZipArchive::new(Cursor::new(body))?;
+        if archive.len() != 1 {
+            return Err(anyhow!(
+                "Manifest archive contains {} files instead of one",
+                archive.len()
+            ));
+        }
+        let mut data = vec![];
+        archive.by_index(0)?.read_to_end(&mut data)?;
+        Ok(data)
+    }
+}
+
+/// The names of Steam apps and their depots.
+#[derive(Default, Deserialize, Serialize)]
+pub(crate) struct Names {
+    apps: BTreeMap<u32, String>,
+    depots: BTreeMap<u32, String>,
+}
+
+impl Names {
+    /// Looks up the names of `apps` and of their depots.
+    ///
+    /// Names are cached in the user's cache directory, and only apps that aren't in the
+    /// cache are looked up on Steam. If that fails (for example, when offline), the
+    /// names that could not be found are left out.
+    pub(crate) async fn lookup(apps: &[u32]) -> Self {
+        let cache_path =
+            ProjectDirs::from("", "", "tev").map(|dirs| dirs.cache_dir().join("names.json"));
+        let mut names = cache_path
+            .as_deref()
+            .and_then(|path| fs::read(path).ok())
+            .and_then(|data| serde_json::from_slice::<Names>(&data).ok())
+            .unwrap_or_default();
+
+        let missing = apps
+            .iter()
+            .copied()
+            .filter(|app| !names.apps.contains_key(app))
+            .collect::<Vec<_>>();
+        if missing.is_empty() {
+            return names;
+        }
+
+        match names.fetch(&missing).await {
+            Ok(()) => {
+                if let Some(path) = cache_path {
+                    if let Err(e) = names.save(&path) {
+                        debug!("Failed to cache names in {}: {e}", path.display());
+                    }
+                }
+            }
+            Err(e) => warn!("Failed to look up names on Steam: {e:#}"),
+        }
+
+        names
+    }
+
+    pub(crate) fn app(&self, app: u32) -> Option<&str> {
+        self.apps.get(&app).map(String::as_str)
+    }
+
+    pub(crate) fn depot(&self, depot: u32) -> Option<&str> {
+        self.depots.get(&depot).map(String::as_str)
+    }
+
+    /// Adds the names in the public app info of `apps`.
+    async fn fetch(&mut self, apps: &[u32]) -> anyhow::Result<()> {
+        let connection = connect(None).await?;
+        let response: CMsgClientPICSProductInfoResponse = connection
+            .job(CMsgClientPICSProductInfoRequest {
+                apps: apps
+                    .iter()
+                    .map(|&app| cmsg_client_picsproduct_info_request::AppInfo {
+                        appid: Some(app),
+                        only_public_obsolete: Some(true),
+                        ..Default::default()
+                    })
+                    .collect(),
+                single_response: Some(true),
+                ..Default::default()
+            })
+            .await?;
+
+        for app_info in &response.apps {
+            // The app info is a KeyValues document, terminated by a NUL byte.
+            let buffer = app_info.buffer();
+            let buffer = buffer.strip_suffix(&[0]).unwrap_or(buffer);
+            let Some(info) = std::str::from_utf8(buffer)
+                .ok()
+                .and_then(|text| vdf::parse(text).ok())
+            else {
+                debug!(app = app_info.appid(), "Could not parse app info");
+                continue;
+            };
+            let info = info.get("appinfo").unwrap_or(&info);
+
+            if let Some(Value::String(name)) = info.get("common").and_then(|c| c.get("name")) {
+                self.apps.insert(app_info.appid(), name.clone());
+            }
+            if let Some(Value::Dict(depots)) = info.get("depots") {
+                for (depot, value) in depots {
+                    if let Some((depot, Value::String(name))) =
+                        depot.parse().ok().zip(value.get("name"))
+                    {
+                        self.depots.insert(depot, name.clone());
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn save(&self, path: &Path) -> anyhow::Result<()> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(path, serde_json::to_vec(self)?)?;
+        Ok(())
+    }
+}
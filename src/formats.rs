@@ -1,4 +1,8 @@
-pub(crate) mod csd;
-pub(crate) mod csm;
-pub(crate) mod manifest;
-pub(crate) mod sis;
+pub mod acf;
+pub mod configvdf;
+pub mod csd;
+pub mod csm;
+pub mod keys;
+pub mod manifest;
+pub mod sis;
+pub mod vdf;
@@ -0,0 +1,133 @@
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use anyhow::anyhow;
+
+use super::vdf;
+
+/// The install state of a Steam app, from an `appmanifest_<appid>.acf` file in a Steam
+/// library's `steamapps` folder.
+#[derive(Debug)]
+pub struct AppState {
+    pub app_id: u32,
+    pub name: String,
+    /// The name of the app's folder under `steamapps/common`.
+    pub install_dir: String,
+    pub build_id: Option<u32>,
+    pub size_on_disk: Option<u64>,
+    pub installed_depots: BTreeMap<u32, InstalledDepot>,
+}
+
+/// A depot that is installed as part of an app.
+#[derive(Debug)]
+pub struct InstalledDepot {
+    pub manifest: u64,
+    pub size: u64,
+    /// The DLC that the depot belongs to, if it is not part of the base app.
+    pub dlc_app_id: Option<u32>,
+}
+
+impl AppState {
+    pub fn read(path: &Path) -> anyhow::Result<Self> {
+        if !path
+            .extension()
+            .is_some_and(|s| s.eq_ignore_ascii_case("acf"))
+        {
+            return Err(anyhow!("App manifest file does not have extension .acf"));
+        }
+
+        Self::parse(&std::fs::read_to_string(path)?)
+    }
+
+    /// Parses an app manifest from its contents.
+    pub fn parse(data: &str) -> anyhow::Result<Self> {
+        let root =
+            vdf::parse(data).map_err(|e| anyhow!("Failed to parse app manifest: {:?}", e))?;
+        let app_state = root
+            .get("AppState")
+            .ok_or_else(|| anyhow!("Failed to parse app manifest: missing \"AppState\" section"))?;
+
+        read::app_state(app_state)
+    }
+}
+
+mod read {
+    use std::{collections::BTreeMap, fmt, str::FromStr};
+
+    use anyhow::anyhow;
+
+    use super::{AppState, InstalledDepot};
+    use crate::formats::vdf::Value;
+
+    /// Reads the fields of an app manifest, which may appear in any order. Unknown
+    /// fields are skipped.
+    pub(super) fn app_state(app_state: &Value) -> anyhow::Result<AppState> {
+        Ok(AppState {
+            app_id: parsed_field(app_state, "appid")?,
+            name: string(field(app_state, "name")?, "name")?.into(),
+            install_dir: string(field(app_state, "installdir")?, "installdir")?.into(),
+            build_id: optional_field(app_state, "buildid")?,
+            size_on_disk: optional_field(app_state, "SizeOnDisk")?,
+            installed_depots: match app_state.get("InstalledDepots") {
+                Some(Value::Dict(depots)) => depots
+                    .iter()
+                    .map(|(depot, value)| {
+                        Ok((
+                            parsed(depot, "InstalledDepots")?,
+                            InstalledDepot {
+                                manifest: parsed_field(value, "manifest")?,
+                                size: parsed_field(value, "size")?,
+                                dlc_app_id: optional_field(value, "dlcappid")?,
+                            },
+                        ))
+                    })
+                    .collect::<anyhow::Result<_>>()?,
+                Some(Value::String(_)) => {
+                    return Err(anyhow!(
+                        "\"InstalledDepots\" in app manifest should be a dictionary"
+                    ))
+                }
+                // Apps that have never finished installing have no depots yet.
+                None => BTreeMap::new(),
+            },
+        })
+    }
+
+    fn field<'a>(value: &'a Value, key: &str) -> anyhow::Result<&'a Value> {
+        value
+            .get(key)
+            .ok_or_else(|| anyhow!("App manifest is missing \"{key}\""))
+    }
+
+    fn parsed_field<T: FromStr>(value: &Value, key: &str) -> anyhow::Result<T>
+    where
+        T::Err: fmt::Display,
+    {
+        parsed(string(field(value, key)?, key)?, key)
+    }
+
+    fn optional_field<T: FromStr>(value: &Value, key: &str) -> anyhow::Result<Option<T>>
+    where
+        T::Err: fmt::Display,
+    {
+        value
+            .get(key)
+            .map(|v| parsed(string(v, key)?, key))
+            .transpose()
+    }
+
+    fn string<'a>(value: &'a Value, key: &str) -> anyhow::Result<&'a str> {
+        match value {
+            Value::String(s) => Ok(s),
+            Value::Dict(_) => Err(anyhow!("\"{key}\" in app manifest should be a string")),
+        }
+    }
+
+    fn parsed<T: FromStr>(s: &str, key: &str) -> anyhow::Result<T>
+    where
+        T::Err: fmt::Display,
+    {
+        s.parse()
+            .map_err(|e| anyhow!("Invalid value \"{s}\" for \"{key}\" in app manifest: {e}"))
+    }
+}
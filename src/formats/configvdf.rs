@@ -0,0 +1,36 @@
+use std::collections::HashMap;
+use std::path::Path;
+
+use anyhow::anyhow;
+
+use super::{
+    keys,
+    vdf::{self, Value},
+};
+
+/// Reads the depot keys cached by the Steam client in its `config/config.vdf` file.
+///
+/// Depots without a (valid) `DecryptionKey` are skipped.
+pub fn read_depot_keys(path: &Path) -> anyhow::Result<HashMap<u32, [u8; 32]>> {
+    let data = std::fs::read_to_string(path)?;
+
+    let root = vdf::parse(&data).map_err(|e| anyhow!("Failed to parse config.vdf: {:?}", e))?;
+
+    let depots = ["InstallConfigStore", "Software", "Valve", "Steam", "depots"]
+        .into_iter()
+        .try_fold(&root, |value, key| value.get(key));
+
+    Ok(match depots {
+        Some(Value::Dict(depots)) => depots
+            .iter()
+            .filter_map(|(depot, entry)| {
+                let depot = depot.parse().ok()?;
+                match entry.get("DecryptionKey")? {
+                    Value::String(key) => keys::parse_key(key).ok().map(|key| (depot, key)),
+                    Value::Dict(_) => None,
+                }
+            })
+            .collect(),
+        _ => HashMap::new(),
+    })
+}
@@ -1,45 +1,59 @@
 use std::collections::HashMap;
-use std::fs::Metadata;
-use std::io::{Cursor, Read, SeekFrom};
-use std::path::Path;
+use std::fs::{self, File, Metadata};
+use std::io::{self, Cursor, Read, Write};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 use anyhow::anyhow;
+use memmap2::Mmap;
 use sha1::{Digest, Sha1};
-use tokio::io::AsyncReadExt;
-use tokio::{
-    fs::File,
-    io::{AsyncSeekExt, BufReader},
-};
-use zip::ZipArchive;
-
-use super::csm::ChunkStoreManifest;
-
-pub(crate) struct ChunkStore {
-    pub(crate) csm: ChunkStoreManifest,
-    csd: BufReader<File>,
-    pub(crate) csm_filename: String,
-    pub(crate) csd_filename: String,
-    pub(crate) csd_metadata: Metadata,
+use tracing::info;
+use zip::{write::SimpleFileOptions, CompressionMethod, ZipArchive, ZipWriter};
+
+use super::csm::{Chunk, ChunkStoreManifest};
+
+pub struct ChunkStore {
+    pub csm: ChunkStoreManifest,
+    csd: CsdReader,
+    pub csm_filename: String,
+    pub csd_filename: String,
+    pub csd_metadata: Metadata,
     chunk_map: HashMap<[u8; 20], usize>,
-    position: u64,
-    buffer: Vec<u8>,
+    depot_key: Option<[u8; 32]>,
+}
+
+/// How chunks are read from a `.csd` file.
+///
+/// Neither variant has a file cursor, so chunks can be read concurrently.
+#[derive(Clone)]
+enum CsdReader {
+    /// The file is memory-mapped, so chunks can be sliced out of it directly.
+    Mapped(Arc<Mmap>),
+    /// The file could not be mapped, so chunks are read with positioned reads.
+    Unmapped(Arc<File>),
 }
 
 impl ChunkStore {
-    pub(crate) async fn open(
-        base_dir: &Path,
+    /// Opens a chunkstore of a backup.
+    ///
+    /// The `.csm` file is found with [`Self::find_csm`].
+    pub async fn open(
+        base_dirs: &[PathBuf],
         depot: u32,
         chunkstore_index: u32,
+        depot_key: Option<[u8; 32]>,
     ) -> anyhow::Result<Self> {
-        let csm_filename = format!("{depot}_depotcache_{chunkstore_index}.csm");
-        let csm_path = base_dir.join(&csm_filename);
+        let csm_path = Self::find_csm(base_dirs, depot, chunkstore_index)?;
+        let csm_filename = file_name(&csm_path);
+
+        // The data file may not have the same case as the manifest.
         let csd_path = csm_path.with_extension("csd");
-        let csd_filename = csd_path
-            .file_name()
-            .expect("exists")
-            .to_str()
-            .expect("valid")
-            .into();
+        let csd_path =
+            find_ignoring_case(csm_path.parent().expect("exists"), &file_name(&csd_path))
+                .unwrap_or(csd_path);
+        let csd_filename = file_name(&csd_path);
 
         let csm = ChunkStoreManifest::read(&csm_path)?;
         if csm.depot != depot {
@@ -49,15 +63,27 @@ impl ChunkStore {
                 csm.depot,
             ));
         }
-        if csm.is_encrypted {
+        if csm.is_encrypted && depot_key.is_none() {
             return Err(anyhow!(
-                "{} is encrypted, which should not be the case for backups.",
+                "{} is encrypted, but no key was provided for depot {}",
                 csm_filename,
+                depot,
             ));
         }
+        // Chunks are only encrypted if the chunkstore says so.
+        let depot_key = depot_key.filter(|_| csm.is_encrypted);
 
-        let csd = File::open(&csd_path).await?;
-        let csd_metadata = csd.metadata().await?;
+        let csd = File::open(&csd_path)?;
+        let csd_metadata = csd.metadata()?;
+
+        // Safety: we assume that the backup is not modified while tev reads it. The chunk
+        // digests don't make this safe: if the file is truncated while it is mapped, reading
+        // the missing pages raises SIGBUS before any digest is checked. Other programs
+        // writing to the file would likewise break the aliasing guarantees of `&[u8]`.
+        let csd = match unsafe { Mmap::map(&csd) } {
+            Ok(mmap) => CsdReader::Mapped(Arc::new(mmap)),
+            Err(_) => CsdReader::Unmapped(Arc::new(csd)),
+        };
 
         let chunk_map = csm
             .chunks
@@ -68,47 +94,184 @@ impl ChunkStore {
 
         Ok(Self {
             csm,
-            csd: BufReader::new(csd),
+            csd,
             csm_filename,
             csd_filename,
             csd_metadata,
             chunk_map,
-            position: 0,
+            depot_key,
+        })
+    }
+
+    /// Finds the `.csm` file of a chunkstore of a backup.
+    ///
+    /// The disks of a multi-disk backup are searched in order for the chunkstore. Files
+    /// are matched ignoring case, and each of the names used by Steam and by other
+    /// backup tools is tried in turn.
+    pub fn find_csm(
+        base_dirs: &[PathBuf],
+        depot: u32,
+        chunkstore_index: u32,
+    ) -> anyhow::Result<PathBuf> {
+        find_chunkstore_file(base_dirs, depot, chunkstore_index, "csm")
+    }
+
+    /// Finds the `.csd` file of a chunkstore of a backup, in the same way as
+    /// [`Self::find_csm`].
+    pub fn find_csd(
+        base_dirs: &[PathBuf],
+        depot: u32,
+        chunkstore_index: u32,
+    ) -> anyhow::Result<PathBuf> {
+        find_chunkstore_file(base_dirs, depot, chunkstore_index, "csd")
+    }
+
+    /// Returns an iterator over the chunks in this chunkstore, in the order they are
+    /// stored in the `.csd` file.
+    pub fn iter_chunks(&self) -> Chunks<'_> {
+        let mut order = (0..self.csm.chunks.len()).collect::<Vec<_>>();
+        order.sort_by_key(|&i| self.csm.chunks[i].1.offset);
+        Chunks {
+            chunkstore: self,
+            order: order.into_iter(),
             buffer: vec![],
+        }
+    }
+
+    pub async fn chunk_data(&self, sha: [u8; 20]) -> anyhow::Result<Vec<u8>> {
+        self.chunk_data_in(sha, vec![]).await
+    }
+
+    /// Reads a chunk like [`Self::chunk_data`], but decompresses it into `buffer`
+    /// (replacing its contents) to reuse its allocation.
+    pub async fn chunk_data_in(&self, sha: [u8; 20], buffer: Vec<u8>) -> anyhow::Result<Vec<u8>> {
+        self.check_chunk(sha, move |body, uncompressed_length, _| {
+            decompress_and_verify(body, uncompressed_length, sha, buffer)
+        })
+        .await
+    }
+
+    /// Reads a chunk like [`Self::chunk_data`], but decompresses it directly into the
+    /// start of `buf`, returning the chunk's length.
+    ///
+    /// The chunk is read on the calling thread, so this should not be called from an
+    /// async task. `buf` must be at least as long as the chunk. If the chunk does not
+    /// match its digest, an error is returned and the contents of `buf` are unspecified.
+    pub fn chunk_data_into(&self, sha: [u8; 20], buf: &mut [u8]) -> anyhow::Result<usize> {
+        let (chunk, range) = self.locate_chunk(sha)?;
+        let uncompressed_length = usize::try_from(chunk.uncompressed_length)?;
+        if buf.len() < uncompressed_length {
+            return Err(anyhow!(
+                "Buffer of {} bytes is too small for chunk of {uncompressed_length} bytes",
+                buf.len(),
+            ));
+        }
+        let buf = &mut buf[..uncompressed_length];
+
+        let checked = read_body(&self.csd, range, self.depot_key, |body, _| {
+            decompress_and_verify_into(body, uncompressed_length, sha, buf)
+        })?;
+        self.checked(chunk, checked)?;
+        Ok(uncompressed_length)
+    }
+
+    /// Checks that a chunk decompresses to the data its digest describes.
+    ///
+    /// Unlike [`Self::chunk_data`], the decompressed data is hashed as it is produced
+    /// instead of being kept in memory.
+    pub async fn verify_chunk(&self, sha: [u8; 20]) -> anyhow::Result<()> {
+        self.check_chunk(sha, move |body, uncompressed_length, _| {
+            decompress_and_hash(body, uncompressed_length, sha)
+        })
+        .await
+    }
+
+    /// Checks a chunk like [`Self::chunk_data`], measuring how long each stage takes.
+    pub async fn time_chunk(&self, sha: [u8; 20]) -> anyhow::Result<ChunkTimings> {
+        self.check_chunk(sha, move |body, uncompressed_length, read| {
+            // A memory-mapped body is only read from disk when it is first accessed.
+            let start = Instant::now();
+            let body = body.to_vec();
+            let read = read + start.elapsed();
+
+            let compression = ChunkCompression::detect(&body, uncompressed_length, sha)?;
+            let start = Instant::now();
+            let mut data = Vec::with_capacity(uncompressed_length);
+            let decompressed = decompress(&body, compression, uncompressed_length, &mut data)?;
+            let decompress = start.elapsed();
+            if decompressed != uncompressed_length as u64 {
+                return Ok(Checked::WrongLength);
+            }
+
+            let start = Instant::now();
+            let digest = Sha1::digest(&data);
+            let hash = start.elapsed();
+            if digest != sha.into() {
+                return Ok(Checked::WrongDigest);
+            }
+
+            Ok(Checked::Valid {
+                data: ChunkTimings {
+                    compression: compression.name(),
+                    compressed_length: body.len() as u64,
+                    uncompressed_length: uncompressed_length as u64,
+                    read,
+                    decompress,
+                    hash,
+                },
+            })
         })
+        .await
     }
 
-    pub(crate) async fn chunk_data(&mut self, sha: [u8; 20]) -> anyhow::Result<Vec<u8>> {
+    /// Reads and decrypts the compressed body of a chunk, and passes it to `check` in a
+    /// blocking task, along with the time taken to read and decrypt it.
+    async fn check_chunk<T: Send + 'static>(
+        &self,
+        sha: [u8; 20],
+        check: impl FnOnce(&[u8], usize, Duration) -> anyhow::Result<Checked<T>> + Send + 'static,
+    ) -> anyhow::Result<T> {
+        let (chunk, range) = self.locate_chunk(sha)?;
+        let uncompressed_length = usize::try_from(chunk.uncompressed_length)?;
+        let depot_key = self.depot_key;
+
+        let csd = self.csd.clone();
+        let checked = tokio::task::spawn_blocking(move || {
+            read_body(&csd, range, depot_key, |body, read| {
+                check(body, uncompressed_length, read)
+            })
+        })
+        .await??;
+
+        self.checked(chunk, checked)
+    }
+
+    /// Returns the chunk with the given digest, and the range of the `.csd` file that
+    /// holds its compressed body.
+    fn locate_chunk(&self, sha: [u8; 20]) -> anyhow::Result<(&Chunk, Range<usize>)> {
         let (_, chunk) = self
             .csm
             .chunks
             .get(*self.chunk_map.get(&sha).ok_or(anyhow!("Unknown chunk"))?)
             .expect("correct by construction");
 
-        // Read the chunk.
-        if chunk.offset != self.position {
-            // The chunk is not sequential in the file. Discard the buffer and seek.
-            self.csd.seek(SeekFrom::Start(chunk.offset)).await?;
-            self.position = chunk.offset;
+        let start = usize::try_from(chunk.offset)?;
+        let range = start..start + usize::try_from(chunk.compressed_length)?;
+        if u64::try_from(range.end)? > self.csd_metadata.len() {
+            return Err(anyhow!(
+                "Chunk in {} at offset {} extends past the end of the file",
+                self.csd_filename,
+                chunk.offset,
+            ));
         }
-        self.buffer.resize(chunk.compressed_length.try_into()?, 0);
-        self.csd.read_exact(&mut self.buffer).await?;
-        self.position += u64::from(chunk.compressed_length);
-
-        // Grab the buffer so we can move it to a blocking thread.
-        let compressed = std::mem::take(&mut self.buffer);
-        let uncompressed_length = usize::try_from(chunk.uncompressed_length)?;
+        Ok((chunk, range))
+    }
 
-        match tokio::task::spawn_blocking(move || {
-            decompress_and_verify(compressed, uncompressed_length, sha)
-        })
-        .await??
-        {
-            Checked::Valid { compressed, data } => {
-                // Put the buffer back to reuse for the next chunk.
-                let _ = std::mem::replace(&mut self.buffer, compressed);
-                Ok(data)
-            }
+    /// Turns the result of checking `chunk` into its data, or an error describing why
+    /// it is invalid.
+    fn checked<T>(&self, chunk: &Chunk, checked: Checked<T>) -> anyhow::Result<T> {
+        match checked {
+            Checked::Valid { data } => Ok(data),
             Checked::WrongLength => Err(anyhow!(
                 "Chunk in {} at offset {} does not match uncompressed length in {}",
                 self.csd_filename,
@@ -125,45 +288,460 @@ impl ChunkStore {
     }
 }
 
+/// Reads and decrypts the compressed body of a chunk from `range` of the `.csd` file,
+/// and passes it to `f` along with the time taken to read and decrypt it.
+fn read_body<T>(
+    csd: &CsdReader,
+    range: Range<usize>,
+    depot_key: Option<[u8; 32]>,
+    f: impl FnOnce(&[u8], Duration) -> anyhow::Result<T>,
+) -> anyhow::Result<T> {
+    let start = Instant::now();
+    let read_buf;
+    let compressed = match csd {
+        CsdReader::Mapped(mmap) => &mmap[range],
+        CsdReader::Unmapped(file) => {
+            let mut buf = vec![0; range.len()];
+            read_exact_at(file, &mut buf, range.start as u64)?;
+            read_buf = buf;
+            &read_buf[..]
+        }
+    };
+
+    // Decrypt the chunk if necessary.
+    let decrypted;
+    let body = match &depot_key {
+        Some(depot_key) => {
+            decrypted =
+                steam_vent_crypto::symmetric_decrypt_without_hmac(compressed.into(), depot_key)?;
+            &decrypted[..]
+        }
+        None => compressed,
+    };
+
+    f(body, start.elapsed())
+}
+
+/// How long each stage of checking a chunk took, as measured by
+/// [`ChunkStore::time_chunk`].
+#[derive(Clone, Copy, Debug)]
+pub struct ChunkTimings {
+    /// The chunk's compression format: `VZ`, `PK`, `VSZ`, `zstd`, or `stored`.
+    pub compression: &'static str,
+    pub compressed_length: u64,
+    pub uncompressed_length: u64,
+    /// The time taken to read (and if necessary, decrypt) the compressed chunk.
+    pub read: Duration,
+    pub decompress: Duration,
+    /// The time taken to compute the SHA-1 digest of the decompressed chunk.
+    pub hash: Duration,
+}
+
+/// An iterator over the chunks of a [`ChunkStore`], created by
+/// [`ChunkStore::iter_chunks`].
+pub struct Chunks<'a> {
+    chunkstore: &'a ChunkStore,
+    /// Indices into the chunkstore's chunks, in file order.
+    order: std::vec::IntoIter<usize>,
+    buffer: Vec<u8>,
+}
+
+impl Chunks<'_> {
+    /// Reads the next chunk, returning its SHA-1 digest and decompressed data.
+    ///
+    /// The data is decompressed into a buffer that is reused by the next call.
+    pub async fn next_chunk(&mut self) -> Option<anyhow::Result<([u8; 20], &[u8])>> {
+        let (sha, _) = self.chunkstore.csm.chunks[self.order.next()?];
+        let buffer = std::mem::take(&mut self.buffer);
+        Some(
+            self.chunkstore
+                .chunk_data_in(sha, buffer)
+                .await
+                .map(|data| {
+                    self.buffer = data;
+                    (sha, &self.buffer[..])
+                }),
+        )
+    }
+
+    /// Returns the number of chunks that have not yet been read.
+    pub fn remaining(&self) -> usize {
+        self.order.len()
+    }
+}
+
+/// Reads exactly `buf.len()` bytes from `file` at `offset`, without using the file's
+/// cursor.
+fn read_exact_at(file: &File, buf: &mut [u8], offset: u64) -> io::Result<()> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::FileExt;
+        file.read_exact_at(buf, offset)
+    }
+
+    #[cfg(windows)]
+    {
+        use std::os::windows::fs::FileExt;
+
+        let (mut buf, mut offset) = (buf, offset);
+        while !buf.is_empty() {
+            match file.seek_read(buf, offset) {
+                Ok(0) => return Err(io::ErrorKind::UnexpectedEof.into()),
+                Ok(n) => {
+                    buf = &mut buf[n..];
+                    offset += n as u64;
+                }
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => (),
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Finds a file of a chunkstore with the given extension, trying each of the names it
+/// may have.
+fn find_chunkstore_file(
+    base_dirs: &[PathBuf],
+    depot: u32,
+    chunkstore_index: u32,
+    extension: &str,
+) -> anyhow::Result<PathBuf> {
+    let candidates = [
+        // The name used by Steam.
+        format!("{depot}_depotcache_{chunkstore_index}.{extension}"),
+        // The name used by some third-party backup tools.
+        format!("{depot}_{chunkstore_index}.{extension}"),
+    ];
+    let path = candidates
+        .iter()
+        .find_map(|name| {
+            base_dirs
+                .iter()
+                .find_map(|base_dir| find_ignoring_case(base_dir, name))
+        })
+        .ok_or_else(|| anyhow!("Cannot find {} (ignoring case)", candidates.join(" or ")))?;
+    if file_name(&path) != candidates[0] {
+        info!(
+            "Using {} for chunkstore {chunkstore_index} of depot {depot}",
+            path.display(),
+        );
+    }
+    Ok(path)
+}
+
+/// Finds the file named `name` in `dir`, ignoring ASCII case.
+///
+/// A file with exactly that name is preferred, followed by the first matching name in
+/// sorted order.
+fn find_ignoring_case(dir: &Path, name: &str) -> Option<PathBuf> {
+    let path = dir.join(name);
+    if path.exists() {
+        return Some(path);
+    }
+
+    fs::read_dir(dir)
+        .ok()?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.file_name())
+        .filter(|entry_name| {
+            entry_name
+                .to_str()
+                .is_some_and(|entry_name| entry_name.eq_ignore_ascii_case(name))
+        })
+        .min()
+        .map(|entry_name| dir.join(entry_name))
+}
+
+fn file_name(path: &Path) -> String {
+    path.file_name()
+        .expect("exists")
+        .to_str()
+        .expect("valid")
+        .into()
+}
+
+/// Compresses a chunk into the `PK` (zip) format used within `.csd` files.
+pub fn compress_chunk(data: &[u8]) -> anyhow::Result<Vec<u8>> {
+    let mut writer = ZipWriter::new(Cursor::new(vec![]));
+    writer.start_file(
+        "z",
+        SimpleFileOptions::default().compression_method(CompressionMethod::Deflated),
+    )?;
+    writer.write_all(data)?;
+    Ok(writer.finish()?.into_inner())
+}
+
 fn decompress_and_verify(
-    compressed: Vec<u8>,
+    body: &[u8],
     uncompressed_length: usize,
     sha: [u8; 20],
-) -> anyhow::Result<Checked> {
-    // Decompress the chunk.
-    let mut data = Vec::with_capacity(uncompressed_length);
-    let decompressed = match &compressed[..2] {
-        b"VZ" => Err(anyhow!("TODO: Implement LZMA decompression")),
-        b"PK" => Ok(ZipArchive::new(Cursor::new(&compressed))?
-            .by_index(0)?
-            .read_to_end(&mut data)?),
-        b"VS" => Ok(zstd::Decoder::new(&compressed[8..])?
-            .single_frame()
-            .read_to_end(&mut data)?),
-        x => Err(anyhow!(
-            "Unknown chunk compression type {}",
-            if let Ok(s) = std::str::from_utf8(x) {
-                s.into()
-            } else {
-                hex::encode(x)
-            }
-        )),
-    }?;
-    if decompressed != uncompressed_length {
+    buffer: Vec<u8>,
+) -> anyhow::Result<Checked<Vec<u8>>> {
+    let mut data = buffer;
+    data.clear();
+    data.reserve(uncompressed_length);
+    let compression = ChunkCompression::detect(body, uncompressed_length, sha)?;
+    let decompressed = decompress(body, compression, uncompressed_length, &mut data)?;
+    if decompressed != uncompressed_length as u64 {
         return Ok(Checked::WrongLength);
     }
 
     // Verify the chunk digest.
     let digest = Sha1::digest(&data);
     if digest == sha.into() {
-        Ok(Checked::Valid { compressed, data })
+        Ok(Checked::Valid { data })
     } else {
         Ok(Checked::WrongDigest)
     }
 }
 
-enum Checked {
-    Valid { compressed: Vec<u8>, data: Vec<u8> },
+/// Decompresses and verifies a chunk like [`decompress_and_verify`], but into `buf`,
+/// which must be exactly `uncompressed_length` bytes long.
+fn decompress_and_verify_into(
+    body: &[u8],
+    uncompressed_length: usize,
+    sha: [u8; 20],
+    buf: &mut [u8],
+) -> anyhow::Result<Checked<()>> {
+    let mut writer = SliceWriter { buf, written: 0 };
+    let compression = ChunkCompression::detect(body, uncompressed_length, sha)?;
+    let decompressed = decompress(body, compression, uncompressed_length, &mut writer)?;
+    if decompressed != uncompressed_length as u64 {
+        return Ok(Checked::WrongLength);
+    }
+
+    if Sha1::digest(&writer.buf[..]) == sha.into() {
+        Ok(Checked::Valid { data: () })
+    } else {
+        Ok(Checked::WrongDigest)
+    }
+}
+
+/// Checks a chunk like [`decompress_and_verify`], but without keeping the decompressed
+/// data in memory.
+fn decompress_and_hash(
+    body: &[u8],
+    uncompressed_length: usize,
+    sha: [u8; 20],
+) -> anyhow::Result<Checked<()>> {
+    let mut hasher = HashingWriter(Sha1::new());
+    let compression = ChunkCompression::detect(body, uncompressed_length, sha)?;
+    let decompressed = decompress(body, compression, uncompressed_length, &mut hasher)?;
+    if decompressed != uncompressed_length as u64 {
+        return Ok(Checked::WrongLength);
+    }
+
+    if hasher.0.finalize() == sha.into() {
+        Ok(Checked::Valid { data: () })
+    } else {
+        Ok(Checked::WrongDigest)
+    }
+}
+
+/// Decompresses a chunk compressed with `compression` (as found by
+/// [`ChunkCompression::detect`]) into `writer`, returning the number of bytes written.
+///
+/// At most one byte more than `uncompressed_length` is written, which is enough to
+/// tell that the chunk is the wrong length.
+fn decompress(
+    body: &[u8],
+    compression: ChunkCompression,
+    uncompressed_length: usize,
+    writer: &mut impl Write,
+) -> anyhow::Result<u64> {
+    let limit = uncompressed_length as u64 + 1;
+    let mut copy = |reader: &mut dyn Read| io::copy(&mut reader.take(limit), writer);
+    Ok(match compression {
+        ChunkCompression::Lzma => return Err(anyhow!("TODO: Implement LZMA decompression")),
+        ChunkCompression::Zip => {
+            // The file may be stored or deflated; the zip reader handles either.
+            let mut archive = ZipArchive::new(Cursor::new(body))?;
+            if archive.len() != 1 {
+                return Err(anyhow!(
+                    "Zip chunk contains {} files instead of one",
+                    archive.len(),
+                ));
+            }
+            let mut file = archive.by_index(0)?;
+            copy(&mut file)?
+        }
+        ChunkCompression::SteamZstd => {
+            let frame = body
+                .get(8..)
+                .ok_or_else(|| anyhow!("Truncated VSZa chunk"))?;
+            copy(&mut zstd::Decoder::new(frame)?.single_frame())?
+        }
+        ChunkCompression::Zstd => copy(&mut zstd::Decoder::new(body)?.single_frame())?,
+        ChunkCompression::Stored => copy(&mut &body[..])?,
+    })
+}
+
+/// A writer that hashes the data written to it, and then discards it.
+struct HashingWriter(Sha1);
+
+impl Write for HashingWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.update(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Writes into a slice, accepting (and discarding) any bytes beyond its end so that the
+/// total number written can be compared to the expected length.
+struct SliceWriter<'a> {
+    buf: &'a mut [u8],
+    written: usize,
+}
+
+impl Write for SliceWriter<'_> {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        let start = self.written.min(self.buf.len());
+        let n = data.len().min(self.buf.len() - start);
+        self.buf[start..start + n].copy_from_slice(&data[..n]);
+        self.written += data.len();
+        Ok(data.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// The magic bytes at the start of a zstd frame.
+pub const ZSTD_MAGIC: [u8; 4] = [0x28, 0xB5, 0x2F, 0xFD];
+
+/// The ways in which chunks within `.csd` files are compressed.
+#[derive(Clone, Copy)]
+enum ChunkCompression {
+    /// Valve's LZMA format, starting with `VZa`.
+    Lzma,
+    /// A zip archive containing a single file, either stored or deflated.
+    Zip,
+    /// Valve's zstd format, starting with `VSZa` and an 8-byte header.
+    SteamZstd,
+    /// A bare zstd frame.
+    Zstd,
+    /// Uncompressed data.
+    Stored,
+}
+
+impl ChunkCompression {
+    fn name(&self) -> &'static str {
+        match self {
+            ChunkCompression::Lzma => "VZ",
+            ChunkCompression::Zip => "PK",
+            ChunkCompression::SteamZstd => "VSZ",
+            ChunkCompression::Zstd => "zstd",
+            ChunkCompression::Stored => "stored",
+        }
+    }
+
+    /// Detects how a chunk is compressed from its magic bytes.
+    ///
+    /// A chunk without recognized magic bytes is treated as uncompressed if it is
+    /// exactly `uncompressed_length` bytes long (its digest is still checked).
+    /// Uncompressed data can also start with magic bytes (such as the first chunk of a
+    /// zip file), so a chunk of that length which matches its digest `sha` as it is is
+    /// treated as uncompressed too.
+    fn detect(body: &[u8], uncompressed_length: usize, sha: [u8; 20]) -> anyhow::Result<Self> {
+        let compression = if body.starts_with(b"VZa") {
+            Some(ChunkCompression::Lzma)
+        } else if body.starts_with(b"PK\x03\x04") {
+            Some(ChunkCompression::Zip)
+        } else if body.starts_with(b"VSZa") {
+            Some(ChunkCompression::SteamZstd)
+        } else if body.starts_with(&ZSTD_MAGIC) {
+            Some(ChunkCompression::Zstd)
+        } else {
+            None
+        };
+
+        match compression {
+            Some(_) if body.len() == uncompressed_length && Sha1::digest(body) == sha.into() => {
+                Ok(ChunkCompression::Stored)
+            }
+            Some(compression) => Ok(compression),
+            None if body.len() == uncompressed_length => Ok(ChunkCompression::Stored),
+            None => {
+                let magic = &body[..body.len().min(4)];
+                Err(anyhow!(
+                    "Unknown chunk compression type {}",
+                    if let Ok(s) = std::str::from_utf8(magic) {
+                        s.into()
+                    } else {
+                        hex::encode(magic)
+                    }
+                ))
+            }
+        }
+    }
+}
+
+enum Checked<T> {
+    Valid { data: T },
     WrongLength,
     WrongDigest,
 }
+
+#[cfg(test)]
+mod tests {
+    use sha1::{Digest, Sha1};
+
+    use super::{compress_chunk, decompress_and_verify, decompress_and_verify_into, Checked};
+
+    fn check(body: &[u8], data: &[u8]) -> Option<Vec<u8>> {
+        match decompress_and_verify(body, data.len(), Sha1::digest(data).into(), vec![]) {
+            Ok(Checked::Valid { data }) => Some(data),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn stored_chunk_with_magic_bytes() {
+        // The start of an uncompressed zip file.
+        let data = b"PK\x03\x04\x14\x00\x00\x00\x08\x00 not a single-file zip chunk";
+        assert_eq!(check(data, data).as_deref(), Some(&data[..]));
+
+        // A zstd frame header.
+        let data = b"\x28\xB5\x2F\xFDsome data";
+        assert_eq!(check(data, data).as_deref(), Some(&data[..]));
+    }
+
+    #[test]
+    fn truncated_steam_zstd_chunk() {
+        assert_eq!(check(b"VSZa\x00", b"some data"), None);
+    }
+
+    #[test]
+    fn decompress_into_slice() {
+        let data = b"PK\x03\x04".repeat(100);
+        let body = compress_chunk(&data).unwrap();
+        let sha = Sha1::digest(&data).into();
+
+        let mut buf = vec![0; data.len()];
+        assert!(matches!(
+            decompress_and_verify_into(&body, data.len(), sha, &mut buf),
+            Ok(Checked::Valid { .. }),
+        ));
+        assert_eq!(buf, data);
+
+        // A chunk that is longer than expected is detected without overflowing.
+        let mut buf = vec![0; data.len() - 1];
+        assert!(matches!(
+            decompress_and_verify_into(&body, data.len() - 1, sha, &mut buf),
+            Ok(Checked::WrongLength),
+        ));
+    }
+
+    #[test]
+    fn compressed_chunk() {
+        let data = b"PK\x03\x04".repeat(100);
+        let body = compress_chunk(&data).unwrap();
+        assert_eq!(check(&body, &data), Some(data));
+    }
+}
@@ -1,41 +1,119 @@
+use std::io::{self, Write};
 use std::path::Path;
 
 use anyhow::anyhow;
 use nom::Finish;
 
 #[derive(Debug)]
-pub(crate) struct ChunkStoreManifest {
-    pub(crate) is_encrypted: bool,
-    pub(crate) depot: u32,
-    pub(crate) chunks: Vec<([u8; 20], Chunk)>,
+pub struct ChunkStoreManifest {
+    pub is_encrypted: bool,
+    pub depot: u32,
+    pub chunks: Vec<([u8; 20], Chunk)>,
 }
 
 #[derive(Clone, Copy, Debug)]
-pub(crate) struct Chunk {
-    pub(crate) offset: u64,
-    pub(crate) uncompressed_length: u32,
-    pub(crate) compressed_length: u32,
+pub struct Chunk {
+    pub offset: u64,
+    pub uncompressed_length: u32,
+    pub compressed_length: u32,
 }
 
 impl ChunkStoreManifest {
-    pub(crate) fn read(path: &Path) -> anyhow::Result<Self> {
+    pub fn read(path: &Path) -> anyhow::Result<Self> {
         if !path
             .extension()
-            .map_or(false, |s| s.eq_ignore_ascii_case("csm"))
+            .is_some_and(|s| s.eq_ignore_ascii_case("csm"))
         {
             return Err(anyhow!(
                 "ChunkStoreManifest file does not have extension .csm"
             ));
         }
 
-        let data = std::fs::read(path)?;
+        Self::parse(&std::fs::read(path)?)
+    }
 
-        let (_, manifest) = read::manifest(&data)
+    /// Parses a chunkstore manifest from its contents.
+    pub fn parse(data: &[u8]) -> anyhow::Result<Self> {
+        let (_, manifest) = read::manifest(data)
             .finish()
             .map_err(|e| anyhow!("Failed to parse ChunkStoreManifest: {:?}", e))?;
 
         Ok(manifest)
     }
+
+    pub fn write<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        writer.write_all(b"SCFS\x14\x00\x00\x00")?;
+        writer.write_all(if self.is_encrypted {
+            b"\x03\x00\x00\x00"
+        } else {
+            b"\x02\x00\x00\x00"
+        })?;
+        writer.write_all(&self.depot.to_le_bytes())?;
+        writer.write_all(&(self.chunks.len() as u32).to_le_bytes())?;
+        for (sha, chunk) in &self.chunks {
+            writer.write_all(sha)?;
+            writer.write_all(&chunk.offset.to_le_bytes())?;
+            writer.write_all(&chunk.uncompressed_length.to_le_bytes())?;
+            writer.write_all(&chunk.compressed_length.to_le_bytes())?;
+        }
+        Ok(())
+    }
+
+    /// Checks how the chunks are laid out in a `.csd` file of `file_length` bytes.
+    ///
+    /// Returns the ranges of the file that are used by more than one chunk, and the
+    /// ranges that are not used by any chunk, in file order.
+    pub fn check_layout(&self, file_length: u64) -> Vec<LayoutProblem> {
+        let mut chunks = self.chunks.iter().collect::<Vec<_>>();
+        chunks.sort_by_key(|(_, chunk)| (chunk.offset, chunk.compressed_length));
+
+        let mut problems = vec![];
+        // The end of the furthest-reaching chunk so far, and its digest.
+        let mut covered = 0;
+        let mut last = None;
+        for (sha, chunk) in chunks {
+            let end = chunk.offset + u64::from(chunk.compressed_length);
+            if chunk.offset > covered {
+                problems.push(LayoutProblem::Gap {
+                    offset: covered,
+                    length: chunk.offset - covered,
+                });
+            } else if let Some(previous) = last.filter(|_| chunk.offset < covered) {
+                problems.push(LayoutProblem::Overlap {
+                    offset: chunk.offset,
+                    length: end.min(covered) - chunk.offset,
+                    chunks: [previous, *sha],
+                });
+            }
+            if end > covered || last.is_none() {
+                covered = end.max(covered);
+                last = Some(*sha);
+            }
+        }
+        if covered < file_length {
+            problems.push(LayoutProblem::Gap {
+                offset: covered,
+                length: file_length - covered,
+            });
+        }
+
+        problems
+    }
+}
+
+/// A problem with how chunks are laid out in a `.csd` file, as found by
+/// [`ChunkStoreManifest::check_layout`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum LayoutProblem {
+    /// A range of the file is used by two chunks.
+    Overlap {
+        offset: u64,
+        length: u64,
+        /// The SHA-1 digests of the chunks, in file order.
+        chunks: [[u8; 20]; 2],
+    },
+    /// A range of the file is not used by any chunk.
+    Gap { offset: u64, length: u64 },
 }
 
 mod read {
@@ -88,3 +166,72 @@ mod read {
         )(input)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{Chunk, ChunkStoreManifest, LayoutProblem};
+
+    fn csm(chunks: &[(u8, u64, u32)]) -> ChunkStoreManifest {
+        ChunkStoreManifest {
+            is_encrypted: false,
+            depot: 101,
+            chunks: chunks
+                .iter()
+                .map(|&(sha, offset, compressed_length)| {
+                    (
+                        [sha; 20],
+                        Chunk {
+                            offset,
+                            uncompressed_length: compressed_length,
+                            compressed_length,
+                        },
+                    )
+                })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn contiguous_layout() {
+        // Chunks are checked in file order, not manifest order.
+        let csm = csm(&[(2, 10, 5), (1, 0, 10), (3, 15, 5)]);
+        assert_eq!(csm.check_layout(20), vec![]);
+    }
+
+    #[test]
+    fn gap() {
+        let csm = csm(&[(1, 0, 10), (2, 12, 8)]);
+        assert_eq!(
+            csm.check_layout(20),
+            vec![LayoutProblem::Gap {
+                offset: 10,
+                length: 2
+            }],
+        );
+    }
+
+    #[test]
+    fn trailing_gap() {
+        let csm = csm(&[(1, 0, 10), (2, 10, 5)]);
+        assert_eq!(
+            csm.check_layout(20),
+            vec![LayoutProblem::Gap {
+                offset: 15,
+                length: 5
+            }],
+        );
+    }
+
+    #[test]
+    fn overlap() {
+        let csm = csm(&[(1, 0, 10), (2, 8, 12)]);
+        assert_eq!(
+            csm.check_layout(20),
+            vec![LayoutProblem::Overlap {
+                offset: 8,
+                length: 2,
+                chunks: [[1; 20], [2; 20]],
+            }],
+        );
+    }
+}
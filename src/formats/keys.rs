@@ -0,0 +1,39 @@
+use std::collections::HashMap;
+use std::path::Path;
+
+use anyhow::anyhow;
+
+/// Reads a file of depot keys, containing one `<DEPOT>:<HEX>` entry per line.
+///
+/// Blank lines, and lines starting with `#`, are ignored.
+pub fn read(path: &Path) -> anyhow::Result<HashMap<u32, [u8; 32]>> {
+    let data = std::fs::read_to_string(path)?;
+
+    data.lines()
+        .zip(1..)
+        .map(|(line, line_number)| (line.trim(), line_number))
+        .filter(|(line, _)| !(line.is_empty() || line.starts_with('#')))
+        .map(|(line, line_number)| {
+            parse_entry(line).map_err(|e| anyhow!("Invalid key on line {line_number}: {e}"))
+        })
+        .collect()
+}
+
+/// Parses a hex-encoded 32-byte depot key.
+pub fn parse_key(s: &str) -> Result<[u8; 32], String> {
+    let key = hex::decode(s).map_err(|e| format!("Invalid hex: {e}"))?;
+    key.try_into()
+        .map_err(|key: Vec<u8>| format!("Depot key must be 32 bytes, got {}", key.len()))
+}
+
+/// Parses a depot ID and its hex-encoded key, in the form `<DEPOT>:<HEX>`.
+pub fn parse_entry(s: &str) -> Result<(u32, [u8; 32]), String> {
+    let (depot, key) = s
+        .split_once(':')
+        .ok_or_else(|| "Expected <DEPOT>:<HEX>".to_string())?;
+    let depot = depot
+        .trim()
+        .parse()
+        .map_err(|e| format!("Invalid depot ID: {e}"))?;
+    Ok((depot, parse_key(key.trim())?))
+}
@@ -1,44 +1,92 @@
-use std::io::Write;
-use std::path::Path;
+use std::collections::HashSet;
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
 use std::{fs::File, io::Read};
 
-use anyhow::anyhow;
+use anyhow::{anyhow, Context};
 use base64::{engine::general_purpose::STANDARD, Engine};
+use flate2::read::GzDecoder;
+use rsa::{
+    pkcs1::DecodeRsaPublicKey,
+    pkcs1v15::{Signature, VerifyingKey},
+    pkcs8::DecodePublicKey,
+    signature::Verifier,
+    RsaPublicKey,
+};
+use serde_json::json;
+use sha1::Sha1;
 use steam_vent::proto::{
     content_manifest::{ContentManifestMetadata, ContentManifestPayload, ContentManifestSignature},
     protobuf::Message,
 };
+use zip::ZipArchive;
 
-const PROTOBUF_PAYLOAD_MAGIC: u32 = 0x71F617D0;
+/// The value at the start of every depot manifest file.
+pub const PROTOBUF_PAYLOAD_MAGIC: u32 = 0x71F617D0;
 const PROTOBUF_METADATA_MAGIC: u32 = 0x1F4812BE;
 const PROTOBUF_SIGNATURE_MAGIC: u32 = 0x1B81B817;
 const PROTOBUF_ENDOFMANIFEST_MAGIC: u32 = 0x32C415AB;
 
+/// The value at the start of every gzip stream.
+const GZIP_MAGIC: [u8; 2] = [0x1F, 0x8B];
+
 #[derive(Debug)]
-pub(crate) struct Manifest {
-    pub(crate) payload: ContentManifestPayload,
-    pub(crate) metadata: ContentManifestMetadata,
-    pub(crate) signature: ContentManifestSignature,
+pub struct Manifest {
+    pub payload: ContentManifestPayload,
+    pub metadata: ContentManifestMetadata,
+    pub signature: ContentManifestSignature,
+    /// The payload as it was read, or `None` if it has since been modified.
+    raw_payload: Option<Vec<u8>>,
 }
 
 impl Manifest {
-    pub(crate) fn open(path: &Path) -> anyhow::Result<Self> {
-        if !path
-            .extension()
-            .map_or(false, |s| s.eq_ignore_ascii_case("manifest"))
+    /// Creates a depot manifest from its parts.
+    pub fn new(
+        payload: ContentManifestPayload,
+        metadata: ContentManifestMetadata,
+        signature: ContentManifestSignature,
+    ) -> Self {
+        Manifest {
+            payload,
+            metadata,
+            signature,
+            raw_payload: None,
+        }
+    }
+
+    /// Opens a depot manifest file, which may be gzip-compressed.
+    pub fn open(path: &Path) -> anyhow::Result<Self> {
+        let extension = path.extension();
+        let has_extension = |ext: &str| extension.is_some_and(|s| s.eq_ignore_ascii_case(ext));
+        if !(has_extension("manifest")
+            || (has_extension("gz")
+                && Path::new(path.file_stem().expect("has extension"))
+                    .extension()
+                    .is_some_and(|s| s.eq_ignore_ascii_case("manifest"))))
         {
             return Err(anyhow!(
-                "Depot manifest file does not have extension .manifest"
+                "Depot manifest file does not have extension .manifest or .manifest.gz"
             ));
         }
 
-        let file = File::open(path)?;
+        Self::read_file(File::open(path)?)
+    }
 
-        Self::read(file)
+    /// Reads the contents of a depot manifest file, decompressing it if it is
+    /// gzip-compressed.
+    pub fn read_file<R: Read>(reader: R) -> anyhow::Result<Self> {
+        let mut reader = BufReader::new(reader);
+        if reader.fill_buf()?.starts_with(&GZIP_MAGIC) {
+            Self::read(GzDecoder::new(reader))
+        } else {
+            Self::read(reader)
+        }
     }
 
-    pub(crate) fn read<R: Read>(mut reader: R) -> anyhow::Result<Self> {
+    pub fn read<R: Read>(mut reader: R) -> anyhow::Result<Self> {
         let mut payload = None;
+        let mut raw_payload = None;
         let mut metadata = None;
         let mut signature = None;
 
@@ -60,6 +108,7 @@ impl Manifest {
                 PROTOBUF_PAYLOAD_MAGIC => {
                     let buf = read_vec(&mut reader)?;
                     payload = Some(ContentManifestPayload::parse_from_bytes(&buf)?);
+                    raw_payload = Some(buf);
                 }
                 PROTOBUF_METADATA_MAGIC => {
                     let buf = read_vec(&mut reader)?;
@@ -81,11 +130,12 @@ impl Manifest {
                 payload,
                 metadata,
                 signature,
+                raw_payload,
             })
             .ok_or(anyhow!("Missing manifest components"))
     }
 
-    pub(crate) fn write<W: Write>(&self, mut writer: W) -> anyhow::Result<()> {
+    pub fn write<W: Write>(&self, mut writer: W) -> anyhow::Result<()> {
         let write_vec = |writer: &mut W, v: Vec<u8>| {
             writer.write_all(&(v.len() as u32).to_le_bytes())?;
             writer.write_all(&v)
@@ -105,7 +155,117 @@ impl Manifest {
         Ok(())
     }
 
-    pub(crate) fn decrypt_filenames(&mut self, depot_key: &[u8; 32]) -> anyhow::Result<()> {
+    /// Checks the payload against the CRC stored in the metadata.
+    ///
+    /// The CRC is stored in `crc_encrypted` if the filenames are encrypted, and in
+    /// `crc_clear` otherwise.
+    ///
+    /// Returns `None` if the payload has been modified since it was read (for example,
+    /// by decrypting its filenames).
+    pub fn check_crc(&self) -> Option<bool> {
+        let expected = if self.metadata.filenames_encrypted() {
+            self.metadata.crc_encrypted()
+        } else {
+            self.metadata.crc_clear()
+        };
+        self.raw_payload
+            .as_deref()
+            .map(|payload| payload_crc_of(payload) == expected)
+    }
+
+    /// Checks the manifest's signature over its payload with Steam's content manifest
+    /// signing key.
+    ///
+    /// Returns `None` if the manifest is unsigned, or if the payload has been modified
+    /// since it was read.
+    pub fn check_signature(&self, signing_key: &RsaPublicKey) -> Option<bool> {
+        if !self.signature.has_signature() {
+            return None;
+        }
+        let payload = self.raw_payload.as_deref()?;
+
+        Some(
+            Signature::try_from(self.signature.signature()).is_ok_and(|signature| {
+                VerifyingKey::<Sha1>::new(signing_key.clone())
+                    .verify(payload, &signature)
+                    .is_ok()
+            }),
+        )
+    }
+
+    /// Returns a JSON representation of this manifest, including every file mapping and
+    /// its chunks.
+    ///
+    /// If the filenames are encrypted, the hex-encoded SHA-1 digest of each filename is
+    /// used in its place.
+    pub fn to_json(&self) -> serde_json::Value {
+        let metadata = &self.metadata;
+        json!({
+            // Manifest IDs don't fit in a JavaScript number.
+            "manifest": metadata.gid_manifest().to_string(),
+            "depot": metadata.depot_id(),
+            "creation_time": metadata.creation_time(),
+            "filenames_encrypted": metadata.filenames_encrypted(),
+            "original_size": metadata.cb_disk_original(),
+            "compressed_size": metadata.cb_disk_compressed(),
+            "unique_chunks": metadata.unique_chunks(),
+            "crc_encrypted": metadata.crc_encrypted(),
+            "crc_clear": metadata.crc_clear(),
+            "signature": self
+                .signature
+                .has_signature()
+                .then(|| hex::encode(self.signature.signature())),
+            "files": self.payload.mappings.iter().map(|file_mapping| json!({
+                "filename": if metadata.filenames_encrypted() {
+                    hex::encode(file_mapping.sha_filename())
+                } else {
+                    file_mapping.filename().into()
+                },
+                "sha_filename": hex::encode(file_mapping.sha_filename()),
+                "sha_content": hex::encode(file_mapping.sha_content()),
+                "size": file_mapping.size(),
+                "flags": file_mapping.flags(),
+                "directory": file_mapping.flags() & 0b0100_0000 != 0,
+                "executable": file_mapping.flags() & 0b1_0000_0000 != 0,
+                "linktarget": (!file_mapping.linktarget().is_empty())
+                    .then(|| file_mapping.linktarget()),
+                "chunks": file_mapping.chunks.iter().map(|chunk| json!({
+                    "sha": hex::encode(chunk.sha()),
+                    "crc": chunk.crc(),
+                    "offset": chunk.offset(),
+                    "original_length": chunk.cb_original(),
+                    "compressed_length": chunk.cb_compressed(),
+                })).collect::<Vec<_>>(),
+            })).collect::<Vec<_>>(),
+        })
+    }
+
+    /// Checks that every chunk referenced by this manifest is in `available`.
+    ///
+    /// Returns the chunks that are missing, and the files that could not be fully
+    /// reconstructed without them.
+    pub fn verify_chunks_present(&self, available: &HashSet<[u8; 20]>) -> MissingChunks {
+        let mut missing = MissingChunks::default();
+        for file_mapping in &self.payload.mappings {
+            let mut complete = true;
+            for chunk in &file_mapping.chunks {
+                if let Ok(sha) = <[u8; 20]>::try_from(chunk.sha()) {
+                    if !available.contains(&sha) {
+                        missing.chunks.push(sha);
+                        complete = false;
+                    }
+                }
+            }
+            if !complete {
+                missing.files.push(file_mapping.filename().into());
+            }
+        }
+        missing.chunks.sort();
+        missing.chunks.dedup();
+        missing
+    }
+
+    pub fn decrypt_filenames(&mut self, depot_key: &[u8; 32]) -> anyhow::Result<()> {
         if self.metadata.filenames_encrypted() {
             for mapping in &mut self.payload.mappings {
                 mapping.set_filename(decrypt_string(mapping.filename(), depot_key)?);
@@ -115,12 +275,159 @@ impl Manifest {
             }
 
             self.metadata.set_filenames_encrypted(false);
+            self.raw_payload = None;
         }
 
         Ok(())
     }
 }
 
+/// The chunks referenced by a [`Manifest`] that are not available, as found by
+/// [`Manifest::verify_chunks_present`].
+#[derive(Debug, Default)]
+pub struct MissingChunks {
+    /// The SHA-1 digests of the missing chunks, in sorted order.
+    pub chunks: Vec<[u8; 20]>,
+    /// The names of the files that use any of the missing chunks, in manifest order.
+    pub files: Vec<String>,
+}
+
+impl MissingChunks {
+    /// Returns `true` if no chunks are missing.
+    pub fn is_empty(&self) -> bool {
+        self.chunks.is_empty()
+    }
+}
+
+/// The user's cached depot manifests: either a folder of manifest files, or a zip
+/// archive of them.
+///
+/// Each manifest is stored as `<depot>_<manifest>.manifest`, or gzip-compressed as
+/// `<depot>_<manifest>.manifest.gz`. Within a zip archive, manifests may be in any
+/// folder.
+pub enum ManifestSource {
+    Dir(PathBuf),
+    Zip {
+        path: PathBuf,
+        archive: Mutex<ZipArchive<BufReader<File>>>,
+    },
+}
+
+impl ManifestSource {
+    /// Opens the folder or zip archive of manifests at `path`.
+    pub fn open(path: &Path) -> anyhow::Result<Self> {
+        if path.is_file() {
+            let archive = ZipArchive::new(BufReader::new(File::open(path)?))
+                .with_context(|| format!("{} is not a folder or zip archive", path.display()))?;
+            Ok(ManifestSource::Zip {
+                path: path.to_owned(),
+                archive: Mutex::new(archive),
+            })
+        } else {
+            Ok(ManifestSource::Dir(path.to_owned()))
+        }
+    }
+
+    /// Returns the path of the folder or zip archive.
+    pub fn path(&self) -> &Path {
+        match self {
+            ManifestSource::Dir(path) | ManifestSource::Zip { path, .. } => path,
+        }
+    }
+
+    /// Returns the folder of manifests, or `None` if this is a zip archive.
+    pub fn dir(&self) -> Option<&Path> {
+        match self {
+            ManifestSource::Dir(path) => Some(path),
+            ManifestSource::Zip { .. } => None,
+        }
+    }
+
+    /// Returns `true` if the manifest with the given ID is present.
+    pub fn contains(&self, depot: u32, manifest_id: u64) -> bool {
+        match self {
+            ManifestSource::Dir(dir) => Self::dir_path(dir, depot, manifest_id).exists(),
+            ManifestSource::Zip { archive, .. } => {
+                Self::zip_entry(&archive.lock().unwrap(), depot, manifest_id).is_some()
+            }
+        }
+    }
+
+    /// Reads the manifest with the given ID.
+    pub fn read(&self, depot: u32, manifest_id: u64) -> anyhow::Result<Manifest> {
+        match self {
+            ManifestSource::Dir(dir) => Manifest::open(&Self::dir_path(dir, depot, manifest_id)),
+            ManifestSource::Zip { archive, .. } => {
+                let mut archive = archive.lock().unwrap();
+                let name = Self::zip_entry(&archive, depot, manifest_id)
+                    .ok_or_else(|| anyhow!("{depot}_{manifest_id}.manifest not found"))?;
+                let manifest = Manifest::read_file(archive.by_name(&name)?);
+                manifest
+            }
+        }
+        .with_context(|| {
+            format!(
+                "Cannot find manifest {manifest_id} for depot {depot} in {}",
+                self.path().display()
+            )
+        })
+    }
+
+    /// Returns the path of a manifest in a folder, preferring an uncompressed manifest
+    /// if both are present.
+    fn dir_path(dir: &Path, depot: u32, manifest_id: u64) -> PathBuf {
+        let path = dir.join(format!("{depot}_{manifest_id}.manifest"));
+        let gz_path = dir.join(format!("{depot}_{manifest_id}.manifest.gz"));
+        if !path.exists() && gz_path.exists() {
+            gz_path
+        } else {
+            path
+        }
+    }
+
+    /// Returns the name of a manifest's entry in a zip archive, preferring an
+    /// uncompressed manifest if both are present.
+    fn zip_entry(
+        archive: &ZipArchive<BufReader<File>>,
+        depot: u32,
+        manifest_id: u64,
+    ) -> Option<String> {
+        let filename = format!("{depot}_{manifest_id}.manifest");
+        let gz_filename = format!("{filename}.gz");
+        let find = |filename: &str| {
+            archive
+                .file_names()
+                .find(|name| name.rsplit('/').next() == Some(filename))
+                .map(String::from)
+        };
+        find(&filename).or_else(|| find(&gz_filename))
+    }
+}
+
+/// Reads an RSA public key for verifying manifest signatures.
+///
+/// The key may be PEM- or DER-encoded, in either SubjectPublicKeyInfo or PKCS #1 form.
+pub fn read_signing_key(path: &Path) -> anyhow::Result<RsaPublicKey> {
+    let data = std::fs::read(path)?;
+    match std::str::from_utf8(&data) {
+        Ok(pem) if pem.contains("-----BEGIN") => RsaPublicKey::from_public_key_pem(pem)
+            .or_else(|_| RsaPublicKey::from_pkcs1_pem(pem))
+            .map_err(|e| anyhow!("Invalid PEM public key: {e}")),
+        _ => RsaPublicKey::from_public_key_der(&data)
+            .or_else(|_| RsaPublicKey::from_pkcs1_der(&data))
+            .map_err(|e| anyhow!("Invalid DER public key: {e}")),
+    }
+}
+
+/// Computes the CRC of a serialized payload the same way Steam does: a CRC-32 over the
+/// payload's length (as a little-endian `u32`) followed by the payload itself.
+fn payload_crc_of(payload: &[u8]) -> u32 {
+    let mut hasher = crc32fast::Hasher::new();
+    hasher.update(&(payload.len() as u32).to_le_bytes());
+    hasher.update(payload);
+    hasher.finalize()
+}
+
 fn decrypt_string(s: &str, depot_key: &[u8; 32]) -> anyhow::Result<String> {
     let encoded = s.lines().fold(String::new(), |acc, line| acc + line);
     let ciphertext = STANDARD.decode(&encoded)?;
@@ -1,187 +1,215 @@
 use std::collections::BTreeMap;
+use std::io::{self, Write};
 use std::path::Path;
 
 use anyhow::anyhow;
-use nom::Finish;
+
+use super::vdf;
 
 #[derive(Debug)]
-pub(crate) struct StockKeepingUnit {
-    pub(crate) name: String,
-    pub(crate) disks: u32,
-    pub(crate) disk: u32,
-    pub(crate) backup: u32,
-    pub(crate) contenttype: u32,
-    pub(crate) apps: Vec<u32>,
-    pub(crate) depots: Vec<u32>,
-    pub(crate) manifests: BTreeMap<u32, u64>,
-    pub(crate) chunkstores: BTreeMap<u32, BTreeMap<u32, i32>>,
+pub struct StockKeepingUnit {
+    pub name: String,
+    pub disks: u32,
+    pub disk: u32,
+    pub backup: u32,
+    pub contenttype: u32,
+    pub apps: Vec<u32>,
+    pub depots: Vec<u32>,
+    pub manifests: BTreeMap<u32, u64>,
+    pub chunkstores: BTreeMap<u32, BTreeMap<u32, i32>>,
 }
 
 impl StockKeepingUnit {
-    pub(crate) fn read(path: &Path) -> anyhow::Result<Self> {
+    pub fn read(path: &Path) -> anyhow::Result<Self> {
         if !path
             .extension()
-            .map_or(false, |s| s.eq_ignore_ascii_case("sis"))
+            .is_some_and(|s| s.eq_ignore_ascii_case("sis"))
         {
             return Err(anyhow!("SKU file does not have extension .sis"));
         }
 
-        let data = std::fs::read_to_string(path)?;
+        Self::parse(&std::fs::read_to_string(path)?)
+    }
+
+    /// Parses a SKU from its contents.
+    pub fn parse(data: &str) -> anyhow::Result<Self> {
+        let root = vdf::parse(data).map_err(|e| anyhow!("Failed to parse SKU: {:?}", e))?;
+        let sku = root
+            .get("SKU")
+            .ok_or_else(|| anyhow!("Failed to parse SKU: missing \"SKU\" section"))?;
 
-        let (_, sku) = read::sku(&data)
-            .finish()
-            .map_err(|e| anyhow!("Failed to parse SKU: {:?}", e))?;
+        read::sku(sku)
+    }
+
+    /// Writes this SKU in the format of a `sku.sis` file.
+    ///
+    /// Writing a SKU read from a file that Steam created reproduces that file, except
+    /// that lines always end with `\n`.
+    pub fn write<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        writeln!(writer, "\"SKU\"")?;
+        writeln!(writer, "{{")?;
+        writeln!(writer, "\t\"name\"\t\t\"{}\"", vdf::escape(&self.name))?;
+        writeln!(writer, "\t\"disks\"\t\t\"{}\"", self.disks)?;
+        writeln!(writer, "\t\"disk\"\t\t\"{}\"", self.disk)?;
+        writeln!(writer, "\t\"backup\"\t\t\"{}\"", self.backup)?;
+        writeln!(writer, "\t\"contenttype\"\t\t\"{}\"", self.contenttype)?;
+        write_dict(&mut writer, "apps", self.apps.iter().enumerate())?;
+        write_dict(&mut writer, "depots", self.depots.iter().enumerate())?;
+        write_dict(&mut writer, "manifests", self.manifests.iter())?;
+        writeln!(writer, "\t\"chunkstores\"")?;
+        writeln!(writer, "\t{{")?;
+        for (depot, chunkstores) in &self.chunkstores {
+            writeln!(writer, "\t\t\"{depot}\"")?;
+            writeln!(writer, "\t\t{{")?;
+            for (index, length) in chunkstores {
+                writeln!(writer, "\t\t\t\"{index}\"\t\t\"{length}\"")?;
+            }
+            writeln!(writer, "\t\t}}")?;
+        }
+        writeln!(writer, "\t}}")?;
+        writeln!(writer, "}}")?;
+        Ok(())
+    }
+}
 
-        Ok(sku)
+/// Writes a top-level dictionary entry where the value is a dictionary of strings.
+fn write_dict<W: Write, K: std::fmt::Display, V: std::fmt::Display>(
+    writer: &mut W,
+    key: &str,
+    entries: impl Iterator<Item = (K, V)>,
+) -> io::Result<()> {
+    writeln!(writer, "\t\"{key}\"")?;
+    writeln!(writer, "\t{{")?;
+    for (k, v) in entries {
+        writeln!(writer, "\t\t\"{k}\"\t\t\"{v}\"")?;
     }
+    writeln!(writer, "\t}}")
 }
 
 mod read {
-    use std::{collections::BTreeMap, str::FromStr};
+    use std::{collections::BTreeMap, fmt, str::FromStr};
 
-    use nom::{
-        bytes::complete::{is_not, tag, tag_no_case},
-        character::complete::{newline, space1, tab},
-        combinator::{map, map_opt, map_res, rest},
-        multi::many_till,
-        sequence::{delimited, pair, preceded, tuple},
-        IResult, Parser,
-    };
+    use anyhow::anyhow;
 
     use super::StockKeepingUnit;
-
-    pub(super) fn sku(input: &str) -> IResult<&str, StockKeepingUnit> {
-        preceded(
-            tuple((tag_no_case("\"SKU\""), newline, tag("{"), newline)),
-            map(
-                tuple((
-                    str_field("name"),
-                    str_field("disks"),
-                    str_field("disk"),
-                    str_field("backup"),
-                    str_field("contenttype"),
-                    vec_field("apps"),
-                    vec_field("depots"),
-                    dict_field("manifests", dict_string(parsed_str)),
-                    dict_field(
-                        "chunkstores",
-                        dict_nested(parsed_str, dict_string(parsed_str)),
-                    ),
-                )),
-                |(name, disks, disk, backup, contenttype, apps, depots, manifests, chunkstores)| {
-                    StockKeepingUnit {
-                        name,
-                        disks,
-                        disk,
-                        backup,
-                        contenttype,
-                        apps,
-                        depots,
-                        manifests,
-                        chunkstores,
-                    }
-                },
-            ),
-        )(input)
+    use crate::formats::vdf::Value;
+
+    /// Reads the fields of a SKU, which may appear in any order. Unknown fields are
+    /// skipped.
+    pub(super) fn sku(sku: &Value) -> anyhow::Result<StockKeepingUnit> {
+        Ok(StockKeepingUnit {
+            name: string(field(sku, "name")?, "name")?.into(),
+            disks: parsed_field(sku, "disks")?,
+            disk: parsed_field(sku, "disk")?,
+            backup: parsed_field(sku, "backup")?,
+            contenttype: parsed_field(sku, "contenttype")?,
+            apps: list(sku, "apps")?,
+            depots: list(sku, "depots")?,
+            manifests: map(field(sku, "manifests")?, "manifests", |value| {
+                parsed(string(value, "manifests")?, "manifests")
+            })?,
+            chunkstores: map(field(sku, "chunkstores")?, "chunkstores", |value| {
+                map(value, "chunkstores", |value| {
+                    parsed(string(value, "chunkstores")?, "chunkstores")
+                })
+            })?,
+        })
     }
 
-    fn str_field<'a, V: FromStr>(
-        key: &'static str,
-    ) -> impl Parser<&'a str, V, nom::error::Error<&'a str>> {
-        map(dict_string(tag_no_case(key)), |(_, v)| v)
+    fn field<'a>(sku: &'a Value, key: &str) -> anyhow::Result<&'a Value> {
+        sku.get(key)
+            .ok_or_else(|| anyhow!("SKU is missing \"{key}\""))
     }
 
-    fn vec_field<'a, V: FromStr>(
-        key: &'static str,
-    ) -> impl Parser<&'a str, Vec<V>, nom::error::Error<&'a str>> {
-        map(dict_vec(tag_no_case(key)), |(_, v)| v)
-    }
-
-    fn dict_field<'a, DK: Ord, DV, F>(
-        key: &'static str,
-        entry: F,
-    ) -> impl Parser<&'a str, BTreeMap<DK, DV>, nom::error::Error<&'a str>>
+    fn parsed_field<T: FromStr>(sku: &Value, key: &str) -> anyhow::Result<T>
     where
-        F: Parser<&'a str, (DK, DV), nom::error::Error<&'a str>>,
+        T::Err: fmt::Display,
     {
-        map(dict_nested(tag_no_case(key), entry), |(_, v)| v)
+        parsed(string(field(sku, key)?, key)?, key)
     }
 
-    /// A dictionary entry where the value is a quoted string.
-    fn dict_string<'a, K, V: FromStr, F>(
-        key: F,
-    ) -> impl Parser<&'a str, (K, V), nom::error::Error<&'a str>>
+    /// A dictionary of quoted strings, keyed by consecutive indices from zero.
+    fn list<T: FromStr>(sku: &Value, key: &str) -> anyhow::Result<Vec<T>>
     where
-        F: Parser<&'a str, K, nom::error::Error<&'a str>>,
+        T::Err: fmt::Display,
     {
-        map_res(
-            dict_entry(key, preceded(pair(tab, tab), quoted_str)),
-            |(k, v)| v.parse().map(|v| (k, v)),
-        )
+        dict(field(sku, key)?, key)?
+            .iter()
+            .enumerate()
+            .map(|(expected_i, (i, value))| {
+                if i.parse() == Ok(expected_i) {
+                    parsed(string(value, key)?, key)
+                } else {
+                    Err(anyhow!("\"{key}\" in SKU has unexpected index \"{i}\""))
+                }
+            })
+            .collect()
     }
 
-    /// A dictionary entry where the value is a list of quoted strings.
-    fn dict_vec<'a, K, V: FromStr, F>(
-        key: F,
-    ) -> impl Parser<&'a str, (K, Vec<V>), nom::error::Error<&'a str>>
+    /// A dictionary with parsed keys.
+    fn map<K: FromStr + Ord, V>(
+        value: &Value,
+        key: &str,
+        parse_value: impl Fn(&Value) -> anyhow::Result<V>,
+    ) -> anyhow::Result<BTreeMap<K, V>>
     where
-        F: Parser<&'a str, K, nom::error::Error<&'a str>>,
+        K::Err: fmt::Display,
     {
-        map_opt(
-            dict_entry(
-                key,
-                preceded(
-                    tuple((newline, space1, tag("{"), newline)),
-                    many_till(dict_string(parsed_str::<usize>), pair(space1, tag("}"))),
-                ),
-            ),
-            |(k, (v, _))| {
-                v.into_iter()
-                    .enumerate()
-                    .map(|(expected_i, (i, v))| (i == expected_i).then(|| v))
-                    .collect::<Option<_>>()
-                    .map(|v| (k, v))
-            },
-        )
+        dict(value, key)?
+            .iter()
+            .map(|(k, v)| Ok((parsed(k, key)?, parse_value(v)?)))
+            .collect()
     }
 
-    /// A dictionary entry where the value is itself a dictionary.
-    fn dict_nested<'a, K, DK: Ord, DV, F, G>(
-        key: F,
-        entry: G,
-    ) -> impl Parser<&'a str, (K, BTreeMap<DK, DV>), nom::error::Error<&'a str>>
-    where
-        F: Parser<&'a str, K, nom::error::Error<&'a str>>,
-        G: Parser<&'a str, (DK, DV), nom::error::Error<&'a str>>,
-    {
-        map(
-            dict_entry(
-                key,
-                preceded(
-                    tuple((newline, space1, tag("{"), newline)),
-                    many_till(entry, pair(space1, tag("}"))),
-                ),
-            ),
-            |(k, (v, _))| (k, v.into_iter().collect()),
-        )
+    fn dict<'a>(value: &'a Value, key: &str) -> anyhow::Result<&'a [(String, Value)]> {
+        match value {
+            Value::Dict(entries) => Ok(entries),
+            Value::String(_) => Err(anyhow!("\"{key}\" in SKU should be a dictionary")),
+        }
+    }
+
+    fn string<'a>(value: &'a Value, key: &str) -> anyhow::Result<&'a str> {
+        match value {
+            Value::String(s) => Ok(s),
+            Value::Dict(_) => Err(anyhow!("\"{key}\" in SKU should be a string")),
+        }
     }
 
-    fn dict_entry<'a, K, V, F, G>(
-        key: F,
-        value: G,
-    ) -> impl Parser<&'a str, (K, V), nom::error::Error<&'a str>>
+    fn parsed<T: FromStr>(s: &str, key: &str) -> anyhow::Result<T>
     where
-        F: Parser<&'a str, K, nom::error::Error<&'a str>>,
-        G: Parser<&'a str, V, nom::error::Error<&'a str>>,
+        T::Err: fmt::Display,
     {
-        delimited(space1, pair(quoted_str.and_then(key), value), newline)
+        s.parse()
+            .map_err(|e| anyhow!("Invalid value \"{s}\" for \"{key}\" in SKU: {e}"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::StockKeepingUnit;
+
+    const SKU: &str = include_str!("../../tests/fixtures/sku.sis");
+    const SKU_CRLF: &str = include_str!("../../tests/fixtures/sku_crlf.sis");
+
+    fn write(sku: &StockKeepingUnit) -> String {
+        let mut written = vec![];
+        sku.write(&mut written).unwrap();
+        String::from_utf8(written).unwrap()
     }
 
-    fn quoted_str(input: &str) -> IResult<&str, &str> {
-        delimited(tag("\""), is_not("\""), tag("\""))(input)
+    #[test]
+    fn round_trip() {
+        let sku = StockKeepingUnit::parse(SKU).unwrap();
+        assert_eq!(sku.name, "Half-Life 2");
+        assert_eq!(sku.depots, [221, 222]);
+        assert_eq!(sku.chunkstores[&222][&1], -1);
+        assert_eq!(write(&sku), SKU);
     }
 
-    fn parsed_str<T: FromStr>(input: &str) -> IResult<&str, T> {
-        map_res(rest, |s: &str| s.parse())(input)
+    #[test]
+    fn round_trip_crlf() {
+        // Lines are always written with `\n` endings.
+        let sku = StockKeepingUnit::parse(SKU_CRLF).unwrap();
+        assert_eq!(write(&sku), SKU);
     }
 }
@@ -0,0 +1,159 @@
+//! Valve's KeyValues text format, used by `config.vdf`, `sku.sis`, and
+//! `appmanifest_*.acf`.
+
+use nom::Finish;
+
+/// A value in a Valve KeyValues document.
+#[derive(Debug, PartialEq)]
+pub enum Value {
+    String(String),
+    Dict(Vec<(String, Value)>),
+}
+
+impl Value {
+    /// Looks up a key in a dictionary, ignoring case as the Steam client does.
+    pub fn get(&self, key: &str) -> Option<&Value> {
+        match self {
+            Value::String(_) => None,
+            Value::Dict(entries) => entries
+                .iter()
+                .find(|(k, _)| k.eq_ignore_ascii_case(key))
+                .map(|(_, v)| v),
+        }
+    }
+}
+
+/// Parses a document, returning its top-level entries as a dictionary.
+pub fn parse(input: &str) -> Result<Value, nom::error::Error<&str>> {
+    read::document(input).finish().map(|(_, value)| value)
+}
+
+/// Escapes a string for writing between quotes, such that [`parse`] reads it back
+/// unchanged.
+pub fn escape(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            '"' => escaped.push_str("\\\""),
+            '\n' => escaped.push_str("\\n"),
+            '\t' => escaped.push_str("\\t"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+mod read {
+    use nom::{
+        branch::alt,
+        bytes::complete::{escaped_transform, is_not, tag},
+        character::complete::{char, multispace1},
+        combinator::{eof, map, opt, value},
+        multi::{many0, many_till},
+        sequence::{delimited, pair, preceded, terminated},
+        IResult,
+    };
+
+    use super::Value;
+
+    pub(super) fn document(input: &str) -> IResult<&str, Value> {
+        map(many_till(entry, preceded(ignored, eof)), |(entries, _)| {
+            Value::Dict(entries)
+        })(input)
+    }
+
+    fn entry(input: &str) -> IResult<&str, (String, Value)> {
+        pair(
+            preceded(ignored, quoted_str),
+            preceded(
+                ignored,
+                alt((
+                    map(quoted_str, Value::String),
+                    map(
+                        delimited(char('{'), many0(entry), preceded(ignored, char('}'))),
+                        Value::Dict,
+                    ),
+                )),
+            ),
+        )(input)
+    }
+
+    /// Whitespace and `//` comments.
+    fn ignored(input: &str) -> IResult<&str, ()> {
+        value(
+            (),
+            many0(alt((
+                multispace1,
+                // A comment on the last line need not end with a newline.
+                terminated(
+                    preceded(tag("//"), map(opt(is_not("\n")), Option::unwrap_or_default)),
+                    alt((tag("\n"), eof)),
+                ),
+            ))),
+        )(input)
+    }
+
+    fn quoted_str(input: &str) -> IResult<&str, String> {
+        map(
+            delimited(
+                char('"'),
+                opt(escaped_transform(
+                    is_not("\\\""),
+                    '\\',
+                    alt((
+                        value("\\", char('\\')),
+                        value("\"", char('"')),
+                        value("\n", char('n')),
+                        value("\t", char('t')),
+                    )),
+                )),
+                char('"'),
+            ),
+            Option::unwrap_or_default,
+        )(input)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse, Value};
+
+    const SKU: &str = include_str!("../../tests/fixtures/sku.sis");
+    const SKU_CRLF: &str = include_str!("../../tests/fixtures/sku_crlf.sis");
+
+    fn string(value: &Value) -> &str {
+        match value {
+            Value::String(s) => s,
+            Value::Dict(_) => panic!("not a string"),
+        }
+    }
+
+    #[test]
+    fn crlf_line_endings() {
+        assert!(SKU_CRLF.contains("\r\n"));
+        let root = parse(SKU_CRLF).unwrap();
+        assert_eq!(root, parse(SKU).unwrap());
+
+        let sku = root.get("SKU").unwrap();
+        assert_eq!(string(sku.get("name").unwrap()), "Half-Life 2");
+        let chunkstores = sku.get("chunkstores").unwrap().get("222").unwrap();
+        assert_eq!(string(chunkstores.get("1").unwrap()), "-1");
+    }
+
+    #[test]
+    fn comments_and_escapes() {
+        let root = parse("// comment\r\n\"a\" \"x\\\"y\\\\z\" // trailing\r\n\"B\" { \"c\" \"\" }")
+            .unwrap();
+        assert_eq!(string(root.get("a").unwrap()), "x\"y\\z");
+        assert_eq!(string(root.get("b").unwrap().get("C").unwrap()), "");
+    }
+
+    #[test]
+    fn comment_at_end_of_input() {
+        let root = parse("\"a\" \"1\"\n// no trailing newline").unwrap();
+        assert_eq!(string(root.get("a").unwrap()), "1");
+        let root = parse("\"a\" { \"b\" \"2\" } //").unwrap();
+        assert_eq!(string(root.get("a").unwrap().get("b").unwrap()), "2");
+    }
+}
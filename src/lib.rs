@@ -0,0 +1,13 @@
+//! Parsers for the files that make up Steam game backups.
+//!
+//! A backup folder contains a SKU ([`StockKeepingUnit`]) describing the game's depots,
+//! and for each depot one or more chunkstores. Each chunkstore is a `.csm` file
+//! ([`ChunkStoreManifest`]) indexing the chunks stored in a `.csd` data file, which can
+//! be read together via [`ChunkStore`]. The files that the chunks make up are described
+//! by the depot's [`Manifest`], which Steam caches separately from the backup.
+
+pub mod formats;
+
+pub use formats::{
+    csd::ChunkStore, csm::ChunkStoreManifest, manifest::Manifest, sis::StockKeepingUnit,
+};
@@ -1,21 +1,61 @@
+use std::io::{self, IsTerminal};
+use std::num::NonZeroUsize;
+
 use clap::Parser;
-use tokio::runtime::Builder;
+use tev::formats;
+use tokio::runtime::{Builder, Runtime};
 
 mod cli;
 mod commands;
-mod formats;
 
 fn main() -> anyhow::Result<()> {
     let opts = cli::Options::parse();
 
+    // Log messages go to stderr, so they don't mix with the results on stdout.
+    tracing_subscriber::fmt()
+        .with_max_level(opts.max_log_level())
+        .with_writer(io::stderr)
+        .with_ansi(io::stderr().is_terminal())
+        .init();
+
+    let threads = opts.threads;
     match opts.command {
         cli::Command::Inspect(command) => command.run(),
         cli::Command::Backup(cli::Backup::Verify(command)) => {
-            let runtime = Builder::new_multi_thread()
-                .thread_name("tev-worker")
-                .build()?;
-            runtime.block_on(command.run())
+            verify_runtime(threads)?.block_on(command.run())
+        }
+        cli::Command::Backup(cli::Backup::Repair(command)) => {
+            verify_runtime(threads)?.block_on(command.run())
+        }
+        cli::Command::Backup(cli::Backup::Bench(command)) => {
+            verify_runtime(threads)?.block_on(command.run())
         }
-        cli::Command::Backup(cli::Backup::Mount(command)) => command.run(),
+        cli::Command::Backup(cli::Backup::Mount(command)) => command.run(threads),
+        cli::Command::Backup(cli::Backup::Cat(command)) => command.run(),
+        cli::Command::Backup(cli::Backup::Ls(command)) => command.run(),
+        cli::Command::Backup(cli::Backup::Find(command)) => command.run(),
+        cli::Command::Backup(cli::Backup::Sha1sums(command)) => command.run(),
+        cli::Command::Backup(cli::Backup::Compare(command)) => command.run(),
+        cli::Command::Backup(cli::Backup::Stats(command)) => command.run(),
+        cli::Command::Backup(cli::Backup::CheckSku(command)) => command.run(),
+        cli::Command::Backup(cli::Backup::Create(command)) => command.run(),
+        cli::Command::Backup(cli::Backup::DecryptFilenames(command)) => command.run(),
+        cli::Command::Backup(cli::Backup::Extract(command)) => command.run(),
+        cli::Command::Backup(cli::Backup::Diff(command)) => command.run(),
+        cli::Command::Manifest(cli::Manifest::Convert(command)) => command.run(),
+        cli::Command::Completion(command) => command.run(),
+    }
+}
+
+/// Builds the runtime on which backups are verified.
+fn verify_runtime(threads: Option<NonZeroUsize>) -> std::io::Result<Runtime> {
+    let mut builder = Builder::new_multi_thread();
+    builder.thread_name("tev-worker").enable_all();
+    if let Some(threads) = threads {
+        // Chunks are read and decompressed on the blocking thread pool.
+        builder
+            .worker_threads(threads.get())
+            .max_blocking_threads(threads.get());
     }
+    builder.build()
 }